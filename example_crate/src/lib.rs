@@ -1,4 +1,6 @@
 use byond_fn::byond_fn;
+use byond_fn::handle::Handle;
+use byond_fn::str_ffi::Rest;
 
 #[byond_fn]
 pub fn add(left: usize, right: usize) -> usize {
@@ -9,3 +11,41 @@ pub fn add(left: usize, right: usize) -> usize {
 pub fn add_optional(left: usize, right: Option<usize>) -> usize {
     left + right.unwrap_or(0)
 }
+
+#[byond_fn]
+pub fn sum(values: Rest<usize>) -> usize {
+    values.iter().sum()
+}
+
+/// The build this library came from, set with `EXAMPLE_CRATE_BUILD` when it's compiled, which
+/// tells builds apart after a reload
+#[byond_fn]
+pub fn build() -> &'static str {
+    option_env!("EXAMPLE_CRATE_BUILD").unwrap_or("dev")
+}
+
+#[byond_fn(name = "add-renamed")]
+pub fn add_renamed(left: usize, right: usize) -> usize {
+    left + right
+}
+
+#[byond_fn(v2)]
+pub fn scale(value: f32, by: f32) -> f32 {
+    value * by
+}
+
+#[byond_fn(chunked = 8)]
+pub fn repeat(text: String, times: usize) -> String {
+    text.repeat(times)
+}
+
+#[byond_fn]
+pub fn counter_new(start: u32) -> Handle<u32> {
+    Handle(start)
+}
+
+#[byond_fn]
+pub fn counter_add(counter: &mut Handle<u32>, by: u32) -> u32 {
+    counter.0 += by;
+    counter.0
+}
@@ -0,0 +1,7 @@
+//! Loaded by BYOND in place of `example_crate`, which it forwards every call to. A new build of
+//! `example_crate` is swapped in with `call_ext("example_loader.dll", "byond_fn_reload")(path)`.
+
+byond_fn::reload::forward! {
+    library = "example_crate";
+    add, add_optional, sum, build, repeat, counter_new, counter_add, "add-renamed", v2 scale
+}
@@ -0,0 +1,125 @@
+#![cfg(target_os = "linux")]
+//! Reloads two builds of `example_crate` through the loader, checking that byond_fn's own exports
+//! are forwarded to whichever build is loaded.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use byond_fn::ffi_v2::{ByondValue, CByondValue};
+use libloading::Library;
+
+type StrShim = unsafe extern "C" fn(c_int, *const *const c_char) -> *const c_char;
+type V2Shim = unsafe extern "C" fn(u32, *const CByondValue) -> CByondValue;
+
+#[cfg(target_arch = "x86_64")]
+const TARGET: &str = "x86_64-unknown-linux-gnu";
+#[cfg(target_arch = "x86")]
+const TARGET: &str = "i686-unknown-linux-gnu";
+
+/// Builds `example_crate` as build `build`, along with the loader, and returns the directory they
+/// were built into
+fn cargo_build(build: &str) -> PathBuf {
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+    // a target directory of its own, as the one running this test is locked
+    let target_dir = workspace.join("target").join("reload_test");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "-p", "example_crate", "-p", "example_loader"])
+        .args([
+            "--features",
+            "byond_fn/allow_other_arch",
+            "--target",
+            TARGET,
+        ])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .env("EXAMPLE_CRATE_BUILD", build)
+        .current_dir(&workspace)
+        .status()
+        .unwrap();
+    assert!(status.success(), "building example_crate failed");
+    target_dir.join(TARGET).join("debug")
+}
+
+struct Loader(Library);
+
+impl Loader {
+    fn call(&self, symbol: &str, args: &[&str]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| CString::new(*arg).unwrap()).collect();
+        let argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let argc = c_int::try_from(argv.len()).unwrap();
+        unsafe {
+            let shim = self.0.get::<StrShim>(symbol.as_bytes()).unwrap();
+            CStr::from_ptr(shim(argc, argv.as_ptr()))
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    fn call_v2(&self, symbol: &str, args: &[f32]) -> Option<f32> {
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| ByondValue::number(*arg).into_raw())
+            .collect();
+        let argc = u32::try_from(args.len()).unwrap();
+        unsafe {
+            let shim = self.0.get::<V2Shim>(symbol.as_bytes()).unwrap();
+            ByondValue::from_raw(shim(argc, args.as_ptr())).as_number()
+        }
+    }
+
+    fn fetch_chunked(&self, header: &str) -> String {
+        let parts: Vec<_> = header.split(';').collect();
+        assert_eq!(parts[0], "@@CHUNKED@@", "{header}");
+        let count: usize = parts[2].parse().unwrap();
+        (0..count)
+            .map(|index| self.call("byond_fn_fetch_chunk", &[parts[1], &index.to_string()]))
+            .collect()
+    }
+}
+
+#[test]
+fn reloads_between_builds() {
+    let scratch = std::env::temp_dir().join(format!("byond_fn_reload_{}", std::process::id()));
+    std::fs::create_dir_all(&scratch).unwrap();
+    let mut builds = Vec::new();
+    for build in ["1", "2"] {
+        let built = cargo_build(build).join("libexample_crate.so");
+        let copy = scratch.join(format!("example_crate_{build}.so"));
+        std::fs::copy(built, &copy).unwrap();
+        builds.push(copy);
+    }
+    let loader_path = cargo_build("2").join("libexample_loader.so");
+    let loader = Loader(unsafe { Library::new(loader_path) }.unwrap());
+
+    let reload = |path: &Path| loader.call("byond_fn_reload", &[path.to_str().unwrap()]);
+    assert_eq!(reload(&builds[0]), "");
+    assert_eq!(loader.call("build", &[]), "1");
+    assert_eq!(loader.call("add", &["2", "3"]), "5");
+    assert_eq!(loader.call("add-renamed", &["2", "3"]), "5");
+    assert_eq!(loader.call_v2("scale", &[2.0, 1.5]), Some(3.0));
+
+    // chunks and handles live in the loaded library, not the loader
+    let header = loader.call("repeat", &["ab", "8"]);
+    assert_eq!(loader.fetch_chunked(&header), "ab".repeat(8));
+    let counter = loader.call("counter_new", &["5"]);
+    assert_eq!(loader.call("counter_add", &[&counter, "2"]), "7");
+    assert_eq!(loader.call("byond_fn_free_handle", &[&counter]), "");
+    let stale = loader.call("counter_add", &[&counter, "2"]);
+    assert!(stale.contains("STALE_HANDLE"), "{stale}");
+
+    assert_eq!(reload(&builds[1]), "");
+    assert_eq!(loader.call("build", &[]), "2");
+    let header = loader.call("repeat", &["xyz", "4"]);
+    assert_eq!(loader.fetch_chunked(&header), "xyz".repeat(4));
+    let counter = loader.call("counter_new", &["1"]);
+    assert_eq!(loader.call("counter_add", &[&counter, "1"]), "2");
+
+    // a failed reload keeps the current build
+    let failed = reload(&scratch.join("missing.so"));
+    assert!(failed.contains("LIBRARY_LOAD"), "{failed}");
+    assert_eq!(loader.call("build", &[]), "2");
+
+    drop(loader);
+    let _ = std::fs::remove_dir_all(scratch);
+}
@@ -0,0 +1,228 @@
+//! Fuzz targets for the string transport, run with `cargo fuzz` from this directory:
+//!
+//! `cargo +nightly fuzz run <target> --features libfuzzer`
+//!
+//! Each target in `fuzz_targets` forwards to the function of the same name here, so they can also
+//! be run over a fixed set of inputs by `cargo test`.
+
+use std::ffi::c_char;
+use std::path::PathBuf;
+
+use byond_fn::byond_fn;
+use byond_fn::fuzz::split_args;
+use byond_fn::handle::Handle;
+use byond_fn::str_ffi::binary::{Base64, Hex};
+use byond_fn::str_ffi::byond_json::ByondJson;
+use byond_fn::str_ffi::encoding::Encoding;
+use byond_fn::str_ffi::json::Json;
+use byond_fn::str_ffi::{parse_raw_args, parse_str_args, Rest, StrArg, StrReturn};
+
+const ENCODINGS: [Encoding; 4] = [
+    Encoding::Utf8,
+    Encoding::Utf8Lossy,
+    Encoding::Latin1,
+    Encoding::Windows1252,
+];
+
+/// Splitting and decoding `argc`/`argv`.
+pub fn str_args(data: &[u8]) {
+    let args = split_args(data);
+    let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+    let argc = argv.len().try_into().unwrap();
+
+    let raw = unsafe { parse_raw_args(argc, argv.as_ptr()) };
+    assert_eq!(raw.len(), args.len());
+    if let Ok(parsed) = unsafe { parse_str_args(argc, argv.as_ptr()) } {
+        assert_eq!(parsed.len(), args.len());
+    }
+
+    for encoding in ENCODINGS {
+        if let Ok(decoded) = encoding.decode_args(&raw) {
+            assert_eq!(decoded.len(), raw.len());
+        }
+    }
+    // single byte encodings have to round trip anything
+    for encoding in [Encoding::Latin1, Encoding::Windows1252] {
+        let decoded = encoding.decode(data).unwrap();
+        assert_eq!(&*encoding.encode(&decoded), data);
+    }
+}
+
+fn from_arg<'a, T: StrArg<'a>>(arg: &'a str) -> Option<T> {
+    T::from_arg(arg, "arg").ok()
+}
+
+/// Every `StrArg` impl of the crate.
+pub fn str_arg(data: &[u8]) {
+    let Ok(arg) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    macro_rules! parse_all {
+        ($($ty:ty),*) => {
+            $(
+                from_arg::<$ty>(arg);
+                from_arg::<Option<$ty>>(arg);
+            )*
+        };
+    }
+    parse_all!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, bool);
+    parse_all!(String, &str, PathBuf, &std::path::Path);
+
+    let parts: Vec<&str> = arg.split(',').collect();
+    let _ = Rest::<i32>::from_args(&parts, "arg");
+
+    // anything that decodes has to encode back the same way, give or take case and padding
+    if let Some(Base64(bytes)) = from_arg::<Base64<Vec<u8>>>(arg) {
+        let encoded = Base64(&bytes).to_return().unwrap().unwrap();
+        let decoded: Base64<Vec<u8>> = from_arg(std::str::from_utf8(&encoded).unwrap()).unwrap();
+        assert_eq!(decoded.0, bytes);
+    }
+    if let Some(Hex(bytes)) = from_arg::<Hex<Vec<u8>>>(arg) {
+        let encoded = Hex(&bytes).to_return().unwrap().unwrap();
+        assert!(encoded.eq_ignore_ascii_case(arg.as_bytes()));
+    }
+    from_arg::<Base64<[u8; 4]>>(arg);
+    from_arg::<Hex<[u8; 4]>>(arg);
+}
+
+#[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+pub struct Player {
+    ckey: String,
+    age: Option<u32>,
+    roles: Vec<String>,
+}
+
+/// `Json<T>` and `ByondJson<T>` arguments and returns.
+pub fn json(data: &[u8]) {
+    let Ok(arg) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    if let Some(Json(value)) = from_arg::<Json<serde_json::Value>>(arg) {
+        // floats aren't parsed exactly without serde_json's `float_roundtrip`, so only check that
+        // the return is valid JSON
+        let returned = Json(value).to_return().unwrap().unwrap();
+        serde_json::from_slice::<serde_json::Value>(&returned).unwrap();
+    }
+    from_arg::<Json<Player>>(arg);
+    from_arg::<Json<Vec<Player>>>(arg);
+
+    if let Some(ByondJson(value)) = from_arg::<ByondJson<serde_json::Value>>(arg) {
+        let returned = ByondJson(value).to_return().unwrap().unwrap();
+        serde_json::from_slice::<serde_json::Value>(&returned).unwrap();
+    }
+    from_arg::<ByondJson<Player>>(arg);
+    from_arg::<ByondJson<Vec<(f32, u8)>>>(arg);
+}
+
+#[byond_fn]
+pub fn numbers(small: u8, flag: bool, big: Option<i64>) -> i64 {
+    i64::from(small) + big.unwrap_or_default() * i64::from(flag)
+}
+
+#[byond_fn]
+pub fn text(text: &str, path: PathBuf) -> String {
+    format!("{text}{}", path.display())
+}
+
+#[byond_fn]
+pub fn joined(sep: String, parts: Rest<u32>) -> String {
+    parts
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(&sep)
+}
+
+#[byond_fn]
+pub fn json_echo(value: Json<serde_json::Value>) -> Json<serde_json::Value> {
+    value
+}
+
+#[byond_fn]
+pub fn binary(data: Base64<Vec<u8>>, raw: &[u8]) -> Hex<Vec<u8>> {
+    Hex(data.iter().chain(raw).copied().collect())
+}
+
+#[byond_fn(encoding = "windows1252", nul = "escape")]
+pub fn legacy(text: String) -> Vec<u8> {
+    text.into_bytes()
+        .into_iter()
+        .flat_map(|byte| [byte, 0])
+        .collect()
+}
+
+#[byond_fn(chunked = 8)]
+pub fn chunked(text: String) -> String {
+    text.repeat(3)
+}
+
+#[byond_fn]
+pub fn handle_new(value: String) -> Handle<String> {
+    Handle(value)
+}
+
+#[byond_fn]
+pub fn handle_swap(left: &mut Handle<String>, right: &mut Handle<String>) {
+    std::mem::swap(&mut left.0, &mut right.0);
+}
+
+const SHIMS: &[fn(&[u8])] = &[
+    __byond_fn_numbers_fuzz,
+    __byond_fn_text_fuzz,
+    __byond_fn_joined_fuzz,
+    __byond_fn_json_echo_fuzz,
+    __byond_fn_binary_fuzz,
+    __byond_fn_legacy_fuzz,
+    __byond_fn_chunked_fuzz,
+    __byond_fn_handle_new_fuzz,
+    __byond_fn_handle_swap_fuzz,
+    byond_fn::handle::__byond_fn_free_handle_fuzz,
+    byond_fn::str_ffi::chunked::__byond_fn_fetch_chunk_fuzz,
+    byond_fn::str_ffi::chunked::__byond_fn_cancel_fuzz,
+];
+
+/// Generated functions, with the first byte picking which one to call.
+pub fn shims(data: &[u8]) {
+    let Some((&pick, args)) = data.split_first() else {
+        return;
+    };
+    SHIMS[usize::from(pick) % SHIMS.len()](args);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SEEDS: &[&[u8]] = &[
+        b"",
+        b"\0",
+        b"1\x002\0true",
+        b"-1\0\0\0",
+        b"\xFF\xFE\x80\0\x93",
+        b"\0Zm9v\0beef",
+        b"Zm8=",
+        b"DEADbeef",
+        b"{\"ckey\":\"a\",\"age\":1,\"roles\":[\"b\"]}",
+        b"[[[[{}]]]]",
+        b"{\"ckey\":\"a\",\"age\":1e+001,\"roles\":[]}",
+        b"[[1.#INF,2],[-1.#IND,1e+002],[\"-inf\",3]]",
+        b"0:1\x000:1",
+        b"1\x000",
+    ];
+
+    #[test]
+    fn seeds_run_cleanly() {
+        for seed in SEEDS {
+            str_args(seed);
+            str_arg(seed);
+            json(seed);
+            for pick in 0..SHIMS.len() {
+                let mut input = vec![u8::try_from(pick).unwrap()];
+                input.extend_from_slice(seed);
+                shims(&input);
+            }
+        }
+    }
+}
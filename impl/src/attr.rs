@@ -0,0 +1,497 @@
+use proc_macro2::{Ident, Span, TokenStream};
+use proc_macro_error::abort;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{Expr, ExprLit, Lit, LitInt, LitStr, Meta, Token};
+
+use crate::{FFI_V2_DESC, STR_FFI_DESC};
+
+const VALID_KEYS: &str =
+    "`name = \"...\"`, `prefix = \"...\"`, `transport = \"...\"`, `str`, `v2`, `chunked`, `chunked = <bytes>`, \
+     `encoding = \"...\"`, `nul = \"...\"`, `min_version = \"...\"`, `fallback = \"...\"`, `errors = \"...\"`, \
+     `max_arg_bytes = <bytes>`, `max_total_bytes = <bytes>`, `max_json_depth = <depth>`, \
+     `max_collection_len = <len>`";
+
+/// Keys of the argument limits, which are also the fields of `byond_fn::str_ffi::limits::ArgLimits`
+const LIMIT_KEYS: [&str; 4] = [
+    "max_arg_bytes",
+    "max_total_bytes",
+    "max_json_depth",
+    "max_collection_len",
+];
+
+/// Which FFI transport the generated shim should use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Transport {
+    Str,
+    #[cfg_attr(not(feature = "ffi_v2"), allow(dead_code))]
+    V2,
+}
+
+impl Transport {
+    /// Appended to the export name of a fallback exported with this transport
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Transport::Str => "str",
+            Transport::V2 => "v2",
+        }
+    }
+}
+
+/// Parsed arguments of a `#[byond_fn(...)]` attribute
+#[derive(Clone)]
+pub(crate) struct ByondFnAttr {
+    pub transport: Transport,
+    pub name: Option<LitStr>,
+    pub prefix: Option<LitStr>,
+    /// Chunk size for chunked returns. `Some(None)` uses the default size
+    pub chunked: Option<Option<LitInt>>,
+    /// Text encoding of args and returns, overriding the library default
+    pub encoding: Option<Ident>,
+    /// How NUL bytes in returns are handled, overriding the library default
+    pub nul: Option<Ident>,
+    /// The oldest BYOND version calls are allowed on, as the major version and build
+    pub min_version: Option<(u32, u32)>,
+    /// A transport to also export the function with, for hosts that can't use the main one
+    pub fallback: Option<Transport>,
+    /// If errors are raised as DM runtime errors, rather than returned as strings
+    pub runtime_errors: bool,
+    /// Argument limits overriding the library defaults, by the `ArgLimits` field they set
+    pub limits: Vec<(Ident, LitInt)>,
+    /// If the function is one of byond_fn's own, which a hot reload loader forwards to the library
+    /// it loaded. Not listed in `VALID_KEYS`, as it's only for byond_fn itself
+    pub builtin: bool,
+}
+
+impl Default for ByondFnAttr {
+    fn default() -> Self {
+        Self {
+            transport: Transport::Str,
+            name: None,
+            prefix: None,
+            chunked: None,
+            encoding: None,
+            nul: None,
+            min_version: None,
+            fallback: None,
+            runtime_errors: false,
+            limits: Vec::new(),
+            builtin: false,
+        }
+    }
+}
+
+impl ByondFnAttr {
+    pub fn parse(args: TokenStream) -> Self {
+        let metas = Punctuated::<Meta, Token![,]>::parse_terminated
+            .parse2(args)
+            .unwrap_or_else(|err| abort!(err.span(), "invalid byond_fn arguments: {}", err; help = "valid arguments are: {}", VALID_KEYS));
+
+        let mut attr = Self::default();
+        let mut transport_set = false;
+        let mut errors_set = false;
+        for meta in metas {
+            match &meta {
+                Meta::Path(path) if path.is_ident("str") || path.is_ident("v2") => {
+                    let ident = path.get_ident().unwrap();
+                    ensure_unset(transport_set, meta.span(), "transport");
+                    transport_set = true;
+                    attr.transport = parse_transport(&ident.to_string(), ident.span());
+                }
+                Meta::Path(path) if path.is_ident("builtin") => {
+                    ensure_unset(attr.builtin, meta.span(), "builtin");
+                    attr.builtin = true;
+                }
+                Meta::Path(path) if path.is_ident("chunked") => {
+                    ensure_unset(attr.chunked.is_some(), meta.span(), "chunked");
+                    attr.chunked = Some(None);
+                }
+                Meta::NameValue(name_value) => {
+                    let key = name_value
+                        .path
+                        .get_ident()
+                        .map(ToString::to_string)
+                        .unwrap_or_default();
+                    match key.as_str() {
+                        "name" => {
+                            let name = expect_str(&name_value.value, &key);
+                            validate_symbol(&name);
+                            ensure_unset(attr.name.is_some(), meta.span(), &key);
+                            attr.name = Some(name);
+                        }
+                        "prefix" => {
+                            let prefix = expect_str(&name_value.value, &key);
+                            validate_symbol(&prefix);
+                            ensure_unset(attr.prefix.is_some(), meta.span(), &key);
+                            attr.prefix = Some(prefix);
+                        }
+                        "chunked" => {
+                            let size = match &name_value.value {
+                                Expr::Lit(ExprLit {
+                                    lit: Lit::Int(lit), ..
+                                }) => lit.clone(),
+                                other => abort!(
+                                    other.span(),
+                                    "byond_fn argument `chunked` expects a chunk size in bytes"
+                                ),
+                            };
+                            if size.base10_parse::<usize>().map_or(true, |size| size == 0) {
+                                abort!(size.span(), "chunk size must be a positive integer");
+                            }
+                            ensure_unset(attr.chunked.is_some(), meta.span(), &key);
+                            attr.chunked = Some(Some(size));
+                        }
+                        "encoding" => {
+                            let encoding = expect_str(&name_value.value, &key);
+                            ensure_unset(attr.encoding.is_some(), meta.span(), &key);
+                            attr.encoding = Some(parse_encoding(&encoding));
+                        }
+                        "nul" => {
+                            let nul = expect_str(&name_value.value, &key);
+                            ensure_unset(attr.nul.is_some(), meta.span(), &key);
+                            attr.nul = Some(parse_nul_policy(&nul));
+                        }
+                        "min_version" => {
+                            let version = expect_str(&name_value.value, &key);
+                            ensure_unset(attr.min_version.is_some(), meta.span(), &key);
+                            attr.min_version = Some(parse_version(&version));
+                        }
+                        "fallback" => {
+                            let fallback = expect_str(&name_value.value, &key);
+                            ensure_unset(attr.fallback.is_some(), meta.span(), &key);
+                            attr.fallback =
+                                Some(parse_transport(&fallback.value(), fallback.span()));
+                            if attr.fallback != Some(Transport::Str) {
+                                abort!(
+                                    fallback.span(),
+                                    "only the str transport can be a fallback";
+                                    help = "use `v2, fallback = \"str\"`"
+                                );
+                            }
+                        }
+                        "errors" => {
+                            let errors = expect_str(&name_value.value, &key);
+                            ensure_unset(errors_set, meta.span(), &key);
+                            errors_set = true;
+                            attr.runtime_errors = parse_error_mode(&errors);
+                        }
+                        key if LIMIT_KEYS.contains(&key) => {
+                            let limit = match &name_value.value {
+                                Expr::Lit(ExprLit {
+                                    lit: Lit::Int(lit), ..
+                                }) => lit.clone(),
+                                other => abort!(
+                                    other.span(),
+                                    "byond_fn argument `{}` expects an integer",
+                                    key
+                                ),
+                            };
+                            if limit.base10_parse::<usize>().is_err() {
+                                abort!(limit.span(), "`{}` must fit in a usize", key);
+                            }
+                            let already_set = attr.limits.iter().any(|(field, _)| field == key);
+                            ensure_unset(already_set, meta.span(), key);
+                            attr.limits.push((Ident::new(key, limit.span()), limit));
+                        }
+                        "transport" => {
+                            let transport = expect_str(&name_value.value, &key);
+                            ensure_unset(transport_set, meta.span(), &key);
+                            transport_set = true;
+                            attr.transport = parse_transport(&transport.value(), transport.span());
+                        }
+                        _ => abort!(
+                            name_value.path.span(),
+                            "unknown byond_fn argument `{}`", key;
+                            help = "valid arguments are: {}", VALID_KEYS
+                        ),
+                    }
+                }
+                _ => abort!(
+                    meta.span(),
+                    "unknown byond_fn argument `{}`", quote::quote!(#meta);
+                    help = "valid arguments are: {}", VALID_KEYS
+                ),
+            }
+        }
+        if attr.fallback.is_some() && attr.transport != Transport::V2 {
+            abort!(
+                Span::call_site(),
+                "a fallback transport can only be used with the v2 transport";
+                help = "use `v2, fallback = \"str\"`"
+            );
+        }
+        if attr.runtime_errors && attr.transport != Transport::V2 {
+            abort!(
+                Span::call_site(),
+                "runtime errors can only be raised with the v2 transport";
+                help = "use `v2, errors = \"runtime\"`"
+            );
+        }
+        attr
+    }
+
+    /// The symbol name the generated function is exported under.
+    ///
+    /// An explicit `name` is used verbatim, otherwise it is the rust ident with the prefix applied.
+    /// Without a prefix of its own, the crate prefix from `BYOND_FN_PREFIX` is applied.
+    pub fn export_name(&self, ident: &syn::Ident) -> Option<String> {
+        self.export_name_with(ident, crate_prefix().as_deref())
+    }
+
+    fn export_name_with(&self, ident: &syn::Ident, crate_prefix: Option<&str>) -> Option<String> {
+        match (&self.name, &self.prefix, crate_prefix) {
+            (Some(name), _, _) => Some(name.value()),
+            (None, Some(prefix), _) => Some(format!("{}{ident}", prefix.value())),
+            (None, None, Some(prefix)) => Some(format!("{prefix}{ident}")),
+            (None, None, None) => None,
+        }
+    }
+}
+
+/// The environment variable holding the prefix for every export of the crate being compiled
+const CRATE_PREFIX_VAR: &str = "BYOND_FN_PREFIX";
+
+/// The crate-level export prefix, set through the environment as a macro can't see a declaration
+/// anywhere else in the crate. Build scripts set it with `cargo:rustc-env`, which also rebuilds
+/// the crate when it changes.
+fn crate_prefix() -> Option<String> {
+    let prefix = std::env::var(CRATE_PREFIX_VAR)
+        .ok()
+        .filter(|prefix| !prefix.is_empty())?;
+    if prefix.contains('\0') || prefix.chars().any(char::is_whitespace) {
+        abort!(
+            Span::call_site(),
+            "`{}` can't contain NUL bytes or whitespace, as it's part of exported names",
+            CRATE_PREFIX_VAR
+        );
+    }
+    Some(prefix)
+}
+
+fn ensure_unset(already_set: bool, span: Span, key: &str) {
+    if already_set {
+        abort!(span, "byond_fn argument `{}` specified more than once", key);
+    }
+}
+
+pub(crate) fn expect_str(expr: &Expr, key: &str) -> LitStr {
+    match expr {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => lit.clone(),
+        _ => abort!(
+            expr.span(),
+            "byond_fn argument `{}` expects a string literal",
+            key
+        ),
+    }
+}
+
+pub(crate) fn validate_symbol(lit: &LitStr) {
+    let value = lit.value();
+    if value.contains('\0') {
+        abort!(lit.span(), "exported names can't contain NUL bytes");
+    }
+    if value.chars().any(char::is_whitespace) {
+        abort!(lit.span(), "exported names can't contain whitespace");
+    }
+}
+
+/// Maps an encoding name to the matching `byond_fn::str_ffi::encoding::Encoding` variant
+fn parse_encoding(lit: &LitStr) -> Ident {
+    let variant = match lit.value().to_ascii_lowercase().as_str() {
+        "utf8" | "utf-8" => "Utf8",
+        "utf8_lossy" | "utf-8-lossy" => "Utf8Lossy",
+        "latin1" | "latin-1" | "iso-8859-1" => "Latin1",
+        "windows1252" | "windows-1252" | "cp1252" => "Windows1252",
+        other => abort!(
+            lit.span(),
+            "unknown encoding \"{}\"", other;
+            help = "valid encodings are: \"utf8\", \"utf8_lossy\", \"latin1\", \"windows1252\""
+        ),
+    };
+    Ident::new(variant, lit.span())
+}
+
+/// Maps a policy name to the matching `byond_fn::str_ffi::binary::NulPolicy` variant
+fn parse_nul_policy(lit: &LitStr) -> Ident {
+    let variant = match lit.value().to_ascii_lowercase().as_str() {
+        "error" => "Error",
+        "escape" => "Escape",
+        "strip" => "Strip",
+        other => abort!(
+            lit.span(),
+            "unknown NUL policy \"{}\"", other;
+            help = "valid policies are: \"error\", \"escape\", \"strip\""
+        ),
+    };
+    Ident::new(variant, lit.span())
+}
+
+/// Whether an error mode name means errors are raised as runtime errors
+fn parse_error_mode(lit: &LitStr) -> bool {
+    match lit.value().to_ascii_lowercase().as_str() {
+        "return" => false,
+        "runtime" => true,
+        other => abort!(
+            lit.span(),
+            "unknown error mode \"{}\"", other;
+            help = "valid error modes are: \"return\", \"runtime\""
+        ),
+    }
+}
+
+/// Parses a BYOND version like `"515.1609"`, or just `"515"`, into the major version and build
+#[cfg(feature = "ffi_v2")]
+fn parse_version(lit: &LitStr) -> (u32, u32) {
+    let value = lit.value();
+    let (major, build) = value.split_once('.').unwrap_or((&value, "0"));
+    match (major.parse(), build.parse()) {
+        (Ok(major), Ok(build)) => (major, build),
+        _ => abort!(
+            lit.span(),
+            "invalid BYOND version \"{}\"", value;
+            help = "versions are written like \"515.1609\""
+        ),
+    }
+}
+
+#[cfg(not(feature = "ffi_v2"))]
+fn parse_version(lit: &LitStr) -> (u32, u32) {
+    abort!(
+        lit.span(),
+        "`min_version` requires the `ffi_v2` feature of byond_fn, which detects the host version"
+    )
+}
+
+fn parse_transport(value: &str, span: Span) -> Transport {
+    match value {
+        "str" | "default" => Transport::Str,
+        #[cfg(feature = "ffi_v2")]
+        "v2" => Transport::V2,
+        #[cfg(not(feature = "ffi_v2"))]
+        "v2" => abort!(
+            span,
+            "the v2 transport requires the `ffi_v2` feature of byond_fn"
+        ),
+        _ => abort!(
+            span,
+            "unknown transport \"{}\"", value;
+            help = "valid transports are:\n{}\n{}", STR_FFI_DESC, FFI_V2_DESC
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quote::quote;
+
+    use super::*;
+
+    #[test]
+    fn parses_empty() {
+        let attr = ByondFnAttr::parse(quote! {});
+        assert_eq!(attr.transport, Transport::Str);
+        assert!(attr.name.is_none());
+        assert!(attr.prefix.is_none());
+    }
+
+    #[test]
+    fn parses_key_values() {
+        let attr = ByondFnAttr::parse(quote! { str, name = "my-fn", prefix = "lib_" });
+        assert_eq!(attr.transport, Transport::Str);
+        assert_eq!(attr.name.unwrap().value(), "my-fn");
+        assert_eq!(attr.prefix.unwrap().value(), "lib_");
+    }
+
+    #[test]
+    fn parses_chunked() {
+        let attr = ByondFnAttr::parse(quote! { chunked });
+        assert!(matches!(attr.chunked, Some(None)));
+
+        let attr = ByondFnAttr::parse(quote! { chunked = 4096 });
+        assert_eq!(
+            attr.chunked
+                .unwrap()
+                .unwrap()
+                .base10_parse::<usize>()
+                .unwrap(),
+            4096
+        );
+    }
+
+    #[test]
+    fn parses_encoding() {
+        let attr = ByondFnAttr::parse(quote! { encoding = "CP1252" });
+        assert_eq!(attr.encoding.unwrap(), "Windows1252");
+    }
+
+    #[test]
+    fn parses_nul_policy() {
+        let attr = ByondFnAttr::parse(quote! { nul = "escape" });
+        assert_eq!(attr.nul.unwrap(), "Escape");
+    }
+
+    #[cfg(feature = "ffi_v2")]
+    #[test]
+    fn parses_versions() {
+        let attr = ByondFnAttr::parse(quote! { v2, min_version = "515.1609", fallback = "str" });
+        assert_eq!(attr.min_version, Some((515, 1609)));
+        assert_eq!(attr.fallback, Some(Transport::Str));
+
+        let attr = ByondFnAttr::parse(quote! { min_version = "516" });
+        assert_eq!(attr.min_version, Some((516, 0)));
+    }
+
+    #[cfg(feature = "ffi_v2")]
+    #[test]
+    fn parses_error_mode() {
+        let attr = ByondFnAttr::parse(quote! { v2 });
+        assert!(!attr.runtime_errors);
+
+        let attr = ByondFnAttr::parse(quote! { v2, errors = "Runtime" });
+        assert!(attr.runtime_errors);
+    }
+
+    #[test]
+    fn parses_limits() {
+        let attr = ByondFnAttr::parse(quote! { max_arg_bytes = 1024, max_json_depth = 8 });
+        let limits: Vec<_> = attr
+            .limits
+            .iter()
+            .map(|(field, limit)| (field.to_string(), limit.base10_parse::<usize>().unwrap()))
+            .collect();
+        assert_eq!(
+            limits,
+            [
+                ("max_arg_bytes".to_string(), 1024),
+                ("max_json_depth".to_string(), 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn export_name_precedence() {
+        let ident = syn::Ident::new("add", Span::call_site());
+
+        let attr = ByondFnAttr::parse(quote! {});
+        assert_eq!(attr.export_name_with(&ident, None), None);
+        assert_eq!(
+            attr.export_name_with(&ident, Some("crate_")).as_deref(),
+            Some("crate_add")
+        );
+
+        let attr = ByondFnAttr::parse(quote! { prefix = "lib_" });
+        assert_eq!(
+            attr.export_name_with(&ident, Some("crate_")).as_deref(),
+            Some("lib_add")
+        );
+
+        let attr = ByondFnAttr::parse(quote! { prefix = "lib_", name = "other" });
+        assert_eq!(
+            attr.export_name_with(&ident, Some("crate_")).as_deref(),
+            Some("other")
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use proc_macro2::TokenStream;
+use proc_macro_error::abort;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_quote, Data, DeriveInput, Fields, GenericParam, Lifetime};
+
+use crate::type_is_named;
+
+pub(crate) fn derive_byond_args2(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse2(input).unwrap();
+    let ident = &input.ident;
+
+    let Data::Struct(data) = &input.data else {
+        abort!(
+            input.span(),
+            "ByondArgs can only be derived for structs";
+            help = "fields are mapped to consecutive arguments, in order"
+        );
+    };
+    let fields: Vec<_> = match &data.fields {
+        Fields::Named(fields) => fields.named.iter().collect(),
+        Fields::Unnamed(fields) => fields.unnamed.iter().collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    // same rules as the arguments of a function
+    let is_rest = |ty| type_is_named(ty, "Rest");
+    let is_option = |ty| type_is_named(ty, "Option");
+    if let Some(field) = fields.iter().rev().skip(1).find(|field| is_rest(&field.ty)) {
+        abort!(
+            field.span(),
+            "Rest fields must be the last field of the struct"
+        );
+    }
+    let mut optional_encountered = false;
+    for field in fields.iter().filter(|field| !is_rest(&field.ty)) {
+        if optional_encountered && !is_option(&field.ty) {
+            abort!(
+                field.span(),
+                "Optional fields must be at the end of the struct"
+            );
+        }
+        optional_encountered = is_option(&field.ty);
+    }
+
+    let has_rest = fields.last().is_some_and(|field| is_rest(&field.ty));
+    let min_args = fields
+        .iter()
+        .filter(|field| !is_option(&field.ty) && !is_rest(&field.ty))
+        .count();
+    let max_args = if has_rest {
+        quote! { usize::MAX }
+    } else {
+        let max_args = fields.len();
+        quote! { #max_args }
+    };
+
+    // fields borrow from the arguments through the struct's lifetime, if it has one
+    let mut lifetimes = input.generics.lifetimes();
+    let lifetime: Lifetime = match (lifetimes.next(), lifetimes.next()) {
+        (None, _) => parse_quote!('__byond_args),
+        (Some(param), None) => param.lifetime.clone(),
+        (Some(_), Some(extra)) => abort!(
+            extra.span(),
+            "ByondArgs can't be derived for structs with more than one lifetime"
+        ),
+    };
+    let mut impl_generics = input.generics.clone();
+    if input.generics.lifetimes().next().is_none() {
+        impl_generics
+            .params
+            .insert(0, GenericParam::Lifetime(parse_quote!(#lifetime)));
+    }
+    let (impl_generics, _, _) = impl_generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut where_clause = where_clause.cloned().unwrap_or_else(|| parse_quote!(where));
+    for field in &fields {
+        let ty = &field.ty;
+        if !is_rest(ty) {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: byond_fn::str_ffi::StrArg<#lifetime>));
+        }
+    }
+
+    let field_values = fields.iter().enumerate().map(|(num, field)| {
+        let name = field
+            .ident
+            .as_ref()
+            .map_or_else(|| num.to_string(), ToString::to_string);
+        if is_rest(&field.ty) {
+            quote! {
+                byond_fn::str_ffi::Rest::from_args(args.get(#num..).unwrap_or_default(), #name)?
+            }
+        } else {
+            quote! {
+                byond_fn::str_ffi::StrArg::map_arg(
+                    args.get(#num).copied(),
+                    Self::MIN_ARGS,
+                    Self::MAX_ARGS,
+                    #name,
+                    #num,
+                )?
+            }
+        }
+    });
+    let construct = match &data.fields {
+        Fields::Named(_) => {
+            let names = fields.iter().map(|field| &field.ident);
+            quote! { Self { #(#names: #field_values),* } }
+        }
+        Fields::Unnamed(_) => quote! { Self(#(#field_values),*) },
+        Fields::Unit => quote! { Self },
+    };
+    quote! {
+        impl #impl_generics byond_fn::str_ffi::ByondArgs<#lifetime> for #ident #ty_generics #where_clause {
+            const MIN_ARGS: usize = #min_args;
+            const MAX_ARGS: usize = #max_args;
+
+            fn from_args(args: &[&#lifetime str]) -> Result<Self, byond_fn::str_ffi::FFIError> {
+                if !(Self::MIN_ARGS..=Self::MAX_ARGS).contains(&args.len()) {
+                    return Err(byond_fn::str_ffi::TransportError::WrongArgCount {
+                        expected_min: Self::MIN_ARGS,
+                        expected_max: Self::MAX_ARGS,
+                        got: args.len(),
+                    }
+                    .into());
+                }
+                Ok(#construct)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_optional_and_rest_fields() {
+        let expanded = derive_byond_args2(quote! {
+            struct Args<'a> {
+                name: &'a str,
+                count: u32,
+                scale: Option<u32>,
+            }
+        })
+        .to_string();
+        assert!(expanded.contains("ByondArgs < 'a > for Args < 'a >"));
+        assert!(expanded.contains("const MIN_ARGS : usize = 2usize"));
+        assert!(expanded.contains("const MAX_ARGS : usize = 3usize"));
+
+        let expanded = derive_byond_args2(quote! {
+            struct Args(String, Rest<u8>);
+        })
+        .to_string();
+        assert!(expanded.contains("ByondArgs < '__byond_args > for Args"));
+        assert!(expanded.contains("const MIN_ARGS : usize = 1usize"));
+        assert!(expanded.contains("const MAX_ARGS : usize = usize :: MAX"));
+    }
+}
@@ -1 +1,182 @@
 #![cfg(feature = "ffi_v2")]
+
+use proc_macro2::TokenStream;
+use proc_macro_error::abort;
+use quote::{format_ident, quote, ToTokens};
+use syn::spanned::Spanned;
+use syn::{FnArg, Signature};
+
+use crate::attr::ByondFnAttr;
+use crate::{
+    handle_ref_mutability, is_args_struct, is_option_type, is_raw_bytes_type, is_rest_type,
+    span_end_tokens, span_start_tokens, version_check_tokens, Callee, FFITokens,
+};
+
+fn return_type_token() -> TokenStream {
+    quote! { byond_fn::ffi_v2::CByondValue }
+}
+
+fn args_tokens() -> TokenStream {
+    quote! { argc: u32, argv: *const byond_fn::ffi_v2::CByondValue }
+}
+
+/// The function values and errors are returned through.
+///
+/// With runtime errors, the body is run by `call_or_crash`, so errors are returned to it as they
+/// are, instead of as strings.
+fn return_fn_tokens(proc_args: &ByondFnAttr) -> TokenStream {
+    if proc_args.runtime_errors {
+        quote! { byond_fn::ffi_v2::runtime::try_return }
+    } else {
+        quote! { byond_fn::ffi_v2::byond_return }
+    }
+}
+
+/// Unwraps a `Result<_, FFIError>`, early returning the error through `return_fn`
+fn try_tokens(expr: TokenStream, return_fn: &TokenStream) -> TokenStream {
+    quote! {
+        match #expr {
+            Ok(arg) => arg,
+            Err(err) => {
+                return #return_fn(err);
+            },
+        }
+    }
+}
+
+/// Aborts on what only the str transport supports
+fn validate(sig: &Signature, proc_args: &ByondFnAttr) {
+    // with a str fallback, these apply to the fallback
+    let str_only =
+        proc_args.chunked.is_some() || proc_args.encoding.is_some() || proc_args.nul.is_some();
+    if str_only && proc_args.fallback.is_none() {
+        abort!(
+            sig.ident.span(),
+            "`chunked`, `encoding` and `nul` only apply to the str transport"
+        );
+    }
+    if !proc_args.limits.is_empty() && proc_args.fallback.is_none() {
+        abort!(
+            sig.ident.span(),
+            "argument limits only apply to the str transport"
+        );
+    }
+    for arg in &sig.inputs {
+        let unsupported = if is_rest_type(arg) {
+            "Rest arguments"
+        } else if is_args_struct(arg) {
+            "`#[byond_args]` parameters"
+        } else if handle_ref_mutability(arg).is_some() {
+            "handle arguments"
+        } else if is_raw_bytes_type(arg) {
+            "raw byte arguments"
+        } else {
+            continue;
+        };
+        abort!(
+            arg.span(),
+            "{} are not supported by the v2 transport", unsupported;
+            help = "take a `ByondValue` or `ByondList` instead"
+        );
+    }
+}
+
+fn fn_body_tokens(sig: &Signature, callee: &Callee, proc_args: &ByondFnAttr) -> TokenStream {
+    let Signature { ident, inputs, .. } = sig;
+    let return_fn = return_fn_tokens(proc_args);
+    let try_tokens = |expr: TokenStream| try_tokens(expr, &return_fn);
+
+    let min_args = inputs.iter().filter(|arg| !is_option_type(arg)).count();
+    let max_args = inputs.len();
+    let args_binding = inputs.iter().enumerate().map(|(num, fn_arg)| {
+        if let FnArg::Typed(arg) = fn_arg {
+            let arg = *arg.pat.clone();
+            let arg_string = arg.to_token_stream().to_string();
+            let map_arg = try_tokens(quote! {
+                byond_fn::ffi_v2::FromByondValue::map_arg(args.get(#num), #min_args, #max_args, #arg_string, #num)
+            });
+            quote! { let #arg = #map_arg; }
+        } else {
+            panic!("Byond functions can't have self argument")
+        }
+    });
+
+    let call_args: Vec<_> = inputs
+        .iter()
+        .map(|fn_arg| {
+            if let FnArg::Typed(arg) = fn_arg {
+                let pat = *arg.pat.clone();
+                quote! { #pat }
+            } else {
+                panic!("Byond functions can't have self argument")
+            }
+        })
+        .collect();
+    let call = callee.call_tokens(ident, &call_args);
+    let call_span = format_ident!("__byond_fn_span_call");
+    let call_span_start = span_start_tokens("call", &call_span);
+    let call = if call_span_start.is_empty() {
+        call
+    } else {
+        quote! {{
+            #call_span_start
+            #call
+        }}
+    };
+
+    let min_args_u32 = min_args as u32;
+    let max_args_u32 = max_args as u32;
+
+    let actual_check = if min_args == max_args {
+        quote! { argc != #min_args_u32 }
+    } else {
+        quote! { !(#min_args_u32..=#max_args_u32).contains(&argc) }
+    };
+
+    let args_span = format_ident!("__byond_fn_span_args");
+    let args_span_start = span_start_tokens("args", &args_span);
+    let args_span_end = span_end_tokens(&args_span);
+    let arg_stuff = if !inputs.is_empty() {
+        quote! {
+            #args_span_start
+            if #actual_check {
+                return #return_fn(byond_fn::str_ffi::TransportError::WrongArgCount {
+                    expected_min: #min_args,
+                    expected_max: #max_args,
+                    got: argc as usize,
+                });
+            }
+            let args = byond_fn::ffi_v2::parse_args(argc, argv);
+            #(#args_binding)*
+            #args_span_end
+        }
+    } else {
+        quote! {}
+    };
+    let return_span_start = span_start_tokens("return", &format_ident!("__byond_fn_span_return"));
+    let version_check = version_check_tokens(proc_args, try_tokens);
+
+    let body = quote! {
+        #version_check
+        #arg_stuff
+        let __byond_fn_ret = #call;
+        #return_span_start
+        #return_fn(__byond_fn_ret)
+    };
+    if proc_args.runtime_errors {
+        quote! {
+            byond_fn::ffi_v2::runtime::call_or_crash(move || { #body })
+        }
+    } else {
+        body
+    }
+}
+
+pub(crate) fn tokens(sig: &Signature, callee: &Callee, proc_args: &ByondFnAttr) -> FFITokens {
+    validate(sig, proc_args);
+    FFITokens {
+        fn_args: args_tokens(),
+        return_type: return_type_token(),
+        fn_body: fn_body_tokens(sig, callee, proc_args),
+    }
+}
@@ -0,0 +1,291 @@
+use proc_macro2::{Ident, TokenStream};
+use proc_macro_error::abort;
+use quote::{format_ident, quote};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{FnArg, ImplItem, ItemImpl, LitStr, Meta, Token, Type};
+
+use crate::attr::{expect_str, validate_symbol, ByondFnAttr};
+use crate::{is_attr_named, shim_tokens, strip_args_markers, validate_inputs, Callee};
+
+/// The arguments of `#[byond_impl(...)]`
+#[derive(Default)]
+struct ImplArgs {
+    receiver_from_handle: bool,
+    /// Set by an enclosing `#[byond_prefix]`, for the exports the block generates itself
+    prefix: Option<LitStr>,
+}
+
+impl ImplArgs {
+    fn parse(args: TokenStream) -> Self {
+        let metas = syn::parse::Parser::parse2(
+            Punctuated::<Meta, Token![,]>::parse_terminated,
+            args.clone(),
+        )
+        .unwrap_or_else(|err| abort!(err.span(), "invalid byond_impl arguments"));
+        let mut impl_args = Self::default();
+        for meta in metas {
+            match &meta {
+                Meta::Path(path) if path.is_ident("handle") && !impl_args.receiver_from_handle => {
+                    impl_args.receiver_from_handle = true;
+                }
+                Meta::NameValue(name_value)
+                    if name_value.path.is_ident("prefix") && impl_args.prefix.is_none() =>
+                {
+                    let prefix = expect_str(&name_value.value, "prefix");
+                    validate_symbol(&prefix);
+                    impl_args.prefix = Some(prefix);
+                }
+                _ => abort!(
+                    meta.span(),
+                    "unknown or repeated byond_impl argument `{}`", quote!(#meta);
+                    help = "valid arguments are: `handle`, `prefix = \"...\"`"
+                ),
+            }
+        }
+        impl_args
+    }
+}
+
+pub(crate) fn byond_impl2(args: TokenStream, input: TokenStream) -> TokenStream {
+    let ImplArgs {
+        receiver_from_handle,
+        prefix,
+    } = ImplArgs::parse(args);
+    let mut item_impl: ItemImpl = syn::parse2(input).unwrap_or_else(|err| {
+        abort!(
+            err.span(),
+            "byond_impl can only be applied to an impl block"
+        )
+    });
+    if let Some((_, path, _)) = &item_impl.trait_ {
+        abort!(
+            path.span(),
+            "byond_impl can only be applied to inherent impl blocks"
+        );
+    }
+    if !item_impl.generics.params.is_empty() {
+        abort!(
+            item_impl.generics.span(),
+            "byond_impl can't be applied to generic impl blocks"
+        );
+    }
+    let self_ty = item_impl.self_ty.clone();
+    let type_ident = type_ident(&self_ty);
+
+    let mut shims = Vec::new();
+    for item in &mut item_impl.items {
+        let ImplItem::Fn(method) = item else {
+            continue;
+        };
+        let Some(position) = method
+            .attrs
+            .iter()
+            .position(|attr| is_attr_named(attr, "byond_fn"))
+        else {
+            continue;
+        };
+        // the attribute is consumed here, as byond_fn itself can't expand inside an impl block
+        let attr = method.attrs.remove(position);
+        let mut proc_args = match &attr.meta {
+            Meta::Path(_) => ByondFnAttr::default(),
+            Meta::List(list) => ByondFnAttr::parse(list.tokens.clone()),
+            Meta::NameValue(name_value) => abort!(
+                name_value.span(),
+                "byond_fn arguments must be passed as a list, e.g. `#[byond_fn(name = \"...\")]`"
+            ),
+        };
+        if proc_args.prefix.is_none() {
+            proc_args.prefix.clone_from(&prefix);
+        }
+
+        let mut sig = method.sig.clone();
+        strip_args_markers(&mut method.sig);
+        let callee = match sig.inputs.first() {
+            Some(FnArg::Receiver(receiver)) => {
+                let Type::Reference(reference) = &*receiver.ty else {
+                    abort!(
+                        receiver.span(),
+                        "byond_impl methods can only take `&self` or `&mut self`";
+                        help = "the receiver is the global instance registered with `byond_fn::instance::set_instance`, \
+                            or a handle with `#[byond_impl(handle)]`"
+                    );
+                };
+                let mutability = reference.mutability;
+                let inputs = sig.inputs.into_iter().skip(1);
+                if receiver_from_handle {
+                    // the receiver is passed from BYOND as a leading handle argument
+                    let handle_arg: FnArg = syn::parse_quote! {
+                        this: &#mutability byond_fn::handle::Handle<#self_ty>
+                    };
+                    sig.inputs = std::iter::once(handle_arg).chain(inputs).collect();
+                    Callee::Associated(self_ty.clone())
+                } else {
+                    sig.inputs = inputs.collect();
+                    Callee::Method(self_ty.clone())
+                }
+            }
+            _ => Callee::Associated(self_ty.clone()),
+        };
+        validate_inputs(&sig);
+
+        let ident = &sig.ident;
+        if receiver_from_handle && ident == "free" {
+            abort!(
+                ident.span(),
+                "`free` is already exported for `#[byond_impl(handle)]` blocks, to free a handle"
+            );
+        }
+        let base_name = format_ident!("{type_ident}_{ident}");
+        let export_name = proc_args
+            .export_name(&base_name)
+            .unwrap_or_else(|| base_name.to_string());
+        let mangled_name = format_ident!("__byond_impl_{}_{ident}", snake_case(&type_ident));
+        shims.push(shim_tokens(
+            &proc_args,
+            &sig,
+            &callee,
+            &mangled_name,
+            Some(export_name),
+        ));
+    }
+
+    let free = receiver_from_handle.then(|| free_tokens(&self_ty, &type_ident, prefix));
+
+    quote! {
+        #item_impl
+        #(#shims)*
+        #free
+    }
+}
+
+/// Generates the `<Type>_free` export, which frees a handle to the type. Its name is prefixed the
+/// same way as the block's methods.
+fn free_tokens(self_ty: &Type, type_ident: &Ident, prefix: Option<LitStr>) -> TokenStream {
+    let base_name = format_ident!("{type_ident}_free");
+    let export_name = ByondFnAttr {
+        prefix,
+        ..ByondFnAttr::default()
+    }
+    .export_name(&base_name)
+    .unwrap_or_else(|| base_name.to_string());
+    let free_fn = format_ident!("__byond_impl_{}_free", snake_case(type_ident));
+    quote! {
+        #[doc(hidden)]
+        #[byond_fn::byond_fn(name = #export_name)]
+        fn #free_fn(handle: &str) -> Result<(), byond_fn::str_ffi::FFIError> {
+            byond_fn::handle::free_typed::<#self_ty>(handle, "handle")
+        }
+    }
+}
+
+fn type_ident(ty: &Type) -> Ident {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            path.path.segments.last().unwrap().ident.clone()
+        }
+        _ => abort!(
+            ty.span(),
+            "byond_impl can only be applied to impl blocks of named types"
+        ),
+    }
+}
+
+fn snake_case(ident: &Ident) -> String {
+    let mut snake = String::new();
+    for (idx, char) in ident.to_string().chars().enumerate() {
+        if char.is_uppercase() {
+            if idx != 0 {
+                snake.push('_');
+            }
+            snake.extend(char.to_lowercase());
+        } else {
+            snake.push(char);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod test {
+    use proc_macro2::Span;
+
+    use super::*;
+
+    #[test]
+    fn snake_cases_type_names() {
+        let ident = Ident::new("RegexCache", Span::call_site());
+        assert_eq!(snake_case(&ident), "regex_cache");
+
+        let ident = Ident::new("parser", Span::call_site());
+        assert_eq!(snake_case(&ident), "parser");
+    }
+
+    #[test]
+    fn handle_receivers_take_leading_arg() {
+        let expanded = byond_impl2(
+            quote! { handle },
+            quote! {
+                impl Counter {
+                    #[byond_fn]
+                    pub fn add(&mut self, by: u32) {
+                        self.count += by;
+                    }
+                }
+            },
+        )
+        .to_string();
+        assert!(expanded
+            .contains(&quote!(<super::Counter>::add(&mut *__byond_fn_guard_0, by)).to_string()));
+        assert!(!expanded.contains("with_instance"));
+        assert!(
+            expanded.contains(&quote!(#[byond_fn::byond_fn(name = "Counter_free")]).to_string())
+        );
+        assert!(expanded.contains(&quote!(free_typed::<Counter>(handle, "handle")).to_string()));
+
+        // inside `#[byond_prefix("lib_")]`
+        let expanded = byond_impl2(
+            quote! { handle, prefix = "lib_" },
+            quote! {
+                impl Counter {
+                    #[byond_fn]
+                    pub fn add(&mut self, by: u32) {
+                        self.count += by;
+                    }
+
+                    #[byond_fn(prefix = "other_")]
+                    pub fn get(&self) -> u32 {
+                        self.count
+                    }
+                }
+            },
+        )
+        .to_string();
+        assert!(expanded.contains(&quote!(#[export_name = "lib_Counter_add"]).to_string()));
+        assert!(expanded.contains(&quote!(#[export_name = "other_Counter_get"]).to_string()));
+        assert!(expanded
+            .contains(&quote!(#[byond_fn::byond_fn(name = "lib_Counter_free")]).to_string()));
+    }
+
+    #[test]
+    fn consumes_byond_fn_attrs() {
+        let expanded = byond_impl2(
+            TokenStream::new(),
+            quote! {
+                impl Counter {
+                    #[byond_fn]
+                    pub fn get(&self) -> u32 {
+                        self.count
+                    }
+
+                    pub fn not_exported(&self) {}
+                }
+            },
+        )
+        .to_string();
+        assert!(!expanded.contains(&quote!(#[byond_fn]).to_string()));
+        assert!(expanded.contains(&quote!(#[export_name = "Counter_get"]).to_string()));
+        assert!(expanded.contains("mod __byond_impl_counter_get"));
+        assert!(!expanded.contains("__byond_impl_counter_not_exported"));
+    }
+}
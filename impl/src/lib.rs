@@ -4,12 +4,17 @@ use proc_macro::TokenStream;
 
 use proc_macro2::{Ident, TokenStream as TokenStream2};
 use proc_macro_error::{abort, proc_macro_error};
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::spanned::Spanned;
-use syn::{FnArg, ItemFn, Signature, Type};
+use syn::{FnArg, ImplItem, Item, ItemFn, ItemMod, LitStr, Signature, Type};
 
+use crate::attr::{ByondFnAttr, Transport};
+
+mod attr;
+mod derive_args;
 #[cfg(feature = "ffi_v2")]
 mod ffi_v2;
+mod impl_block;
 mod str_ffi;
 
 pub(crate) struct FFITokens {
@@ -18,16 +23,80 @@ pub(crate) struct FFITokens {
     fn_body: TokenStream2,
 }
 
+fn type_is_named(ty: &Type, name: &str) -> bool {
+    match ty {
+        Type::Path(path) => path.path.segments.last().unwrap().ident == name,
+        _ => false,
+    }
+}
+
+fn is_type_named(arg: &FnArg, name: &str) -> bool {
+    match arg {
+        FnArg::Receiver(_) => abort!(arg.span(), "byond_fn can't have self argument"),
+        FnArg::Typed(arg) => type_is_named(&arg.ty, name),
+    }
+}
+
 fn is_option_type(arg: &FnArg) -> bool {
+    is_type_named(arg, "Option")
+}
+
+fn is_rest_type(arg: &FnArg) -> bool {
+    is_type_named(arg, "Rest")
+}
+
+/// If `arg` is marked `#[byond_args]`, which takes every argument as a `ByondArgs` struct
+fn is_args_struct(arg: &FnArg) -> bool {
+    match arg {
+        FnArg::Receiver(_) => false,
+        FnArg::Typed(arg) => arg
+            .attrs
+            .iter()
+            .any(|attr| is_attr_named(attr, "byond_args")),
+    }
+}
+
+/// Removes `#[byond_args]` markers, which aren't real attributes, from the parameters of `sig`
+fn strip_args_markers(sig: &mut Signature) {
+    for arg in &mut sig.inputs {
+        if let FnArg::Typed(arg) = arg {
+            arg.attrs.retain(|attr| !is_attr_named(attr, "byond_args"));
+        }
+    }
+}
+
+/// If `arg` is a `&[u8]`, which is passed the raw bytes of the argument
+fn is_raw_bytes_type(arg: &FnArg) -> bool {
     match arg {
         FnArg::Receiver(_) => abort!(arg.span(), "byond_fn can't have self argument"),
         FnArg::Typed(arg) => match *arg.ty {
-            Type::Path(ref path) => path.path.segments.last().unwrap().ident == "Option",
+            Type::Reference(ref reference) => match *reference.elem {
+                Type::Slice(ref slice) => {
+                    matches!(*slice.elem, Type::Path(ref path) if path.path.is_ident("u8"))
+                }
+                _ => false,
+            },
             _ => false,
         },
     }
 }
 
+/// If `arg` is a `&Handle<T>` or `&mut Handle<T>`, returns whether it's a mutable reference
+fn handle_ref_mutability(arg: &FnArg) -> Option<bool> {
+    match arg {
+        FnArg::Receiver(_) => abort!(arg.span(), "byond_fn can't have self argument"),
+        FnArg::Typed(arg) => match *arg.ty {
+            Type::Reference(ref reference) => match *reference.elem {
+                Type::Path(ref path) if path.path.segments.last().unwrap().ident == "Handle" => {
+                    Some(reference.mutability.is_some())
+                }
+                _ => None,
+            },
+            _ => None,
+        },
+    }
+}
+
 #[proc_macro_error]
 #[proc_macro_attribute]
 pub fn byond_fn(args: TokenStream, input: TokenStream) -> TokenStream {
@@ -39,18 +108,81 @@ const FFI_V2_DESC: &str =
     "\"v2\": New FFI Format added with BYOND 515 that uses `ByondType` as the FFI medium";
 
 fn byond_fn2(proc_args: TokenStream2, input: TokenStream2) -> TokenStream2 {
-    let original_fn: ItemFn = syn::parse2(input).unwrap();
+    let mut original_fn: ItemFn = syn::parse2(input).unwrap();
+    let marked_sig = original_fn.sig.clone();
+    strip_args_markers(&mut original_fn.sig);
+
+    let proc_args = ByondFnAttr::parse(proc_args);
+
+    let sig = &marked_sig;
+    let ident = &sig.ident;
+
+    validate_inputs(sig);
+
+    let mangled_name = Ident::new(format!("__byond_fn_{ident}").as_str(), ident.span());
+    let shim = shim_tokens(
+        &proc_args,
+        sig,
+        &Callee::Free,
+        &mangled_name,
+        proc_args.export_name(ident),
+    );
+
+    quote! {
+        #original_fn
+        #shim
+    }
+}
 
-    let proc_args: Ident =
-        syn::parse2(proc_args.clone()).unwrap_or(Ident::new("default", proc_args.span()));
+/// What the generated shim calls into, relative to the module the shim is generated in
+pub(crate) enum Callee {
+    /// A free function, called as `super::ident(args)`
+    Free,
+    /// An associated function without a receiver, called as `super::Type::ident(args)`
+    Associated(Box<Type>),
+    /// A method, called on the global instance of its type registered in `byond_fn::instance`
+    Method(Box<Type>),
+}
 
-    let sig = &original_fn.sig;
+impl Callee {
+    /// An expression calling the target with `args`, that evaluates to a `StrReturn`
+    pub(crate) fn call_tokens(&self, ident: &Ident, args: &[TokenStream2]) -> TokenStream2 {
+        match self {
+            Callee::Free => quote! { super::#ident(#(#args),*) },
+            Callee::Associated(ty) => quote! { <super::#ty>::#ident(#(#args),*) },
+            Callee::Method(ty) => quote! {
+                byond_fn::instance::with_instance(|this: &mut super::#ty| this.#ident(#(#args),*))
+            },
+        }
+    }
+}
 
-    let Signature { ident, inputs, .. } = &sig;
+/// Verifies the arguments of a signature (with any receiver already removed) can be mapped to BYOND arguments
+fn validate_inputs(sig: &Signature) {
+    let inputs = &sig.inputs;
+
+    //verify an args struct is the only param, as it takes every argument
+    if let Some(arg) = inputs.iter().find(|arg| is_args_struct(arg)) {
+        if inputs.len() > 1 {
+            abort!(
+                arg.span(),
+                "a `#[byond_args]` parameter must be the only parameter of the function";
+                help = "move the other parameters into the `ByondArgs` struct"
+            );
+        }
+    }
+
+    //verify a rest param can only be the very last one
+    if let Some(arg) = inputs.iter().rev().skip(1).find(|arg| is_rest_type(arg)) {
+        abort!(
+            arg.span(),
+            "Rest arguments must be the last argument of the function signature"
+        );
+    }
 
     //verify optional params are at the tail of the sig
     let mut optional_encountered = false;
-    for arg in inputs.iter() {
+    for arg in inputs.iter().filter(|arg| !is_rest_type(arg)) {
         if optional_encountered && !is_option_type(arg) {
             abort!(
                 arg.span(),
@@ -60,26 +192,316 @@ fn byond_fn2(proc_args: TokenStream2, input: TokenStream2) -> TokenStream2 {
             optional_encountered = is_option_type(arg);
         }
     }
+}
 
-    let mangled_name = Ident::new(
-        format!("__byond_fn_{}", ident.to_string()).as_str(),
-        ident.span(),
-    );
+/// Generates the module holding the exported extern function for `sig`
+fn shim_tokens(
+    proc_args: &ByondFnAttr,
+    sig: &Signature,
+    callee: &Callee,
+    mangled_name: &Ident,
+    export_name: Option<String>,
+) -> TokenStream2 {
+    let ident = &sig.ident;
 
     let FFITokens {
         fn_args,
         return_type,
         fn_body,
-    } = str_ffi::tokens(sig);
+    } = match proc_args.transport {
+        Transport::Str => str_ffi::tokens(sig, callee, proc_args),
+        #[cfg(feature = "ffi_v2")]
+        Transport::V2 => ffi_v2::tokens(sig, callee, proc_args),
+        #[cfg(not(feature = "ffi_v2"))]
+        Transport::V2 => unreachable!("the v2 transport is rejected without the ffi_v2 feature"),
+    };
+
+    let symbol = export_name.clone().unwrap_or_else(|| ident.to_string());
+    let forward = forward_builtin_tokens(proc_args, &symbol);
+    let main_thread = main_thread_tokens();
+    let metrics = metrics_tokens(&symbol);
+    let span = span_start_tokens(&symbol, &format_ident!("__byond_fn_span"));
+    let export_attr = match export_name {
+        Some(export_name) => quote! { #[export_name = #export_name] },
+        None => quote! { #[no_mangle] },
+    };
+
+    // the fuzz entry point passes strings, and recordings hold them, so only str shims get them
+    let (fuzz, fn_body) = match proc_args.transport {
+        Transport::Str => (
+            fuzz_tokens(proc_args, mangled_name, ident),
+            record_tokens(&symbol, fn_body),
+        ),
+        Transport::V2 => (quote! {}, fn_body),
+    };
+
+    let fallback = proc_args.fallback.map(|transport| {
+        let fallback_args = ByondFnAttr {
+            transport,
+            fallback: None,
+            min_version: None,
+            runtime_errors: false,
+            ..proc_args.clone()
+        };
+        shim_tokens(
+            &fallback_args,
+            sig,
+            callee,
+            &format_ident!("{}_{}", mangled_name, transport.suffix()),
+            Some(format!("{symbol}_{}", transport.suffix())),
+        )
+    });
+
+    // runtime errors unwind out of the shim back into BYOND
+    let abi = if proc_args.runtime_errors {
+        quote! { "C-unwind" }
+    } else {
+        quote! { "C" }
+    };
 
     quote! {
-        #original_fn
+        #fuzz
         mod #mangled_name {
-            #[no_mangle]
-            pub unsafe extern "C" fn #ident(#fn_args) -> #return_type {
+            #export_attr
+            pub unsafe extern #abi fn #ident(#fn_args) -> #return_type {
+                #forward
+                #main_thread
+                #metrics
+                #span
                 #fn_body
             }
         }
+        #fallback
+    }
+}
+
+/// Returns an `UNSUPPORTED_HOST` error through `try_tokens` if the host is older than the
+/// function's `min_version`
+fn version_check_tokens(
+    proc_args: &ByondFnAttr,
+    try_tokens: impl Fn(TokenStream2) -> TokenStream2,
+) -> TokenStream2 {
+    match proc_args.min_version {
+        Some((major, build)) => {
+            let check = try_tokens(quote! {
+                byond_fn::host::require(byond_fn::host::ByondVersion::new(#major, #build))
+            });
+            quote! { #check; }
+        }
+        None => quote! {},
+    }
+}
+
+/// Forwards a call to one of byond_fn's own functions to the loaded library, when this is a hot
+/// reload loader. Only generated inside byond_fn, so the `hot_reload` feature is byond_fn's own
+fn forward_builtin_tokens(proc_args: &ByondFnAttr, symbol: &str) -> TokenStream2 {
+    if !proc_args.builtin || proc_args.transport != Transport::Str {
+        return quote! {};
+    }
+    let symbol = format!("{symbol}\0");
+    quote! {
+        #[cfg(feature = "hot_reload")]
+        if let Some(returned) = byond_fn::reload::forward_builtin(#symbol.as_bytes(), argc, argv) {
+            return returned;
+        }
+    }
+}
+
+/// Records the thread BYOND calls the shim on as the main thread, which byondapi is limited to
+#[cfg(feature = "ffi_v2")]
+fn main_thread_tokens() -> TokenStream2 {
+    quote! {
+        byond_fn::ffi_v2::api::enter_main_thread();
+    }
+}
+
+#[cfg(not(feature = "ffi_v2"))]
+fn main_thread_tokens() -> TokenStream2 {
+    quote! {}
+}
+
+/// Times the call and records it into the function's metrics, once the shim returns
+#[cfg(feature = "metrics")]
+fn metrics_tokens(stats_name: &str) -> TokenStream2 {
+    quote! {
+        static __BYOND_FN_STATS: byond_fn::metrics::FnStats = byond_fn::metrics::FnStats::new(#stats_name);
+        let __byond_fn_call = byond_fn::metrics::CallGuard::start(&__BYOND_FN_STATS);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+fn metrics_tokens(_stats_name: &str) -> TokenStream2 {
+    quote! {}
+}
+
+/// Runs `fn_body` and records the call, once it returns
+#[cfg(feature = "recording")]
+fn record_tokens(symbol: &str, fn_body: TokenStream2) -> TokenStream2 {
+    quote! {
+        let __byond_fn_recorder = byond_fn::recording::Recorder::start(#symbol, argc, argv);
+        // a closure, so the body's early returns are recorded too
+        #[allow(clippy::redundant_closure_call)]
+        let __byond_fn_returned = (|| { #fn_body })();
+        __byond_fn_recorder.finish(__byond_fn_returned)
+    }
+}
+
+#[cfg(not(feature = "recording"))]
+fn record_tokens(_symbol: &str, fn_body: TokenStream2) -> TokenStream2 {
+    fn_body
+}
+
+/// A fuzz entry point that calls the generated function with arbitrary arguments
+#[cfg(feature = "fuzzing")]
+fn fuzz_tokens(proc_args: &ByondFnAttr, mangled_name: &Ident, ident: &Ident) -> TokenStream2 {
+    let fuzz_ident = format_ident!("{}_fuzz", mangled_name);
+    let encoding = match &proc_args.encoding {
+        Some(variant) => quote! { byond_fn::str_ffi::encoding::Encoding::#variant },
+        None => quote! { byond_fn::str_ffi::encoding::default_encoding() },
+    };
+    quote! {
+        #[doc(hidden)]
+        #[allow(dead_code)]
+        pub fn #fuzz_ident(data: &[u8]) {
+            byond_fn::fuzz::call_shim(#mangled_name::#ident, data, #encoding);
+        }
+    }
+}
+
+#[cfg(not(feature = "fuzzing"))]
+fn fuzz_tokens(_proc_args: &ByondFnAttr, _mangled_name: &Ident, _ident: &Ident) -> TokenStream2 {
+    quote! {}
+}
+
+/// Starts a profiling span named `name`, which ends once `binding` is dropped
+#[cfg(feature = "profiling")]
+fn span_start_tokens(name: &str, binding: &Ident) -> TokenStream2 {
+    quote! { let #binding = byond_fn::profiling::span(#name); }
+}
+
+#[cfg(not(feature = "profiling"))]
+fn span_start_tokens(_name: &str, _binding: &Ident) -> TokenStream2 {
+    quote! {}
+}
+
+/// Ends a profiling span started with `span_start_tokens` before the end of its scope
+#[cfg(feature = "profiling")]
+fn span_end_tokens(binding: &Ident) -> TokenStream2 {
+    quote! { drop(#binding); }
+}
+
+#[cfg(not(feature = "profiling"))]
+fn span_end_tokens(_binding: &Ident) -> TokenStream2 {
+    quote! {}
+}
+
+/// Derives `byond_fn::str_ffi::ByondArgs`, mapping the fields of a struct to consecutive arguments.
+///
+/// `Option` fields must come last, and are optional arguments. A `Rest` field can be the very last
+/// field. The struct is taken as the single parameter of a `#[byond_fn]` marked `#[byond_args]`.
+#[proc_macro_error]
+#[proc_macro_derive(ByondArgs)]
+pub fn derive_byond_args(input: TokenStream) -> TokenStream {
+    derive_args::derive_byond_args2(input.into()).into()
+}
+
+/// Exports the associated functions of an inherent impl block that are marked with `#[byond_fn]`.
+///
+/// Exported names are prefixed with the type name, e.g. `Counter_increment`.
+/// Methods taking `&self` or `&mut self` are called on the global instance registered with
+/// `byond_fn::instance::set_instance`. With `#[byond_impl(handle)]`, they instead take a handle to
+/// the instance as their first argument from BYOND (see `byond_fn::handle`).
+///
+/// `prefix = "..."` prefixes every export of the block, including the `<Type>_free` export of a
+/// handle block. Inside a `#[byond_prefix]` module it's set to the module's prefix.
+#[proc_macro_error]
+#[proc_macro_attribute]
+pub fn byond_impl(args: TokenStream, input: TokenStream) -> TokenStream {
+    impl_block::byond_impl2(args.into(), input.into()).into()
+}
+
+/// Applies an export name prefix to every `#[byond_fn]` inside an inline module.
+///
+/// Nested modules are included, unless they have their own `#[byond_prefix]`.
+/// Functions with an explicit `name` are exported under that name as-is. To prefix every export
+/// of a crate, set `BYOND_FN_PREFIX` from its build script instead, see the byond_fn docs.
+#[proc_macro_error]
+#[proc_macro_attribute]
+pub fn byond_prefix(args: TokenStream, input: TokenStream) -> TokenStream {
+    byond_prefix2(args.into(), input.into()).into()
+}
+
+fn byond_prefix2(args: TokenStream2, input: TokenStream2) -> TokenStream2 {
+    let prefix: LitStr = syn::parse2(args).unwrap_or_else(|err| {
+        abort!(err.span(), "byond_prefix expects a single string literal"; help = "e.g. `#[byond_prefix(\"mylib_\")]`")
+    });
+    let mut module: ItemMod = syn::parse2(input)
+        .unwrap_or_else(|err| abort!(err.span(), "byond_prefix can only be applied to a module"));
+    if module.content.is_none() {
+        abort!(
+            module.span(),
+            "byond_prefix can only be applied to an inline module"
+        );
+    }
+    apply_prefix(&mut module, &prefix);
+    quote! { #module }
+}
+
+fn is_attr_named(attr: &syn::Attribute, name: &str) -> bool {
+    attr.path()
+        .segments
+        .last()
+        .is_some_and(|seg| seg.ident == name)
+}
+
+fn has_attr_named(attrs: &[syn::Attribute], name: &str) -> bool {
+    attrs.iter().any(|attr| is_attr_named(attr, name))
+}
+
+fn apply_prefix(module: &mut ItemMod, prefix: &LitStr) {
+    let Some((_, items)) = module.content.as_mut() else {
+        return;
+    };
+    for item in items {
+        match item {
+            Item::Fn(func) => prefix_attrs(&mut func.attrs, "byond_fn", prefix),
+            Item::Impl(item_impl) if has_attr_named(&item_impl.attrs, "byond_impl") => {
+                // for the exports the block generates itself
+                prefix_attrs(&mut item_impl.attrs, "byond_impl", prefix);
+                for impl_item in &mut item_impl.items {
+                    if let ImplItem::Fn(method) = impl_item {
+                        prefix_attrs(&mut method.attrs, "byond_fn", prefix);
+                    }
+                }
+            }
+            Item::Mod(inner) if !has_attr_named(&inner.attrs, "byond_prefix") => {
+                apply_prefix(inner, prefix);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn prefix_attrs(attrs: &mut [syn::Attribute], name: &str, prefix: &LitStr) {
+    for attr in attrs.iter_mut().filter(|attr| is_attr_named(attr, name)) {
+        let path = attr.path().clone();
+        let existing = match &attr.meta {
+            syn::Meta::List(list) => list.tokens.clone(),
+            _ => TokenStream2::new(),
+        };
+        let has_prefix = existing
+            .clone()
+            .into_iter()
+            .any(|tt| matches!(tt, proc_macro2::TokenTree::Ident(ident) if ident == "prefix"));
+        if has_prefix {
+            continue;
+        }
+        let args = if existing.is_empty() {
+            quote! { prefix = #prefix }
+        } else {
+            quote! { #existing, prefix = #prefix }
+        };
+        *attr = syn::parse_quote! { #[#path(#args)] };
     }
 }
 
@@ -97,4 +519,104 @@ mod test {
         let arg: FnArg = syn::parse2(quote! { foo: Option<i32> }).unwrap();
         assert!(is_option_type(&arg));
     }
+
+    #[test]
+    fn is_rest_valid() {
+        let arg: FnArg = syn::parse2(quote! { foo: Vec<i32> }).unwrap();
+        assert!(!is_rest_type(&arg));
+
+        let arg: FnArg = syn::parse2(quote! { foo: byond_fn::str_ffi::Rest<i32> }).unwrap();
+        assert!(is_rest_type(&arg));
+    }
+
+    #[test]
+    fn is_raw_bytes_valid() {
+        let arg: FnArg = syn::parse2(quote! { foo: &str }).unwrap();
+        assert!(!is_raw_bytes_type(&arg));
+
+        let arg: FnArg = syn::parse2(quote! { foo: &[u8] }).unwrap();
+        assert!(is_raw_bytes_type(&arg));
+    }
+
+    #[test]
+    fn handle_ref_valid() {
+        let arg: FnArg = syn::parse2(quote! { foo: Handle<i32> }).unwrap();
+        assert_eq!(handle_ref_mutability(&arg), None);
+
+        let arg: FnArg = syn::parse2(quote! { foo: &str }).unwrap();
+        assert_eq!(handle_ref_mutability(&arg), None);
+
+        let arg: FnArg = syn::parse2(quote! { foo: &Handle<i32> }).unwrap();
+        assert_eq!(handle_ref_mutability(&arg), Some(false));
+
+        let arg: FnArg = syn::parse2(quote! { foo: &mut byond_fn::handle::Handle<i32> }).unwrap();
+        assert_eq!(handle_ref_mutability(&arg), Some(true));
+    }
+
+    #[test]
+    fn prefix_applies_to_nested_fns() {
+        let prefix: LitStr = syn::parse_quote!("lib_");
+        let mut module: ItemMod = syn::parse_quote! {
+            mod exports {
+                #[byond_fn]
+                fn plain() {}
+                #[byond_fn(name = "explicit")]
+                fn named() {}
+                mod nested {
+                    #[byond_fn::byond_fn]
+                    fn nested() {}
+                }
+                #[byond_impl(handle)]
+                impl Counter {
+                    #[byond_fn]
+                    fn add(&mut self) {}
+                }
+                #[byond_prefix("other_")]
+                mod overridden {
+                    #[byond_fn]
+                    fn overridden() {}
+                }
+            }
+        };
+        apply_prefix(&mut module, &prefix);
+        let expanded = quote!(#module).to_string();
+        assert!(expanded.contains(
+            &quote!(
+                #[byond_fn(prefix = "lib_")]
+                fn plain() {}
+            )
+            .to_string()
+        ));
+        assert!(expanded.contains(
+            &quote!(
+                #[byond_fn(name = "explicit", prefix = "lib_")]
+                fn named() {}
+            )
+            .to_string()
+        ));
+        assert!(expanded.contains(
+            &quote!(
+                #[byond_fn::byond_fn(prefix = "lib_")]
+                fn nested() {}
+            )
+            .to_string()
+        ));
+        assert!(expanded.contains(
+            &quote!(
+                #[byond_impl(handle, prefix = "lib_")]
+                impl Counter {
+                    #[byond_fn(prefix = "lib_")]
+                    fn add(&mut self) {}
+                }
+            )
+            .to_string()
+        ));
+        assert!(expanded.contains(
+            &quote!(
+                #[byond_fn]
+                fn overridden() {}
+            )
+            .to_string()
+        ));
+    }
 }
@@ -1,8 +1,12 @@
 use proc_macro2::TokenStream;
-use quote::{quote, ToTokens};
+use quote::{format_ident, quote, ToTokens};
 use syn::{FnArg, Signature};
 
-use crate::{is_option_type, FFITokens};
+use crate::attr::ByondFnAttr;
+use crate::{
+    handle_ref_mutability, is_args_struct, is_option_type, is_raw_bytes_type, is_rest_type,
+    span_end_tokens, span_start_tokens, version_check_tokens, Callee, FFITokens,
+};
 
 fn return_type_token() -> TokenStream {
     quote! { *const ::std::os::raw::c_char }
@@ -12,81 +16,238 @@ fn args_tokens() -> TokenStream {
     quote! { argc: ::std::os::raw::c_int, argv: *const *const ::std::os::raw::c_char }
 }
 
-fn fn_body_tokens(sig: &Signature) -> TokenStream {
+/// Unwraps a `Result<_, FFIError>`, early returning the error to BYOND
+fn try_tokens(expr: TokenStream) -> TokenStream {
+    quote! {
+        match #expr {
+            Ok(arg) => arg,
+            Err(err) => {
+                return byond_fn::str_ffi::byond_return_encoded(err, encoding);
+            },
+        }
+    }
+}
+
+fn fn_body_tokens(sig: &Signature, callee: &Callee, proc_args: &ByondFnAttr) -> TokenStream {
     let Signature { ident, inputs, .. } = sig;
 
-    let min_args = inputs.iter().filter(|arg| !is_option_type(arg)).count();
-    let max_args = inputs.len();
-    let args_binding = inputs.iter().enumerate().map(|(num, arg)| {
-        if let FnArg::Typed(arg) = arg {
+    let has_rest = inputs.last().is_some_and(is_rest_type);
+    let min_args = inputs
+        .iter()
+        .filter(|arg| !is_option_type(arg) && !is_rest_type(arg))
+        .count();
+    let max_args = if has_rest { usize::MAX } else { inputs.len() };
+    let args_binding = inputs.iter().enumerate().map(|(num, fn_arg)| {
+        if let FnArg::Typed(arg) = fn_arg {
             let arg = *arg.pat.clone();
             let arg_string = arg.to_token_stream().to_string();
-            quote! {
-                let #arg = match byond_fn::str_ffi::StrArg::map_arg(args.get(#num).map(|arg| *arg), #min_args, #max_args, #arg_string, #num) {
-                    Ok(arg) => arg,
-                    Err(err) => {
-                        return byond_fn::str_ffi::byond_return(err);
-                    },
+            if is_raw_bytes_type(fn_arg) {
+                let raw = try_tokens(quote! {
+                    byond_fn::str_ffi::encoding::raw_arg(args.get(#num).copied(), #min_args, #max_args, #num)
+                });
+                return quote! { let #arg = #raw; };
+            }
+            let decoded = format_ident!("__byond_fn_arg_{}", num);
+            if is_args_struct(fn_arg) {
+                let decode = try_tokens(quote! { encoding.decode_args(&args) });
+                let args_struct = try_tokens(quote! {
+                    __byond_fn_args_struct.from_args(&#decoded)
+                });
+                // bound to a name of our own, as the parameter may destructure the struct
+                return quote! {
+                    let #decoded = #decode;
+                    let #decoded: Vec<&str> = #decoded.iter().map(AsRef::as_ref).collect();
+                    let __byond_fn_args_struct = #args_struct;
                 };
             }
+            if is_rest_type(fn_arg) {
+                let decode = try_tokens(quote! {
+                    encoding.decode_args(args.get(#num..).unwrap_or_default())
+                });
+                let rest = try_tokens(quote! {
+                    byond_fn::str_ffi::Rest::from_args(&#decoded, #arg_string)
+                });
+                return quote! {
+                    let #decoded = #decode;
+                    let #decoded: Vec<&str> = #decoded.iter().map(AsRef::as_ref).collect();
+                    let #arg = #rest;
+                };
+            }
+            let decode = try_tokens(quote! { encoding.decode_arg(&args, #num) });
+            let map_arg = quote! {
+                byond_fn::str_ffi::StrArg::map_arg(#decoded.as_deref(), #min_args, #max_args, #arg_string, #num)
+            };
+            if let Some(mutable) = handle_ref_mutability(fn_arg) {
+                let handle_ref = format_ident!("__byond_fn_handle_{}", num);
+                let guard = format_ident!("__byond_fn_guard_{}", num);
+                let guard_mut = mutable.then(|| quote! { mut });
+                let map_arg = try_tokens(map_arg);
+                let lock = try_tokens(quote! { #handle_ref.lock(#arg_string) });
+                return quote! {
+                    let #decoded = #decode;
+                    let #handle_ref: byond_fn::handle::HandleRef<_> = #map_arg;
+                    let #guard_mut #guard = #lock;
+                };
+            }
+            let map_arg = try_tokens(map_arg);
+            quote! {
+                let #decoded = #decode;
+                let #arg = #map_arg;
+            }
         } else {
             panic!("Byond functions can't have self argument")
         }
     });
 
-    let return_args = inputs.iter().map(|arg| {
-        if let FnArg::Typed(arg) = arg {
-            let pat = *arg.pat.clone();
-            quote! { #pat }
-        } else {
-            panic!("Byond functions can't have self argument")
+    let return_args: Vec<_> = inputs
+        .iter()
+        .enumerate()
+        .map(|(num, fn_arg)| {
+            if let Some(mutable) = handle_ref_mutability(fn_arg) {
+                let guard = format_ident!("__byond_fn_guard_{}", num);
+                return if mutable {
+                    quote! { &mut *#guard }
+                } else {
+                    quote! { &*#guard }
+                };
+            }
+            if is_args_struct(fn_arg) {
+                return quote! { __byond_fn_args_struct };
+            }
+            if let FnArg::Typed(arg) = fn_arg {
+                let pat = *arg.pat.clone();
+                quote! { #pat }
+            } else {
+                panic!("Byond functions can't have self argument")
+            }
+        })
+        .collect();
+    let call = callee.call_tokens(ident, &return_args);
+    let call_span = format_ident!("__byond_fn_span_call");
+    let call_span_start = span_start_tokens("call", &call_span);
+    let call = if call_span_start.is_empty() {
+        call
+    } else {
+        quote! {{
+            #call_span_start
+            #call
+        }}
+    };
+    // the policy is applied here, once, before the value is encoded and chunked
+    let nul_policy = match &proc_args.nul {
+        Some(policy) => quote! { byond_fn::str_ffi::binary::NulPolicy::#policy },
+        None => quote! { byond_fn::str_ffi::binary::nul_policy() },
+    };
+    let call = quote! {
+        byond_fn::str_ffi::binary::NulChecked::new(#call, #nul_policy)
+    };
+    let call = match &proc_args.chunked {
+        Some(chunk_size) => {
+            let chunk_size = chunk_size
+                .as_ref()
+                .map(|size| quote! { #size })
+                .unwrap_or_else(|| quote! { byond_fn::str_ffi::chunked::DEFAULT_CHUNK_SIZE });
+            // encoded before chunking, so chunks can be passed back as-is
+            quote! {
+                byond_fn::str_ffi::chunked::Chunked::new(
+                    byond_fn::str_ffi::encoding::Encoded::new(#call, encoding),
+                    #chunk_size,
+                )
+            }
         }
-    });
+        None => call,
+    };
+
+    let encoding = match &proc_args.encoding {
+        Some(variant) => quote! { byond_fn::str_ffi::encoding::Encoding::#variant },
+        None => quote! { byond_fn::str_ffi::encoding::default_encoding() },
+    };
 
     let min_args_i32 = min_args as i32;
     let max_args_i32 = max_args as i32;
 
-    let actual_check = if min_args == max_args {
+    let actual_check = if has_rest {
+        quote! { argc < #min_args_i32 }
+    } else if min_args == max_args {
         quote! { argc != #min_args_i32 }
     } else {
-        quote! { argc < #min_args_i32 || argc > #max_args_i32 }
+        quote! { !(#min_args_i32..=#max_args_i32).contains(&argc) }
     };
 
     let range_check = quote! {
         if #actual_check {
-            return byond_fn::str_ffi::byond_return(byond_fn::str_ffi::TransportError::WrongArgCount {
+            return byond_fn::str_ffi::byond_return_encoded(byond_fn::str_ffi::TransportError::WrongArgCount {
                 expected_min: #min_args,
                 expected_max: #max_args,
                 got: argc as usize,
-            });
+            }, encoding);
         }
     };
 
+    let args_span = format_ident!("__byond_fn_span_args");
+    let args_span_start = span_start_tokens("args", &args_span);
+    let args_span_end = span_end_tokens(&args_span);
+    // an args struct's range is only known from its `ByondArgs` impl, but is still checked before
+    // anything is decoded
+    let range_check = if inputs.iter().any(is_args_struct) {
+        let check = try_tokens(quote! {
+            __byond_fn_args_struct.check_count(argc as usize)
+        });
+        quote! {
+            let __byond_fn_args_struct = byond_fn::str_ffi::ArgsStruct::new();
+            #check;
+        }
+    } else {
+        range_check
+    };
+    let arg_names = inputs.iter().map(|fn_arg| match fn_arg {
+        FnArg::Typed(arg) => arg.pat.to_token_stream().to_string(),
+        FnArg::Receiver(_) => panic!("Byond functions can't have self argument"),
+    });
+    let limits = proc_args.limits.iter().map(|(field, limit)| {
+        quote! { #field: Some(#limit), }
+    });
+    let limits_scope = try_tokens(quote! {
+        byond_fn::str_ffi::limits::enter_call(
+            byond_fn::str_ffi::limits::ArgLimits {
+                #(#limits)*
+                ..byond_fn::str_ffi::limits::default_limits()
+            },
+            &args,
+            &[#(#arg_names),*],
+        )
+    });
     let arg_stuff = if !inputs.is_empty() {
         quote! {
+            #args_span_start
             #range_check
-            let args = match byond_fn::str_ffi::parse_str_args(argc, argv) {
-                Ok(args) => args,
-                Err(err) => {
-                    return byond_fn::str_ffi::byond_return(err);
-                },
-            };
+            let args = byond_fn::str_ffi::parse_raw_args(argc, argv);
+            // the limits are checked before anything is decoded, and last while args are parsed
+            let __byond_fn_limits = #limits_scope;
             #(#args_binding)*
+            drop(__byond_fn_limits);
+            #args_span_end
         }
     } else {
         quote! {}
     };
+    let return_span_start = span_start_tokens("return", &format_ident!("__byond_fn_span_return"));
+    let version_check = version_check_tokens(proc_args, try_tokens);
 
     quote! {
+        let encoding = #encoding;
+        #version_check
         #arg_stuff
-        byond_fn::str_ffi::byond_return(super::#ident(#(#return_args),*))
+        let __byond_fn_ret = #call;
+        #return_span_start
+        byond_fn::str_ffi::byond_return_encoded(__byond_fn_ret, encoding)
     }
 }
 
-pub(crate) fn tokens(sig: &Signature) -> FFITokens {
+pub(crate) fn tokens(sig: &Signature, callee: &Callee, proc_args: &ByondFnAttr) -> FFITokens {
     FFITokens {
         fn_args: args_tokens(),
         return_type: return_type_token(),
-        fn_body: fn_body_tokens(sig),
+        fn_body: fn_body_tokens(sig, callee, proc_args),
     }
 }
@@ -0,0 +1,308 @@
+//! Replays calls recorded with the `recording` feature of byond_fn against a library, and reports
+//! the calls that returned something different than when they were recorded.
+//!
+//! ```text
+//! byond_fn_replay <library> <recording>...
+//! ```
+//!
+//! Rotated recordings are replayed in the order they are given, so the oldest should come first.
+//! Calls are made from a single thread, in the order they were recorded, so functions that depend
+//! on state kept between calls see the same calls they did when recorded. Exits with 1 if any call
+//! returned something different, and 2 if the library or a recording couldn't be read.
+//!
+//! The transfer ids in the headers of chunked results are counted up per process, so they aren't
+//! compared. Instead, the recorded id is mapped to the replayed one, and recorded calls fetching or
+//! cancelling the transfer are made with the replayed id.
+
+use std::collections::HashMap;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use byond_fn::recording::{read_records, CallRecord};
+use byond_fn::str_ffi::chunked::HEADER;
+use libloading::Library;
+
+type StrShim = unsafe extern "C" fn(c_int, *const *const c_char) -> *const c_char;
+
+/// The exports that take a transfer id as their first argument
+const TRANSFER_EXPORTS: [&str; 2] = ["byond_fn_fetch_chunk", "byond_fn_cancel_transfer"];
+
+/// The outcome of replaying a single call
+#[derive(Debug, PartialEq, Eq)]
+enum Replayed {
+    Matched,
+    Differed {
+        returned: Vec<u8>,
+    },
+    /// The library doesn't export the function
+    Skipped,
+}
+
+#[derive(Default)]
+struct Summary {
+    matched: usize,
+    differed: usize,
+    skipped: usize,
+    recorded_time: Duration,
+    replayed_time: Duration,
+}
+
+/// Recorded transfer ids of chunked results, mapped to the ids they were replayed as
+#[derive(Default)]
+struct Transfers(HashMap<Vec<u8>, Vec<u8>>);
+
+impl Transfers {
+    /// The arguments to replay `record` with, with a recorded transfer id replaced
+    fn args(&self, record: &CallRecord) -> Vec<Vec<u8>> {
+        let mut args = record.args.clone();
+        if TRANSFER_EXPORTS.contains(&record.name.as_str()) {
+            if let Some(id) = args.first_mut() {
+                if let Some(replayed) = self.0.get(id) {
+                    id.clone_from(replayed);
+                }
+            }
+        }
+        args
+    }
+
+    /// Whether `returned` matches what was recorded, which for chunked results is the chunk count
+    fn matches(&mut self, recorded: &[u8], returned: &[u8]) -> bool {
+        match (chunk_header(recorded), chunk_header(returned)) {
+            (Some((recorded_id, recorded_count)), Some((replayed_id, replayed_count))) => {
+                self.0.insert(recorded_id.to_vec(), replayed_id.to_vec());
+                recorded_count == replayed_count
+            }
+            _ => recorded == returned,
+        }
+    }
+}
+
+/// The transfer id and chunk count of a chunked result
+fn chunk_header(returned: &[u8]) -> Option<(&[u8], &[u8])> {
+    let rest = returned.strip_prefix(HEADER.as_bytes())?.strip_prefix(b";")?;
+    let split = rest.iter().position(|&byte| byte == b';')?;
+    Some((&rest[..split], &rest[split + 1..]))
+}
+
+/// Calls `shim` with `args`, returning what it returned.
+fn call(shim: StrShim, args: &[Vec<u8>]) -> Vec<u8> {
+    // the arguments came from C strings, so they can't contain NUL bytes
+    let args: Vec<_> = args
+        .iter()
+        .map(|arg| CString::new(arg.clone()).unwrap_or_default())
+        .collect();
+    let argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+    let argc = c_int::try_from(argv.len()).unwrap_or(c_int::MAX);
+    // SAFETY: exported functions take and return C strings
+    let returned = unsafe { shim(argc, argv.as_ptr()) };
+    if returned.is_null() {
+        return Vec::new();
+    }
+    unsafe { CStr::from_ptr(returned) }.to_bytes().to_vec()
+}
+
+/// Replays `record` through the shim `lookup` finds for it.
+fn replay(
+    record: &CallRecord,
+    lookup: &mut impl FnMut(&str) -> Option<StrShim>,
+    transfers: &mut Transfers,
+    summary: &mut Summary,
+) -> Replayed {
+    let Some(shim) = lookup(&record.name) else {
+        summary.skipped += 1;
+        return Replayed::Skipped;
+    };
+    let args = transfers.args(record);
+    let start = Instant::now();
+    let returned = call(shim, &args);
+    summary.replayed_time += start.elapsed();
+    summary.recorded_time += record.duration;
+    if transfers.matches(&record.returned, &returned) {
+        summary.matched += 1;
+        Replayed::Matched
+    } else {
+        summary.differed += 1;
+        Replayed::Differed { returned }
+    }
+}
+
+fn describe(record: &CallRecord) -> String {
+    let args: Vec<_> = record
+        .args
+        .iter()
+        .map(|arg| format!("{:?}", String::from_utf8_lossy(arg)))
+        .collect();
+    format!("{}({})", record.name, args.join(", "))
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let (Some(library_path), recordings) = (args.next(), args.collect::<Vec<_>>()) else {
+        eprintln!("usage: byond_fn_replay <library> <recording>...");
+        return ExitCode::from(2);
+    };
+    if recordings.is_empty() {
+        eprintln!("usage: byond_fn_replay <library> <recording>...");
+        return ExitCode::from(2);
+    }
+
+    // SAFETY: the library is trusted to be one built with byond_fn
+    let library = match unsafe { Library::new(&library_path) } {
+        Ok(library) => library,
+        Err(err) => {
+            eprintln!("failed to load {library_path}: {err}");
+            return ExitCode::from(2);
+        }
+    };
+    let mut shims: HashMap<String, Option<StrShim>> = HashMap::new();
+    let mut lookup = |name: &str| {
+        *shims.entry(name.to_string()).or_insert_with(|| {
+            // SAFETY: exported functions of the str transport have this signature
+            let shim = unsafe { library.get::<StrShim>(name.as_bytes()) }
+                .ok()
+                .map(|symbol| *symbol);
+            if shim.is_none() {
+                eprintln!("{library_path} doesn't export {name}, skipping its calls");
+            }
+            shim
+        })
+    };
+
+    let mut transfers = Transfers::default();
+    let mut summary = Summary::default();
+    for path in &recordings {
+        let records = match File::open(path).and_then(|file| read_records(BufReader::new(file))) {
+            Ok(records) => records,
+            Err(err) => {
+                eprintln!("failed to read {path}: {err}");
+                return ExitCode::from(2);
+            }
+        };
+        for (index, record) in records.enumerate() {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    eprintln!("{path}: failed to read call {index}, skipping the rest: {err}");
+                    break;
+                }
+            };
+            let replayed = replay(&record, &mut lookup, &mut transfers, &mut summary);
+            if let Replayed::Differed { returned } = replayed {
+                println!("{path}:{index}: {}", describe(&record));
+                println!(
+                    "  recorded: {:?}",
+                    String::from_utf8_lossy(&record.returned)
+                );
+                println!("  replayed: {:?}", String::from_utf8_lossy(&returned));
+            }
+        }
+    }
+
+    println!(
+        "{} matched, {} differed, {} skipped. Took {:?} to replay, {:?} when recorded",
+        summary.matched,
+        summary.differed,
+        summary.skipped,
+        summary.replayed_time,
+        summary.recorded_time,
+    );
+    if summary.differed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use byond_fn::byond_fn;
+
+    use super::*;
+
+    #[byond_fn]
+    fn double(number: i32) -> i32 {
+        number * 2
+    }
+
+    #[byond_fn(chunked = 2)]
+    fn repeat(text: &str, times: usize) -> String {
+        text.repeat(times)
+    }
+
+    extern "C" {
+        fn byond_fn_fetch_chunk(argc: c_int, argv: *const *const c_char) -> *const c_char;
+    }
+
+    fn lookup(name: &str) -> Option<StrShim> {
+        match name {
+            "double" => Some(__byond_fn_double::double),
+            "repeat" => Some(__byond_fn_repeat::repeat),
+            "byond_fn_fetch_chunk" => Some(byond_fn_fetch_chunk),
+            _ => None,
+        }
+    }
+
+    fn record(name: &str, args: &[&str], returned: &str) -> CallRecord {
+        CallRecord {
+            name: name.to_string(),
+            args: args.iter().map(|arg| arg.as_bytes().to_vec()).collect(),
+            returned: returned.as_bytes().to_vec(),
+            started: SystemTime::now(),
+            duration: Duration::ZERO,
+            thread: 1,
+        }
+    }
+
+    #[test]
+    fn reports_differing_calls() {
+        let mut transfers = Transfers::default();
+        let mut summary = Summary::default();
+        let mut replay = |record| replay(&record, &mut lookup, &mut transfers, &mut summary);
+
+        assert_eq!(replay(record("double", &["2"], "4")), Replayed::Matched);
+        let differed = record("double", &["3"], "7");
+        assert_eq!(describe(&differed), "double(\"3\")");
+        assert_eq!(
+            replay(differed),
+            Replayed::Differed {
+                returned: b"6".to_vec()
+            }
+        );
+        assert_eq!(replay(record("triple", &["3"], "9")), Replayed::Skipped);
+
+        assert_eq!(
+            (summary.matched, summary.differed, summary.skipped),
+            (1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn chunked_results_are_fetched_with_the_replayed_id() {
+        let mut transfers = Transfers::default();
+        let mut summary = Summary::default();
+        let mut replay = |record| replay(&record, &mut lookup, &mut transfers, &mut summary);
+
+        // recorded by another process, which was on another transfer
+        let header = format!("{HEADER};1000;2");
+        assert_eq!(
+            replay(record("repeat", &["ab", "2"], &header)),
+            Replayed::Matched
+        );
+        for (index, chunk) in ["ab", "ab"].iter().enumerate() {
+            let fetch = record("byond_fn_fetch_chunk", &["1000", &index.to_string()], chunk);
+            assert_eq!(replay(fetch), Replayed::Matched);
+        }
+
+        let more_chunks = format!("{HEADER};1001;3");
+        assert!(matches!(
+            replay(record("repeat", &["ab", "2"], &more_chunks)),
+            Replayed::Differed { .. }
+        ));
+        assert_eq!((summary.matched, summary.differed), (3, 1));
+    }
+}
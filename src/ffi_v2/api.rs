@@ -0,0 +1,888 @@
+//! Calls into BYOND through byondapi.
+//!
+//! byondapi is exported by the host process (`byondcore.dll`, or `libbyond.so` on Linux). Its
+//! functions are looked up when first needed rather than linked against, so a library still loads
+//! on hosts that are missing some of them, and calling one that's missing returns
+//! [`ApiError::MissingSymbol`].
+//!
+//! byondapi can only be used on BYOND's main thread. Every generated function records the thread
+//! it's first called on as the main thread, and [`api`] returns [`ApiError::NotMainThread`] on any
+//! other, so a background thread has to go through [`thread_sync`](crate::ffi_v2::thread_sync).
+//!
+//! Every call goes through the [`ByondApi`] trait. Outside of BYOND, such as in tests, a
+//! [`MockApi`](mock::MockApi) can stand in for the host with [`with_api`]:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use byond_fn::ffi_v2::api::{self, mock::MockApi};
+//! use byond_fn::ffi_v2::ByondValue;
+//!
+//! let mock = Arc::new(MockApi::new());
+//! api::with_api(mock.clone(), || {
+//!     let mob = mock.new_datum("/mob");
+//!     api::write_var(&mob, "health", &ByondValue::number(100.0)).unwrap();
+//!     let health = api::read_var(&mob, "health").unwrap();
+//!     assert_eq!(health.as_number(), Some(100.0));
+//! });
+//! ```
+
+pub mod mock;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::fmt::{Display, Formatter};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use std::thread::{self, ThreadId};
+
+use libloading::Library;
+
+use crate::ffi_v2::{ByondValue, CByondValue};
+use crate::host::ByondVersion;
+use crate::str_ffi::error_keys;
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// byondapi couldn't be found in the host process
+    NoHost(String),
+    /// The host doesn't export a byondapi function, usually because its BYOND version is too old
+    MissingSymbol(&'static str),
+    /// A byondapi function failed, with the message BYOND gave for it
+    Failed {
+        function: &'static str,
+        message: String,
+    },
+    /// A var or proc name can't be passed to BYOND
+    InvalidName(String),
+    /// A value couldn't be converted to or from the Rust type it was expected as
+    Convert {
+        expected: &'static str,
+        actual: String,
+    },
+    /// A value couldn't be converted through serde, see
+    /// [`byond_serde`](crate::ffi_v2::byond_serde)
+    Serde(String),
+    /// byondapi was used off BYOND's main thread
+    NotMainThread,
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{};", error_keys::CLASS_API)?;
+        match self {
+            Self::NoHost(reason) => write!(
+                f,
+                "{};byondapi is not available in this process: {}",
+                error_keys::API_TYPE_NO_HOST,
+                reason,
+            ),
+            Self::MissingSymbol(symbol) => write!(
+                f,
+                "{};The host doesn't export \"{}\", it may be too old",
+                error_keys::API_TYPE_MISSING_SYMBOL,
+                symbol,
+            ),
+            Self::Failed { function, message } => write!(
+                f,
+                "{};{} failed: {}",
+                error_keys::API_TYPE_FAILED,
+                function,
+                message,
+            ),
+            Self::InvalidName(name) => write!(
+                f,
+                "{};\"{}\" contains a NUL byte",
+                error_keys::API_TYPE_INVALID_NAME,
+                name.escape_debug(),
+            ),
+            Self::Convert { expected, actual } => write!(
+                f,
+                "{};Expected {}, got {}",
+                error_keys::API_TYPE_CONVERT,
+                expected,
+                actual,
+            ),
+            Self::Serde(message) => write!(f, "{};{}", error_keys::API_TYPE_SERDE, message),
+            Self::NotMainThread => write!(
+                f,
+                "{};byondapi can only be used on the main thread, use thread_sync from others",
+                error_keys::API_TYPE_NOT_MAIN_THREAD,
+            ),
+        }
+    }
+}
+
+impl Error for ApiError {}
+
+/// The byondapi functions used by this crate.
+///
+/// [`DynamicApi`] calls the real functions of the host, while [`MockApi`](mock::MockApi)
+/// implements them in Rust. Values passed in and out are only valid on the main thread, during
+/// the current call.
+pub trait ByondApi: Send + Sync {
+    /// The thread the API can be used on, or `None` if it isn't known yet.
+    fn main_thread(&self) -> Option<ThreadId>;
+
+    /// The BYOND version of the host. See [`host`](crate::host).
+    fn version(&self) -> Result<ByondVersion, ApiError>;
+
+    /// Creates a string value.
+    fn create_string(&self, text: &CStr) -> Result<CByondValue, ApiError>;
+
+    /// The text of a value, as DM would convert it with `"[value]"`.
+    fn to_string(&self, value: &CByondValue) -> Result<Vec<u8>, ApiError>;
+
+    fn read_var(&self, src: &CByondValue, name: &CStr) -> Result<CByondValue, ApiError>;
+
+    fn write_var(
+        &self,
+        src: &CByondValue,
+        name: &CStr,
+        value: &CByondValue,
+    ) -> Result<(), ApiError>;
+
+    fn call_proc(
+        &self,
+        src: &CByondValue,
+        name: &CStr,
+        args: &[CByondValue],
+    ) -> Result<CByondValue, ApiError>;
+
+    fn call_global_proc(&self, name: &CStr, args: &[CByondValue]) -> Result<CByondValue, ApiError>;
+
+    fn create_list(&self) -> Result<CByondValue, ApiError>;
+
+    /// The items of a list, without their associated values.
+    fn read_list(&self, list: &CByondValue) -> Result<Vec<CByondValue>, ApiError>;
+
+    /// Replaces the items of a list, clearing associated values.
+    fn write_list(&self, list: &CByondValue, items: &[CByondValue]) -> Result<(), ApiError>;
+
+    /// The items of a list and their associated values, as pairs.
+    fn read_list_assoc(
+        &self,
+        list: &CByondValue,
+    ) -> Result<Vec<(CByondValue, CByondValue)>, ApiError>;
+
+    /// `list[index]`, where `index` is a 1-based number or a key.
+    fn read_list_index(
+        &self,
+        list: &CByondValue,
+        index: &CByondValue,
+    ) -> Result<CByondValue, ApiError>;
+
+    /// `list[index] = value`, where `index` is a 1-based number or a key.
+    fn write_list_index(
+        &self,
+        list: &CByondValue,
+        index: &CByondValue,
+        value: &CByondValue,
+    ) -> Result<(), ApiError>;
+
+    /// Keeps the value alive until a matching [`dec_ref`](Self::dec_ref), even once DM has no
+    /// references to it left.
+    fn inc_ref(&self, value: &CByondValue) -> Result<(), ApiError>;
+
+    fn dec_ref(&self, value: &CByondValue) -> Result<(), ApiError>;
+
+    /// Whether a reference still refers to something that exists.
+    fn test_ref(&self, value: &CByondValue) -> Result<bool, ApiError>;
+
+    /// Runs `job` on the main thread, returning once it has run if `block` is set. See
+    /// [`thread_sync`](crate::ffi_v2::thread_sync).
+    fn thread_sync(&self, job: Job, block: bool) -> Result<(), ApiError>;
+
+    /// Raises a DM runtime error with `message` in the proc that called into Rust, unwinding back
+    /// into BYOND. Only returns if it can't. See [`runtime`](crate::ffi_v2::runtime).
+    fn crash(&self, message: &CStr) -> ApiError;
+}
+
+/// A closure to run on the main thread
+pub type Job = Box<dyn FnOnce() + Send>;
+
+/// The type of a byondapi function, which is `extern "C"` unless another ABI is given
+macro_rules! symbol_type {
+    (fn($($arg:ty),*) $(-> $ret:ty)?) => {
+        unsafe extern "C" fn($($arg),*) $(-> $ret)?
+    };
+    ($abi:literal fn($($arg:ty),*) $(-> $ret:ty)?) => {
+        unsafe extern $abi fn($($arg),*) $(-> $ret)?
+    };
+}
+
+macro_rules! symbols {
+    ($($name:ident: $($abi:literal)? fn($($arg:ty),*) $(-> $ret:ty)?;)*) => {
+        /// The byondapi functions exported by the host, if it has them
+        #[allow(non_snake_case)]
+        #[cfg_attr(test, derive(Default))]
+        struct Symbols {
+            $($name: Option<symbol_type!($($abi)? fn($($arg),*) $(-> $ret)?)>,)*
+        }
+
+        impl Symbols {
+            fn resolve(library: &Library) -> Self {
+                Self {
+                    $(
+                        // SAFETY: the type is the signature byondapi declares for the symbol
+                        $name: unsafe { library.get(concat!(stringify!($name), "\0").as_bytes()) }
+                            .ok()
+                            .map(|symbol| *symbol),
+                    )*
+                }
+            }
+        }
+    };
+}
+
+symbols! {
+    Byond_GetLastError: fn() -> *const c_char;
+    Byond_GetVersion: fn(*mut u32, *mut u32);
+    ByondValue_SetStr: fn(*mut CByondValue, *const c_char);
+    Byond_ToString: fn(*const CByondValue, *mut c_char, *mut u32) -> bool;
+    Byond_GetStrId: fn(*const c_char) -> u32;
+    Byond_ReadVarByStrId: fn(*const CByondValue, u32, *mut CByondValue) -> bool;
+    Byond_WriteVarByStrId: fn(*const CByondValue, u32, *const CByondValue) -> bool;
+    Byond_CallProcByStrId: fn(*const CByondValue, u32, *const CByondValue, u32, *mut CByondValue) -> bool;
+    Byond_CallGlobalProcByStrId: fn(u32, *const CByondValue, u32, *mut CByondValue) -> bool;
+    Byond_CreateList: fn(*mut CByondValue) -> bool;
+    Byond_ReadList: fn(*const CByondValue, *mut CByondValue, *mut u32) -> bool;
+    Byond_WriteList: fn(*const CByondValue, *const CByondValue, u32) -> bool;
+    Byond_ReadListAssoc: fn(*const CByondValue, *mut CByondValue, *mut u32) -> bool;
+    Byond_ReadListIndex: fn(*const CByondValue, *const CByondValue, *mut CByondValue) -> bool;
+    Byond_WriteListIndex: fn(*const CByondValue, *const CByondValue, *const CByondValue) -> bool;
+    ByondValue_IncRef: fn(*const CByondValue);
+    ByondValue_DecRef: fn(*const CByondValue);
+    Byond_TestRef: fn(*mut CByondValue) -> bool;
+    Byond_ThreadSync: fn(extern "C" fn(*mut c_void) -> CByondValue, *mut c_void, bool) -> CByondValue;
+    // raises the runtime by unwinding back into BYOND, so it never returns
+    Byond_CRASH: "C-unwind" fn(*const c_char);
+}
+
+/// Looks up a symbol, or returns `ApiError::MissingSymbol` from the calling function
+macro_rules! symbol {
+    ($api:expr, $name:ident) => {
+        $api.symbols
+            .$name
+            .ok_or(ApiError::MissingSymbol(stringify!($name)))?
+    };
+}
+
+/// What `Byond_GetStrId` returns for a string that doesn't exist
+const NO_STR_ID: u32 = 0xFFFF;
+
+/// byondapi as exported by the host process.
+///
+/// Var and proc names are passed to byondapi as the IDs of the strings holding them, which are
+/// cached after the first successful use of each name.
+pub struct DynamicApi {
+    symbols: Symbols,
+    /// The host version, asked for once when byondapi is looked up
+    version: Option<ByondVersion>,
+    str_ids: RwLock<HashMap<CString, u32>>,
+    // keeps the symbols valid
+    _library: Library,
+}
+
+impl DynamicApi {
+    /// Looks up byondapi in the host process.
+    ///
+    /// # Errors
+    ///
+    /// If the host library isn't loaded in this process. Missing functions are only reported when
+    /// they are called.
+    pub fn from_host() -> Result<Self, ApiError> {
+        #[cfg(windows)]
+        let library: Library =
+            libloading::os::windows::Library::open_already_loaded("byondcore.dll")
+                .map_err(|err| ApiError::NoHost(err.to_string()))?
+                .into();
+        // libbyond.so is linked into DreamDaemon, so its symbols are global
+        #[cfg(unix)]
+        let library: Library = libloading::os::unix::Library::this().into();
+        let symbols = Symbols::resolve(&library);
+        let version = symbols.Byond_GetVersion.map(|get_version| {
+            let (mut major, mut build) = (0, 0);
+            unsafe { get_version(&mut major, &mut build) };
+            ByondVersion::new(major, build)
+        });
+        Ok(Self {
+            symbols,
+            version,
+            str_ids: RwLock::default(),
+            _library: library,
+        })
+    }
+
+    /// The error for a byondapi function that returned failure
+    fn failed(&self, function: &'static str) -> ApiError {
+        let message = self
+            .symbols
+            .Byond_GetLastError
+            // SAFETY: byondapi returns a NUL terminated string, or null if there was no error
+            .map(|last_error| unsafe { last_error() })
+            .filter(|message| !message.is_null())
+            .map(|message| {
+                unsafe { CStr::from_ptr(message) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .unwrap_or_else(|| "unknown error".to_string());
+        ApiError::Failed { function, message }
+    }
+
+    /// Calls `f` with the string ID of `name`, caching the ID if `f` succeeds.
+    ///
+    /// Only names of vars and procs that exist are cached, as those are part of the compiled world
+    /// and never go away, while the ID of any other string can be reused once it's deleted.
+    fn with_str_id<R>(
+        &self,
+        name: &CStr,
+        function: &'static str,
+        f: impl FnOnce(u32) -> Result<R, ApiError>,
+    ) -> Result<R, ApiError> {
+        let cached = self
+            .str_ids
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .copied();
+        if let Some(id) = cached {
+            return f(id);
+        }
+        let get_str_id = symbol!(self, Byond_GetStrId);
+        let id = unsafe { get_str_id(name.as_ptr()) };
+        if id == NO_STR_ID {
+            // nothing can be named with a string that doesn't exist
+            return Err(ApiError::Failed {
+                function,
+                message: format!("nothing is named \"{}\"", name.to_string_lossy()),
+            });
+        }
+        let result = f(id)?;
+        self.str_ids
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_owned(), id);
+        Ok(result)
+    }
+
+    /// Calls a byondapi function that fills a buffer of values, which reports the length it needs
+    /// if the buffer is too small
+    fn read_buffer(
+        &self,
+        function: &'static str,
+        mut read: impl FnMut(*mut CByondValue, *mut u32) -> bool,
+    ) -> Result<Vec<CByondValue>, ApiError> {
+        let mut buf = Vec::new();
+        loop {
+            let mut len = arg_count(&buf);
+            if read(buf.as_mut_ptr(), &mut len) {
+                buf.truncate(len as usize);
+                return Ok(buf);
+            }
+            // the list may have grown between calls, so retry until it fits
+            if len as usize <= buf.len() {
+                return Err(self.failed(function));
+            }
+            buf.resize(len as usize, CByondValue::default());
+        }
+    }
+}
+
+fn arg_count(args: &[CByondValue]) -> u32 {
+    // BYOND can't have anywhere near this many arguments anyway
+    u32::try_from(args.len()).unwrap_or(u32::MAX)
+}
+
+impl ByondApi for DynamicApi {
+    fn main_thread(&self) -> Option<ThreadId> {
+        MAIN_THREAD.get().copied()
+    }
+
+    fn version(&self) -> Result<ByondVersion, ApiError> {
+        self.version
+            .ok_or(ApiError::MissingSymbol("Byond_GetVersion"))
+    }
+
+    fn create_string(&self, text: &CStr) -> Result<CByondValue, ApiError> {
+        let set_str = symbol!(self, ByondValue_SetStr);
+        let mut value = CByondValue::default();
+        unsafe { set_str(&mut value, text.as_ptr()) };
+        Ok(value)
+    }
+
+    fn to_string(&self, value: &CByondValue) -> Result<Vec<u8>, ApiError> {
+        let to_string = symbol!(self, Byond_ToString);
+        // the first call only reports the length needed, including the NUL terminator
+        let mut len = 0;
+        if unsafe { to_string(value, ptr::null_mut(), &mut len) } && len == 0 {
+            return Ok(Vec::new());
+        }
+        let mut buf = vec![0u8; len as usize];
+        if !unsafe { to_string(value, buf.as_mut_ptr().cast(), &mut len) } {
+            return Err(self.failed("Byond_ToString"));
+        }
+        let text_len = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+        buf.truncate(text_len);
+        Ok(buf)
+    }
+
+    fn read_var(&self, src: &CByondValue, name: &CStr) -> Result<CByondValue, ApiError> {
+        let read_var = symbol!(self, Byond_ReadVarByStrId);
+        self.with_str_id(name, "Byond_ReadVarByStrId", |id| {
+            let mut result = CByondValue::default();
+            if unsafe { read_var(src, id, &mut result) } {
+                Ok(result)
+            } else {
+                Err(self.failed("Byond_ReadVarByStrId"))
+            }
+        })
+    }
+
+    fn write_var(
+        &self,
+        src: &CByondValue,
+        name: &CStr,
+        value: &CByondValue,
+    ) -> Result<(), ApiError> {
+        let write_var = symbol!(self, Byond_WriteVarByStrId);
+        self.with_str_id(name, "Byond_WriteVarByStrId", |id| {
+            if unsafe { write_var(src, id, value) } {
+                Ok(())
+            } else {
+                Err(self.failed("Byond_WriteVarByStrId"))
+            }
+        })
+    }
+
+    fn call_proc(
+        &self,
+        src: &CByondValue,
+        name: &CStr,
+        args: &[CByondValue],
+    ) -> Result<CByondValue, ApiError> {
+        let call_proc = symbol!(self, Byond_CallProcByStrId);
+        self.with_str_id(name, "Byond_CallProcByStrId", |id| {
+            let mut result = CByondValue::default();
+            if unsafe { call_proc(src, id, args.as_ptr(), arg_count(args), &mut result) } {
+                Ok(result)
+            } else {
+                Err(self.failed("Byond_CallProcByStrId"))
+            }
+        })
+    }
+
+    fn call_global_proc(&self, name: &CStr, args: &[CByondValue]) -> Result<CByondValue, ApiError> {
+        let call_global_proc = symbol!(self, Byond_CallGlobalProcByStrId);
+        self.with_str_id(name, "Byond_CallGlobalProcByStrId", |id| {
+            let mut result = CByondValue::default();
+            if unsafe { call_global_proc(id, args.as_ptr(), arg_count(args), &mut result) } {
+                Ok(result)
+            } else {
+                Err(self.failed("Byond_CallGlobalProcByStrId"))
+            }
+        })
+    }
+
+    fn create_list(&self) -> Result<CByondValue, ApiError> {
+        let create_list = symbol!(self, Byond_CreateList);
+        let mut result = CByondValue::default();
+        if unsafe { create_list(&mut result) } {
+            Ok(result)
+        } else {
+            Err(self.failed("Byond_CreateList"))
+        }
+    }
+
+    fn read_list(&self, list: &CByondValue) -> Result<Vec<CByondValue>, ApiError> {
+        let read_list = symbol!(self, Byond_ReadList);
+        self.read_buffer("Byond_ReadList", |buf, len| unsafe {
+            read_list(list, buf, len)
+        })
+    }
+
+    fn write_list(&self, list: &CByondValue, items: &[CByondValue]) -> Result<(), ApiError> {
+        let write_list = symbol!(self, Byond_WriteList);
+        if unsafe { write_list(list, items.as_ptr(), arg_count(items)) } {
+            Ok(())
+        } else {
+            Err(self.failed("Byond_WriteList"))
+        }
+    }
+
+    fn read_list_assoc(
+        &self,
+        list: &CByondValue,
+    ) -> Result<Vec<(CByondValue, CByondValue)>, ApiError> {
+        let read_list_assoc = symbol!(self, Byond_ReadListAssoc);
+        let flat = self.read_buffer("Byond_ReadListAssoc", |buf, len| unsafe {
+            read_list_assoc(list, buf, len)
+        })?;
+        Ok(flat
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect())
+    }
+
+    fn read_list_index(
+        &self,
+        list: &CByondValue,
+        index: &CByondValue,
+    ) -> Result<CByondValue, ApiError> {
+        let read_list_index = symbol!(self, Byond_ReadListIndex);
+        let mut result = CByondValue::default();
+        if unsafe { read_list_index(list, index, &mut result) } {
+            Ok(result)
+        } else {
+            Err(self.failed("Byond_ReadListIndex"))
+        }
+    }
+
+    fn write_list_index(
+        &self,
+        list: &CByondValue,
+        index: &CByondValue,
+        value: &CByondValue,
+    ) -> Result<(), ApiError> {
+        let write_list_index = symbol!(self, Byond_WriteListIndex);
+        if unsafe { write_list_index(list, index, value) } {
+            Ok(())
+        } else {
+            Err(self.failed("Byond_WriteListIndex"))
+        }
+    }
+
+    fn inc_ref(&self, value: &CByondValue) -> Result<(), ApiError> {
+        let inc_ref = symbol!(self, ByondValue_IncRef);
+        unsafe { inc_ref(value) };
+        Ok(())
+    }
+
+    fn dec_ref(&self, value: &CByondValue) -> Result<(), ApiError> {
+        let dec_ref = symbol!(self, ByondValue_DecRef);
+        unsafe { dec_ref(value) };
+        Ok(())
+    }
+
+    fn test_ref(&self, value: &CByondValue) -> Result<bool, ApiError> {
+        let test_ref = symbol!(self, Byond_TestRef);
+        // nulls out the value if it's no longer valid, so it's tested on a copy
+        let mut value = *value;
+        Ok(unsafe { test_ref(&mut value) })
+    }
+
+    fn thread_sync(&self, job: Job, block: bool) -> Result<(), ApiError> {
+        extern "C" fn run_job(data: *mut c_void) -> CByondValue {
+            // SAFETY: `data` is the job boxed below, which BYOND passes back exactly once
+            let job = unsafe { Box::from_raw(data.cast::<Job>()) };
+            // a panic can't unwind into BYOND
+            let _ = catch_unwind(AssertUnwindSafe(job));
+            CByondValue::default()
+        }
+
+        let thread_sync = symbol!(self, Byond_ThreadSync);
+        let data = Box::into_raw(Box::new(job));
+        unsafe { thread_sync(run_job, data.cast(), block) };
+        Ok(())
+    }
+
+    fn crash(&self, message: &CStr) -> ApiError {
+        let Some(crash) = self.symbols.Byond_CRASH else {
+            return ApiError::MissingSymbol("Byond_CRASH");
+        };
+        unsafe { crash(message.as_ptr()) };
+        ApiError::Failed {
+            function: "Byond_CRASH",
+            message: "returned instead of raising a runtime error".to_string(),
+        }
+    }
+}
+
+/// The [`ByondApi`] calls go through, see [`api`]
+pub type SharedApi = Arc<dyn ByondApi>;
+
+fn global_api() -> &'static RwLock<Option<SharedApi>> {
+    static API: OnceLock<RwLock<Option<SharedApi>>> = OnceLock::new();
+    API.get_or_init(Default::default)
+}
+
+thread_local! {
+    static THREAD_API: RefCell<Option<SharedApi>> = const { RefCell::new(None) };
+}
+
+static MAIN_THREAD: OnceLock<ThreadId> = OnceLock::new();
+
+/// Records the calling thread as BYOND's main thread, if none was recorded yet. Every generated
+/// function calls this when it's entered, as BYOND only calls them on its main thread.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub fn enter_main_thread() {
+    MAIN_THREAD.get_or_init(|| thread::current().id());
+}
+
+/// The API calls go through: the one set for this thread with [`with_api`], else the one set with
+/// [`set_api`], else byondapi from the host process.
+///
+/// # Errors
+///
+/// If nothing was set and byondapi can't be found in the host process, or if this isn't the main
+/// thread of the API.
+pub fn api() -> Result<SharedApi, ApiError> {
+    let api = any_thread_api()?;
+    match api.main_thread() {
+        Some(main) if main != thread::current().id() => Err(ApiError::NotMainThread),
+        _ => Ok(api),
+    }
+}
+
+/// Same as [`api`], but on any thread, for handing work over to the main thread.
+pub(crate) fn any_thread_api() -> Result<SharedApi, ApiError> {
+    if let Some(api) = THREAD_API.with(|api| api.borrow().clone()) {
+        return Ok(api);
+    }
+    if let Some(api) = &*global_api().read().unwrap_or_else(PoisonError::into_inner) {
+        return Ok(api.clone());
+    }
+    let mut global = global_api().write().unwrap_or_else(PoisonError::into_inner);
+    if let Some(api) = &*global {
+        return Ok(api.clone());
+    }
+    let api: SharedApi = Arc::new(DynamicApi::from_host()?);
+    *global = Some(api.clone());
+    Ok(api)
+}
+
+/// Sets the API used by every thread, in place of byondapi from the host process.
+pub fn set_api(api: SharedApi) {
+    *global_api().write().unwrap_or_else(PoisonError::into_inner) = Some(api);
+}
+
+/// Runs `f` with every call on this thread going through `api`, so tests running in parallel can
+/// each use their own mock.
+pub fn with_api<R>(api: SharedApi, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<SharedApi>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREAD_API.with(|api| *api.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(THREAD_API.with(|current| current.replace(Some(api))));
+    f()
+}
+
+/// A var or proc name as byondapi takes it
+pub(crate) fn c_name(name: &str) -> Result<CString, ApiError> {
+    CString::new(name).map_err(|_| ApiError::InvalidName(name.to_string()))
+}
+
+fn raw_values(values: &[ByondValue]) -> &[CByondValue] {
+    // SAFETY: `ByondValue` is a transparent wrapper around `CByondValue`
+    unsafe { std::slice::from_raw_parts(values.as_ptr().cast(), values.len()) }
+}
+
+/// Reads the var `name` of `src`.
+///
+/// # Errors
+///
+/// If `src` has no such var, or isn't something with vars.
+pub fn read_var(src: &ByondValue, name: &str) -> Result<ByondValue, ApiError> {
+    let value = api()?.read_var(src.as_raw(), &c_name(name)?)?;
+    // SAFETY: received from byondapi during this call
+    Ok(unsafe { ByondValue::from_raw(value) })
+}
+
+/// Sets the var `name` of `src` to `value`.
+///
+/// # Errors
+///
+/// If `src` has no such var, or isn't something with vars.
+pub fn write_var(src: &ByondValue, name: &str, value: &ByondValue) -> Result<(), ApiError> {
+    api()?.write_var(src.as_raw(), &c_name(name)?, value.as_raw())
+}
+
+/// Calls the proc `name` of `src`, like `src.name(args...)`.
+///
+/// # Errors
+///
+/// If `src` has no such proc.
+pub fn call_proc(
+    src: &ByondValue,
+    name: &str,
+    args: &[ByondValue],
+) -> Result<ByondValue, ApiError> {
+    let value = api()?.call_proc(src.as_raw(), &c_name(name)?, raw_values(args))?;
+    // SAFETY: received from byondapi during this call
+    Ok(unsafe { ByondValue::from_raw(value) })
+}
+
+/// Calls the global proc `name`, like `global.name(args...)`.
+///
+/// # Errors
+///
+/// If there is no such proc.
+pub fn call_global_proc(name: &str, args: &[ByondValue]) -> Result<ByondValue, ApiError> {
+    let value = api()?.call_global_proc(&c_name(name)?, raw_values(args))?;
+    // SAFETY: received from byondapi during this call
+    Ok(unsafe { ByondValue::from_raw(value) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ffi_v2::api::mock::MockApi;
+    use crate::ffi_v2::ValueType;
+
+    #[test]
+    fn missing_symbols_are_reported() {
+        // this test binary is the "host", which has no byondapi. It's called directly, as the
+        // main thread of the host is whichever test first called a generated function
+        let api = DynamicApi::from_host().unwrap();
+        {
+            let err = api.create_string(c"text").unwrap_err();
+            assert!(matches!(err, ApiError::MissingSymbol("ByondValue_SetStr")));
+            assert_eq!(
+                err.to_string(),
+                "API;MISSING_SYMBOL;The host doesn't export \"ByondValue_SetStr\", it may be too old"
+            );
+        }
+    }
+
+    #[test]
+    fn procs_and_vars_go_through_the_api() {
+        let mock = Arc::new(MockApi::new());
+        mock.define_proc("heal", |src, args| {
+            let amount = args[0].as_number().unwrap_or_default();
+            let health = read_var(src, "health")?.as_number().unwrap_or_default();
+            write_var(src, "health", &ByondValue::number(health + amount))?;
+            Ok(ByondValue::null())
+        });
+        mock.define_global_proc("greet", |_, args| {
+            ByondValue::string(&format!("hello {}", args[0].text()?))
+        });
+
+        with_api(mock.clone(), || {
+            let mob = mock.new_datum("/mob");
+            write_var(&mob, "health", &ByondValue::number(50.0)).unwrap();
+            call_proc(&mob, "heal", &[ByondValue::number(25.0)]).unwrap();
+            assert_eq!(read_var(&mob, "health").unwrap().as_number(), Some(75.0));
+            assert!(matches!(
+                read_var(&mob, "mana"),
+                Err(ApiError::Failed {
+                    function: "Byond_ReadVarByStrId",
+                    ..
+                })
+            ));
+
+            let name = ByondValue::string("world").unwrap();
+            let greeting = call_global_proc("greet", &[name]).unwrap();
+            assert_eq!(greeting.value_type(), ValueType::STRING);
+            assert_eq!(greeting.text().unwrap(), "hello world");
+            assert!(matches!(
+                read_var(&mob, "bad\0name"),
+                Err(ApiError::InvalidName(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn other_threads_cant_use_the_api() {
+        let mock = Arc::new(MockApi::new());
+        std::thread::spawn(move || {
+            with_api(mock, || {
+                let err = ByondValue::string("text").unwrap_err();
+                assert!(matches!(err, ApiError::NotMainThread));
+                assert_eq!(
+                    err.to_string(),
+                    "API;NOT_MAIN_THREAD;byondapi can only be used on the main thread, use \
+                     thread_sync from others"
+                );
+                assert!(matches!(api(), Err(ApiError::NotMainThread)));
+                assert!(any_thread_api().is_ok());
+            });
+        })
+        .join()
+        .unwrap();
+    }
+
+    /// A host where only `health` and `mana` are strings, and only `health` is a var
+    mod str_ids {
+        use std::ffi::{c_char, CStr};
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        use crate::ffi_v2::CByondValue;
+
+        pub static LOOKUPS: AtomicU32 = AtomicU32::new(0);
+
+        pub unsafe extern "C" fn get_str_id(name: *const c_char) -> u32 {
+            LOOKUPS.fetch_add(1, Ordering::Relaxed);
+            match unsafe { CStr::from_ptr(name) }.to_bytes() {
+                b"health" => 1,
+                b"mana" => 2,
+                _ => super::NO_STR_ID,
+            }
+        }
+
+        pub unsafe extern "C" fn read_var(
+            _src: *const CByondValue,
+            id: u32,
+            result: *mut CByondValue,
+        ) -> bool {
+            if id != 1 {
+                return false;
+            }
+            unsafe { *result = crate::ffi_v2::ByondValue::number(75.0).into_raw() };
+            true
+        }
+
+        pub unsafe extern "C" fn last_error() -> *const c_char {
+            c"no such var".as_ptr()
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn only_names_that_worked_are_cached() {
+        use std::sync::atomic::Ordering;
+
+        let api = DynamicApi {
+            symbols: Symbols {
+                Byond_GetStrId: Some(str_ids::get_str_id),
+                Byond_ReadVarByStrId: Some(str_ids::read_var),
+                Byond_GetLastError: Some(str_ids::last_error),
+                ..Symbols::default()
+            },
+            version: None,
+            str_ids: RwLock::default(),
+            _library: libloading::os::unix::Library::this().into(),
+        };
+        let src = CByondValue::default();
+        let lookups = || str_ids::LOOKUPS.load(Ordering::Relaxed);
+
+        for _ in 0..2 {
+            let health = api.read_var(&src, c"health").unwrap();
+            assert_eq!(health.value_type, ValueType::NUMBER.0);
+        }
+        assert_eq!(lookups(), 1);
+
+        // a string that isn't a var, and one that doesn't exist, are looked up every time
+        for _ in 0..2 {
+            let err = api.read_var(&src, c"mana").unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "API;FAILED;Byond_ReadVarByStrId failed: no such var"
+            );
+            let err = api.read_var(&src, c"stamina").unwrap_err();
+            assert!(
+                err.to_string().contains("nothing is named \"stamina\""),
+                "{err}"
+            );
+        }
+        assert_eq!(lookups(), 5);
+        assert_eq!(api.str_ids.read().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,548 @@
+//! A stand-in for byondapi, for running code that calls into BYOND outside of it.
+//!
+//! The mock keeps its own strings and datums, with vars that can be read and written freely, and
+//! procs defined as Rust closures. Lists are plain Rust vectors, with the `Add`, `Remove` and `Cut`
+//! procs built in.
+//!
+//! Jobs sent to the main thread with [`thread_sync`](crate::ffi_v2::thread_sync) are queued until
+//! the thread standing in for the main thread runs them with [`MockApi::run_jobs`].
+//!
+//! Runtime errors raised through the mock panic with a [`MockRuntime`], which a test can catch.
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CStr;
+use std::panic;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+
+use crate::ffi_v2::api::{ApiError, ByondApi, Job};
+use crate::ffi_v2::{ByondValue, CByondValue, ValueType};
+use crate::host::ByondVersion;
+
+/// A proc of the mock, called with `src` (null for global procs) and the arguments
+pub type MockProc =
+    Arc<dyn Fn(&ByondValue, &[ByondValue]) -> Result<ByondValue, ApiError> + Send + Sync>;
+
+struct Datum {
+    path: String,
+    vars: HashMap<String, CByondValue>,
+}
+
+/// The items of a list, with their associated values
+type List = Vec<(CByondValue, CByondValue)>;
+
+#[derive(Default)]
+struct State {
+    strings: Vec<String>,
+    datums: HashMap<u32, Datum>,
+    lists: HashMap<u32, List>,
+    ref_counts: HashMap<(u8, u32), u32>,
+    next_ref: u32,
+    procs: HashMap<String, MockProc>,
+    global_procs: HashMap<String, MockProc>,
+    version: Option<ByondVersion>,
+}
+
+/// The panic payload a runtime error raised through the mock unwinds with, as the mock can't
+/// unwind into BYOND
+#[derive(Debug)]
+pub struct MockRuntime {
+    pub message: String,
+}
+
+/// A pure Rust implementation of [`ByondApi`].
+///
+/// The thread that creates the mock stands in for BYOND's main thread.
+pub struct MockApi {
+    state: Mutex<State>,
+    jobs: Mutex<VecDeque<Job>>,
+    main_thread: ThreadId,
+}
+
+impl Default for MockApi {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                version: Some(Self::DEFAULT_VERSION),
+                ..State::default()
+            }),
+            jobs: Mutex::default(),
+            main_thread: thread::current().id(),
+        }
+    }
+}
+
+fn failed(function: &'static str, message: impl Into<String>) -> ApiError {
+    ApiError::Failed {
+        function,
+        message: message.into(),
+    }
+}
+
+fn name(name: &CStr) -> String {
+    name.to_string_lossy().into_owned()
+}
+
+/// Whether two values are the same, as list lookups compare them
+fn same(a: &CByondValue, b: &CByondValue) -> bool {
+    // SAFETY: the mock isn't tied to a thread or call
+    unsafe { ByondValue::from_raw(*a) == ByondValue::from_raw(*b) }
+}
+
+/// The 0-based position of a 1-based DM index into a list of `len` items, where `len + 1` is
+/// allowed if `end` is set
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn position(index: &CByondValue, len: usize, end: bool) -> Option<usize> {
+    let index = f32::from_bits(index.data);
+    let max = if end { len + 1 } else { len };
+    (index.fract() == 0.0 && index >= 1.0 && index as usize <= max).then(|| index as usize - 1)
+}
+
+fn number(number: f32) -> CByondValue {
+    ByondValue::number(number).into_raw()
+}
+
+impl MockApi {
+    /// The version the mock reports, unless set with [`set_version`](Self::set_version)
+    pub const DEFAULT_VERSION: ByondVersion = ByondVersion::new(515, 1647);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the version the mock reports, where `None` stands in for a host that doesn't report
+    /// one.
+    pub fn set_version(&self, version: Option<ByondVersion>) {
+        self.state().version = version;
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn list<'a>(
+        state: &'a mut State,
+        list: &CByondValue,
+        function: &'static str,
+    ) -> Result<&'a mut List, ApiError> {
+        state
+            .lists
+            .get_mut(&list.data)
+            .filter(|_| ValueType(list.value_type) == ValueType::LIST)
+            .ok_or_else(|| failed(function, "not a list"))
+    }
+
+    /// The built in procs of lists
+    fn call_list_proc(
+        &self,
+        list: &CByondValue,
+        name: &str,
+        args: &[CByondValue],
+    ) -> Result<CByondValue, ApiError> {
+        let mut state = self.state();
+        // adding a list adds its items instead
+        let mut items = Vec::new();
+        for arg in args {
+            match state.lists.get(&arg.data) {
+                Some(other) if ValueType(arg.value_type) == ValueType::LIST => {
+                    items.extend(other.iter().map(|(item, _)| *item));
+                }
+                _ => items.push(*arg),
+            }
+        }
+        let list = Self::list(&mut state, list, "Byond_CallProcByStrId")?;
+        match name {
+            "Add" => {
+                list.extend(items.into_iter().map(|item| (item, CByondValue::default())));
+                Ok(CByondValue::default())
+            }
+            "Remove" => {
+                let mut removed = false;
+                for item in items {
+                    if let Some(found) = list.iter().rposition(|(other, _)| same(other, &item)) {
+                        list.remove(found);
+                        removed = true;
+                    }
+                }
+                Ok(number(if removed { 1.0 } else { 0.0 }))
+            }
+            "Cut" => {
+                let len = list.len();
+                let start = args.first().copied().unwrap_or_else(|| number(1.0));
+                let end = args.get(1).copied().unwrap_or_else(|| number(0.0));
+                let start = position(&start, len, true);
+                // 0 is the end of the list
+                let end = if f32::from_bits(end.data) == 0.0 {
+                    Some(len)
+                } else {
+                    position(&end, len, true)
+                };
+                match (start, end) {
+                    (Some(start), Some(end)) if start <= end => {
+                        list.drain(start..end);
+                        Ok(number(1.0))
+                    }
+                    _ => Err(failed("Byond_CallProcByStrId", "bad index")),
+                }
+            }
+            _ => Err(failed(
+                "Byond_CallProcByStrId",
+                format!("undefined proc \"{name}\""),
+            )),
+        }
+    }
+
+    /// Creates a datum of type `path`, with no vars.
+    pub fn new_datum(&self, path: &str) -> ByondValue {
+        let mut state = self.state();
+        state.next_ref += 1;
+        let id = state.next_ref;
+        state.datums.insert(
+            id,
+            Datum {
+                path: path.to_string(),
+                vars: HashMap::new(),
+            },
+        );
+        let raw = CByondValue {
+            value_type: ValueType::DATUM.0,
+            junk: [0; 3],
+            data: id,
+        };
+        // SAFETY: the mock isn't tied to a thread or call
+        unsafe { ByondValue::from_raw(raw) }
+    }
+
+    /// Deletes a datum or list, like `del(value)`. References to it are no longer valid.
+    pub fn delete(&self, value: &ByondValue) {
+        let mut state = self.state();
+        let raw = value.as_raw();
+        match ValueType(raw.value_type) {
+            ValueType::LIST => state.lists.remove(&raw.data).map(drop),
+            _ => state.datums.remove(&raw.data).map(drop),
+        };
+        state.ref_counts.remove(&(raw.value_type, raw.data));
+    }
+
+    /// How many references were added to a value with [`ByondApi::inc_ref`], and not yet
+    /// released.
+    pub fn ref_count(&self, value: &ByondValue) -> u32 {
+        let raw = value.as_raw();
+        self.state()
+            .ref_counts
+            .get(&(raw.value_type, raw.data))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Defines a proc every datum has.
+    pub fn define_proc(
+        &self,
+        name: &str,
+        proc: impl Fn(&ByondValue, &[ByondValue]) -> Result<ByondValue, ApiError>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.state().procs.insert(name.to_string(), Arc::new(proc));
+    }
+
+    /// Defines a global proc, which is called with a null `src`.
+    pub fn define_global_proc(
+        &self,
+        name: &str,
+        proc: impl Fn(&ByondValue, &[ByondValue]) -> Result<ByondValue, ApiError>
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.state()
+            .global_procs
+            .insert(name.to_string(), Arc::new(proc));
+    }
+
+    /// Runs the jobs queued by other threads, as BYOND would during a tick, including any queued
+    /// while running them. Returns how many ran.
+    pub fn run_jobs(&self) -> usize {
+        let mut ran = 0;
+        loop {
+            let job = self
+                .jobs
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop_front();
+            let Some(job) = job else {
+                return ran;
+            };
+            job();
+            ran += 1;
+        }
+    }
+
+    /// Drops the queued jobs without running them, as BYOND does when it shuts down. Returns how
+    /// many were dropped.
+    pub fn drop_jobs(&self) -> usize {
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let dropped = jobs.len();
+        jobs.clear();
+        dropped
+    }
+
+    /// Calls a proc with the state unlocked, so it can call back into the mock
+    fn call(
+        proc: &MockProc,
+        src: &CByondValue,
+        args: &[CByondValue],
+    ) -> Result<CByondValue, ApiError> {
+        // SAFETY: the mock isn't tied to a thread or call
+        let src = unsafe { ByondValue::from_raw(*src) };
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| unsafe { ByondValue::from_raw(*arg) })
+            .collect();
+        proc(&src, &args).map(ByondValue::into_raw)
+    }
+}
+
+impl ByondApi for MockApi {
+    fn main_thread(&self) -> Option<ThreadId> {
+        Some(self.main_thread)
+    }
+
+    fn version(&self) -> Result<ByondVersion, ApiError> {
+        self.state()
+            .version
+            .ok_or(ApiError::MissingSymbol("Byond_GetVersion"))
+    }
+
+    fn create_string(&self, text: &CStr) -> Result<CByondValue, ApiError> {
+        let mut state = self.state();
+        let text = text.to_string_lossy();
+        let id = match state.strings.iter().position(|string| *string == text) {
+            Some(id) => id,
+            None => {
+                state.strings.push(text.into_owned());
+                state.strings.len() - 1
+            }
+        };
+        Ok(CByondValue {
+            value_type: ValueType::STRING.0,
+            junk: [0; 3],
+            data: u32::try_from(id).map_err(|_| failed("ByondValue_SetStr", "too many strings"))?,
+        })
+    }
+
+    fn to_string(&self, value: &CByondValue) -> Result<Vec<u8>, ApiError> {
+        let state = self.state();
+        let text = match ValueType(value.value_type) {
+            ValueType::NULL => String::new(),
+            ValueType::NUMBER => f32::from_bits(value.data).to_string(),
+            ValueType::LIST => "/list".to_string(),
+            ValueType::STRING => state
+                .strings
+                .get(value.data as usize)
+                .cloned()
+                .ok_or_else(|| failed("Byond_ToString", "bad string ID"))?,
+            _ => state
+                .datums
+                .get(&value.data)
+                .map(|datum| datum.path.clone())
+                .ok_or_else(|| failed("Byond_ToString", "bad ref"))?,
+        };
+        Ok(text.into_bytes())
+    }
+
+    fn read_var(&self, src: &CByondValue, name: &CStr) -> Result<CByondValue, ApiError> {
+        let state = self.state();
+        let datum = state
+            .datums
+            .get(&src.data)
+            .filter(|_| ValueType(src.value_type).is_ref())
+            .ok_or_else(|| failed("Byond_ReadVarByStrId", "not a datum"))?;
+        datum
+            .vars
+            .get(&*name.to_string_lossy())
+            .copied()
+            .ok_or_else(|| {
+                failed(
+                    "Byond_ReadVarByStrId",
+                    format!("undefined var \"{}\"", self::name(name)),
+                )
+            })
+    }
+
+    fn write_var(
+        &self,
+        src: &CByondValue,
+        name: &CStr,
+        value: &CByondValue,
+    ) -> Result<(), ApiError> {
+        let mut state = self.state();
+        let datum = state
+            .datums
+            .get_mut(&src.data)
+            .filter(|_| ValueType(src.value_type).is_ref())
+            .ok_or_else(|| failed("Byond_WriteVarByStrId", "not a datum"))?;
+        datum.vars.insert(self::name(name), *value);
+        Ok(())
+    }
+
+    fn call_proc(
+        &self,
+        src: &CByondValue,
+        name: &CStr,
+        args: &[CByondValue],
+    ) -> Result<CByondValue, ApiError> {
+        if ValueType(src.value_type) == ValueType::LIST {
+            return self.call_list_proc(src, &name.to_string_lossy(), args);
+        }
+        let proc = self
+            .state()
+            .procs
+            .get(&*name.to_string_lossy())
+            .cloned()
+            .ok_or_else(|| {
+                failed(
+                    "Byond_CallProcByStrId",
+                    format!("undefined proc \"{}\"", self::name(name)),
+                )
+            })?;
+        Self::call(&proc, src, args)
+    }
+
+    fn call_global_proc(&self, name: &CStr, args: &[CByondValue]) -> Result<CByondValue, ApiError> {
+        let proc = self
+            .state()
+            .global_procs
+            .get(&*name.to_string_lossy())
+            .cloned()
+            .ok_or_else(|| {
+                failed(
+                    "Byond_CallGlobalProcByStrId",
+                    format!("undefined proc \"{}\"", self::name(name)),
+                )
+            })?;
+        Self::call(&proc, &CByondValue::default(), args)
+    }
+
+    fn create_list(&self) -> Result<CByondValue, ApiError> {
+        let mut state = self.state();
+        state.next_ref += 1;
+        let id = state.next_ref;
+        state.lists.insert(id, Vec::new());
+        Ok(CByondValue {
+            value_type: ValueType::LIST.0,
+            junk: [0; 3],
+            data: id,
+        })
+    }
+
+    fn read_list(&self, list: &CByondValue) -> Result<Vec<CByondValue>, ApiError> {
+        let mut state = self.state();
+        let list = Self::list(&mut state, list, "Byond_ReadList")?;
+        Ok(list.iter().map(|(item, _)| *item).collect())
+    }
+
+    fn write_list(&self, list: &CByondValue, items: &[CByondValue]) -> Result<(), ApiError> {
+        let mut state = self.state();
+        let list = Self::list(&mut state, list, "Byond_WriteList")?;
+        *list = items
+            .iter()
+            .map(|item| (*item, CByondValue::default()))
+            .collect();
+        Ok(())
+    }
+
+    fn read_list_assoc(
+        &self,
+        list: &CByondValue,
+    ) -> Result<Vec<(CByondValue, CByondValue)>, ApiError> {
+        let mut state = self.state();
+        Ok(Self::list(&mut state, list, "Byond_ReadListAssoc")?.clone())
+    }
+
+    fn read_list_index(
+        &self,
+        list: &CByondValue,
+        index: &CByondValue,
+    ) -> Result<CByondValue, ApiError> {
+        let mut state = self.state();
+        let list = Self::list(&mut state, list, "Byond_ReadListIndex")?;
+        if ValueType(index.value_type) == ValueType::NUMBER {
+            let position = position(index, list.len(), false)
+                .ok_or_else(|| failed("Byond_ReadListIndex", "list index out of bounds"))?;
+            return Ok(list[position].0);
+        }
+        Ok(list
+            .iter()
+            .find(|(key, _)| same(key, index))
+            .map(|(_, value)| *value)
+            .unwrap_or_default())
+    }
+
+    fn write_list_index(
+        &self,
+        list: &CByondValue,
+        index: &CByondValue,
+        value: &CByondValue,
+    ) -> Result<(), ApiError> {
+        let mut state = self.state();
+        let list = Self::list(&mut state, list, "Byond_WriteListIndex")?;
+        if ValueType(index.value_type) == ValueType::NUMBER {
+            let position = position(index, list.len(), false)
+                .ok_or_else(|| failed("Byond_WriteListIndex", "list index out of bounds"))?;
+            list[position] = (*value, CByondValue::default());
+            return Ok(());
+        }
+        match list.iter_mut().find(|(key, _)| same(key, index)) {
+            Some((_, assoc)) => *assoc = *value,
+            None => list.push((*index, *value)),
+        }
+        Ok(())
+    }
+
+    fn inc_ref(&self, value: &CByondValue) -> Result<(), ApiError> {
+        *self
+            .state()
+            .ref_counts
+            .entry((value.value_type, value.data))
+            .or_default() += 1;
+        Ok(())
+    }
+
+    fn dec_ref(&self, value: &CByondValue) -> Result<(), ApiError> {
+        let mut state = self.state();
+        let key = (value.value_type, value.data);
+        match state.ref_counts.get_mut(&key) {
+            Some(1) => drop(state.ref_counts.remove(&key)),
+            Some(count) => *count -= 1,
+            None => return Err(failed("ByondValue_DecRef", "the value has no references")),
+        }
+        Ok(())
+    }
+
+    fn test_ref(&self, value: &CByondValue) -> Result<bool, ApiError> {
+        let state = self.state();
+        Ok(match ValueType(value.value_type) {
+            ValueType::NULL | ValueType::NUMBER => true,
+            ValueType::STRING => (value.data as usize) < state.strings.len(),
+            ValueType::LIST => state.lists.contains_key(&value.data),
+            _ => state.datums.contains_key(&value.data),
+        })
+    }
+
+    fn thread_sync(&self, job: Job, _block: bool) -> Result<(), ApiError> {
+        // blocking callers wait for the job to report back, so both are the same here
+        self.jobs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_back(job);
+        Ok(())
+    }
+
+    fn crash(&self, message: &CStr) -> ApiError {
+        panic::panic_any(MockRuntime {
+            message: name(message),
+        })
+    }
+}
@@ -0,0 +1,771 @@
+//! Converting any serde type to and from [`ByondValue`]s.
+//!
+//! [`ByondSerde<T>`] can be taken and returned by `#[byond_fn(v2)]` functions, for types that
+//! implement `Deserialize` and `Serialize`. Values map to DM like they would through JSON:
+//! - structs and maps are assoc lists, keyed by field name for structs. Map keys can't be numbers,
+//!   which DM would take as list indexes
+//! - sequences and tuples are plain lists
+//! - numbers are floats, and integers have to fit one exactly. Floats that are out of range
+//!   are an error, rather than turning infinite
+//! - `bool`s are `1` and `0`, `None` and `()` are null
+//! - enum variants without data are their name as a string, and any other variant is an assoc
+//!   list with its name as the only key
+//!
+//! Any other reference, such as a datum, can't be converted. Take it as a [`ByondValue`] field
+//! instead, through [`FromByondValue`] directly.
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//! use byond_fn::ffi_v2::byond_serde::ByondSerde;
+//!
+//! #[derive(serde::Deserialize, serde::Serialize)]
+//! pub struct Gas {
+//!     moles: f32,
+//!     temperature: f32,
+//! }
+//!
+//! #[byond_fn(v2)]
+//! pub fn mix(gases: ByondSerde<Vec<Gas>>) -> ByondSerde<Gas> {
+//!     let moles: f32 = gases.0.iter().map(|gas| gas.moles).sum();
+//!     let energy: f32 = gases.0.iter().map(|gas| gas.moles * gas.temperature).sum();
+//!     ByondSerde(Gas {
+//!         moles,
+//!         temperature: if moles > 0.0 { energy / moles } else { 0.0 },
+//!     })
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! `call_ext("example_name.dll", "byond:mix")(list(list("moles" = 1, "temperature" = 300)))`
+
+use std::fmt::Display;
+use std::vec;
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, Unexpected, VariantAccess,
+    Visitor,
+};
+use serde::ser::{
+    SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::{de, forward_to_deserialize_any, ser, Serialize};
+
+use crate::ffi_v2::api::ApiError;
+use crate::ffi_v2::{ByondList, ByondValue, FromByondValue, IntoByondValue, ValueType};
+use crate::str_ffi::FFIError;
+
+/// Wraps another type to represent it should be converted from, or returned as, a BYOND value
+/// through serde. See the [module docs](self) for how types map to DM.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct ByondSerde<T>(pub T);
+
+impl<T> ByondSerde<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for ByondSerde<T> {
+    fn from(t: T) -> Self {
+        ByondSerde(t)
+    }
+}
+
+impl<T: DeserializeOwned> FromByondValue for ByondSerde<T> {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        Ok(ByondSerde(from_value(value)?))
+    }
+}
+
+impl<T: Serialize> IntoByondValue for ByondSerde<T> {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(to_value(&self.0)?)
+    }
+}
+
+/// Converts a `T` into a BYOND value.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If `value` can't be represented in DM, or byondapi fails to create it.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<ByondValue, ApiError> {
+    value.serialize(Serializer)
+}
+
+/// Converts a BYOND value into a `T`.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If `value` doesn't match `T`, or byondapi fails to read it.
+pub fn from_value<T: DeserializeOwned>(value: &ByondValue) -> Result<T, ApiError> {
+    T::deserialize(Deserializer(value.clone()))
+}
+
+impl ser::Error for ApiError {
+    fn custom<T: Display>(msg: T) -> Self {
+        ApiError::Serde(msg.to_string())
+    }
+}
+
+impl de::Error for ApiError {
+    fn custom<T: Display>(msg: T) -> Self {
+        ApiError::Serde(msg.to_string())
+    }
+}
+
+/// Unwraps the `ApiError` of a conversion, which is the only error they return
+fn api_error(err: FFIError) -> ApiError {
+    match err {
+        FFIError::ApiError(err) => err,
+        err => ApiError::Serde(err.to_string()),
+    }
+}
+
+fn convert(value: impl IntoByondValue) -> Result<ByondValue, ApiError> {
+    value.into_byond_value().map_err(api_error)
+}
+
+/// An assoc list with `key` as its only key
+fn single_entry(key: &str, value: &ByondValue) -> Result<ByondValue, ApiError> {
+    let list = ByondList::new()?;
+    list.set_assoc(&ByondValue::string(key)?, value)?;
+    Ok(list.into_value())
+}
+
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<ByondValue, ApiError> {
+        convert(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<ByondValue, ApiError> {
+        convert(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<ByondValue, ApiError> {
+        convert(v.to_vec())
+    }
+
+    fn serialize_none(self) -> Result<ByondValue, ApiError> {
+        Ok(ByondValue::null())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<ByondValue, ApiError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<ByondValue, ApiError> {
+        Ok(ByondValue::null())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<ByondValue, ApiError> {
+        Ok(ByondValue::null())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<ByondValue, ApiError> {
+        ByondValue::string(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<ByondValue, ApiError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<ByondValue, ApiError> {
+        single_entry(variant, &value.serialize(Serializer)?)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, ApiError> {
+        Ok(SeqSerializer {
+            items: Vec::with_capacity(len.unwrap_or_default()),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, ApiError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, ApiError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, ApiError> {
+        Ok(SeqSerializer {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer, ApiError> {
+        Ok(MapSerializer {
+            list: ByondList::new()?,
+            key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, ApiError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, ApiError> {
+        Ok(MapSerializer {
+            variant: Some(variant),
+            ..self.serialize_map(Some(len))?
+        })
+    }
+}
+
+struct SeqSerializer {
+    items: Vec<ByondValue>,
+    /// The variant the list is the data of, if any
+    variant: Option<&'static str>,
+}
+
+impl SerializeSeq for SeqSerializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        self.items.push(value.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<ByondValue, ApiError> {
+        let list = ByondList::new()?;
+        list.write(&self.items)?;
+        match self.variant {
+            Some(variant) => single_entry(variant, list.as_value()),
+            None => Ok(list.into_value()),
+        }
+    }
+}
+
+impl SerializeTuple for SeqSerializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<ByondValue, ApiError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleStruct for SeqSerializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<ByondValue, ApiError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleVariant for SeqSerializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<ByondValue, ApiError> {
+        SerializeSeq::end(self)
+    }
+}
+
+struct MapSerializer {
+    list: ByondList,
+    /// The key of the value being serialized next
+    key: Option<ByondValue>,
+    /// The variant the list is the data of, if any
+    variant: Option<&'static str>,
+}
+
+impl MapSerializer {
+    fn entry(&self, key: &ByondValue, value: &ByondValue) -> Result<(), ApiError> {
+        if key.value_type() == ValueType::NUMBER {
+            return Err(ApiError::Serde(format!(
+                "map key {key:?} is a number, which DM would take as a list index"
+            )));
+        }
+        self.list.set_assoc(key, value)
+    }
+}
+
+impl SerializeMap for MapSerializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ApiError> {
+        self.key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        let key = self.key.take().unwrap_or_default();
+        self.entry(&key, &value.serialize(Serializer)?)
+    }
+
+    fn end(self) -> Result<ByondValue, ApiError> {
+        match self.variant {
+            Some(variant) => single_entry(variant, self.list.as_value()),
+            None => Ok(self.list.into_value()),
+        }
+    }
+}
+
+impl SerializeStruct for MapSerializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ApiError> {
+        self.entry(&ByondValue::string(key)?, &value.serialize(Serializer)?)
+    }
+
+    fn end(self) -> Result<ByondValue, ApiError> {
+        SerializeMap::end(self)
+    }
+}
+
+impl SerializeStructVariant for MapSerializer {
+    type Ok = ByondValue;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ApiError> {
+        SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<ByondValue, ApiError> {
+        SerializeMap::end(self)
+    }
+}
+
+/// Deserializes from a [`ByondValue`], see [`from_value`].
+pub struct Deserializer(ByondValue);
+
+impl<'de> IntoDeserializer<'de, ApiError> for ByondValue {
+    type Deserializer = Deserializer;
+
+    fn into_deserializer(self) -> Deserializer {
+        Deserializer(self)
+    }
+}
+
+impl Deserializer {
+    fn list(&self) -> Result<ByondList, ApiError> {
+        ByondList::try_from(self.0.clone())
+    }
+
+    fn items(&self) -> Result<SeqDeserializer<vec::IntoIter<ByondValue>, ApiError>, ApiError> {
+        Ok(SeqDeserializer::new(self.list()?.iter()?))
+    }
+
+    fn pairs(
+        &self,
+    ) -> Result<MapDeserializer<'static, vec::IntoIter<(ByondValue, ByondValue)>, ApiError>, ApiError>
+    {
+        Ok(MapDeserializer::new(self.list()?.pairs()?))
+    }
+
+    fn unexpected(&self) -> Unexpected<'static> {
+        match self.0.value_type() {
+            ValueType::NULL => Unexpected::Unit,
+            ValueType::NUMBER => Unexpected::Float(self.0.as_number().unwrap_or_default().into()),
+            ValueType::STRING => Unexpected::Other("a string"),
+            ValueType::LIST => Unexpected::Other("a list"),
+            _ => Unexpected::Other("a reference"),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = ApiError;
+
+    #[allow(clippy::cast_possible_truncation, clippy::float_cmp)]
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        match self.0.value_type() {
+            ValueType::NULL => visitor.visit_unit(),
+            ValueType::NUMBER => {
+                let number = self.0.as_number().unwrap_or_default();
+                // whole numbers are visited as integers, so integer types accept them
+                let int = number as i64;
+                if int as f32 == number {
+                    visitor.visit_i64(int)
+                } else {
+                    visitor.visit_f32(number)
+                }
+            }
+            ValueType::STRING => visitor.visit_string(self.0.text()?),
+            ValueType::LIST => {
+                // a list is only a map if anything in it has an associated value
+                let pairs: Vec<_> = self.list()?.pairs()?.collect();
+                if pairs.iter().any(|(_, value)| !value.is_null()) {
+                    let mut map = MapDeserializer::new(pairs.into_iter());
+                    let value = visitor.visit_map(&mut map)?;
+                    map.end()?;
+                    Ok(value)
+                } else {
+                    let mut seq = SeqDeserializer::new(pairs.into_iter().map(|(key, _)| key));
+                    let value = visitor.visit_seq(&mut seq)?;
+                    seq.end()?;
+                    Ok(value)
+                }
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        match bool::from_byond_value(&self.0) {
+            Ok(value) => visitor.visit_bool(value),
+            Err(_) => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        if self.0.is_null() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        if self.0.value_type() != ValueType::LIST {
+            return Err(de::Error::invalid_type(self.unexpected(), &visitor));
+        }
+        let mut seq = self.items()?;
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        if self.0.value_type() != ValueType::LIST {
+            return Err(de::Error::invalid_type(self.unexpected(), &visitor));
+        }
+        let mut map = self.pairs()?;
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        match self.0.value_type() {
+            ValueType::STRING => visitor.visit_enum(self.0.text()?.into_deserializer()),
+            ValueType::LIST => {
+                let mut pairs = self.list()?.pairs()?;
+                match (pairs.next(), pairs.next()) {
+                    (Some((variant, value)), None) => visitor.visit_enum(Enum { variant, value }),
+                    _ => Err(de::Error::invalid_value(
+                        self.unexpected(),
+                        &"a list with a single key",
+                    )),
+                }
+            }
+            _ => Err(de::Error::invalid_type(self.unexpected(), &visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        // skipped values may be references, which deserialize_any would reject
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct identifier
+    }
+}
+
+/// An enum variant with data, as an assoc list of the variant name to the data
+struct Enum {
+    variant: ByondValue,
+    value: ByondValue,
+}
+
+impl<'de> EnumAccess<'de> for Enum {
+    type Error = ApiError;
+    type Variant = Deserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Deserializer), ApiError> {
+        let variant = seed.deserialize(Deserializer(self.variant))?;
+        Ok((variant, Deserializer(self.value)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Deserializer {
+    type Error = ApiError;
+
+    fn unit_variant(self) -> Result<(), ApiError> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, ApiError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, ApiError> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::ffi_v2::api::mock::MockApi;
+    use crate::ffi_v2::api::with_api;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Point,
+        Circle(f32),
+        Rect { width: u16, height: u16 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Room {
+        name: String,
+        area: Option<u32>,
+        shapes: Vec<Shape>,
+        tags: BTreeMap<String, bool>,
+        corner: (i8, i8),
+    }
+
+    #[test]
+    fn values_round_trip() {
+        with_api(Arc::new(MockApi::new()), || {
+            let room = Room {
+                name: "bar".to_string(),
+                area: None,
+                shapes: vec![
+                    Shape::Point,
+                    Shape::Circle(1.5),
+                    Shape::Rect {
+                        width: 3,
+                        height: 4,
+                    },
+                ],
+                tags: BTreeMap::from([("public".to_string(), true)]),
+                corner: (-1, 2),
+            };
+            let value = to_value(&room).unwrap();
+            assert_eq!(from_value::<Room>(&value).unwrap(), room);
+
+            // structs are assoc lists keyed by field name
+            let list = ByondList::try_from(value).unwrap();
+            let name = list
+                .get_assoc(&ByondValue::string("name").unwrap())
+                .unwrap();
+            assert_eq!(name.text().unwrap(), "bar");
+            let shapes = list
+                .get_assoc(&ByondValue::string("shapes").unwrap())
+                .unwrap();
+            let point = ByondList::try_from(shapes).unwrap().get(0).unwrap();
+            assert_eq!(point.text().unwrap(), "Point");
+        });
+    }
+
+    #[test]
+    fn dm_values_deserialize() {
+        with_api(Arc::new(MockApi::new()), || {
+            // what `list("a" = 1, "b" = list(2, 3))` would be
+            let list = ByondList::new().unwrap();
+            let numbers: ByondValue = vec![2, 3].into_byond_value().unwrap();
+            list.set_assoc(&ByondValue::string("a").unwrap(), &ByondValue::number(1.0))
+                .unwrap();
+            list.set_assoc(&ByondValue::string("b").unwrap(), &numbers)
+                .unwrap();
+
+            #[derive(Debug, PartialEq, Deserialize)]
+            #[serde(untagged)]
+            enum Any {
+                Number(u8),
+                List(Vec<Any>),
+            }
+
+            let map: HashMap<String, Any> = from_value(list.as_value()).unwrap();
+            assert_eq!(map["a"], Any::Number(1));
+            assert_eq!(map["b"], Any::List(vec![Any::Number(2), Any::Number(3)]));
+        });
+    }
+
+    #[test]
+    fn values_are_range_checked() {
+        with_api(Arc::new(MockApi::new()), || {
+            assert!(to_value(&f64::MAX).is_err());
+            assert!(to_value(&u64::MAX).is_err());
+            assert!(to_value(&HashMap::from([(1, 2)])).is_err());
+            let err = from_value::<u8>(&ByondValue::number(300.0)).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "API;SERDE;invalid value: integer `300`, expected u8"
+            );
+            assert!(from_value::<u8>(&ByondValue::number(1.5)).is_err());
+            assert!(from_value::<String>(&ByondValue::number(1.0)).is_err());
+        });
+    }
+}
@@ -0,0 +1,320 @@
+//! DM lists, through byondapi.
+//!
+//! A [`ByondList`] is a handle to a list that lives in BYOND, so it's only valid for as long as
+//! the value it was made from, and every method goes through byondapi. Indexes are 0-based, like
+//! everywhere else in Rust, and converted to DM's 1-based indexes.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use byond_fn::ffi_v2::api::{self, mock::MockApi};
+//! use byond_fn::ffi_v2::{ByondList, ByondValue};
+//!
+//! api::with_api(Arc::new(MockApi::new()), || {
+//!     let list = ByondList::new().unwrap();
+//!     let key = ByondValue::string("health").unwrap();
+//!     list.set_assoc(&key, &ByondValue::number(100.0)).unwrap();
+//!     assert_eq!(list.len().unwrap(), 1);
+//!     assert_eq!(list.get(0).unwrap(), key);
+//!     assert_eq!(list.get_assoc(&key).unwrap().as_number(), Some(100.0));
+//! });
+//! ```
+
+use std::vec;
+
+use crate::ffi_v2::api::{self, api, ApiError};
+use crate::ffi_v2::{ByondValue, CByondValue, FromByondValue, IntoByondValue, ValueType};
+use crate::str_ffi::FFIError;
+
+/// A list in BYOND, plain or associative.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ByondList(ByondValue);
+
+/// A DM list index, from a Rust index
+#[allow(clippy::cast_precision_loss)]
+fn dm_index(index: usize) -> ByondValue {
+    ByondValue::number((index + 1) as f32)
+}
+
+fn wrap_all(values: Vec<CByondValue>) -> vec::IntoIter<ByondValue> {
+    values
+        .into_iter()
+        // SAFETY: received from byondapi during this call
+        .map(|value| unsafe { ByondValue::from_raw(value) })
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+impl ByondList {
+    /// Creates an empty list.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to create the list.
+    pub fn new() -> Result<Self, ApiError> {
+        let raw = api()?.create_list()?;
+        // SAFETY: received from byondapi during this call
+        Ok(Self(unsafe { ByondValue::from_raw(raw) }))
+    }
+
+    pub fn as_value(&self) -> &ByondValue {
+        &self.0
+    }
+
+    pub fn into_value(self) -> ByondValue {
+        self.0
+    }
+
+    /// The number of items in the list, like `length(list)`.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to read the list.
+    pub fn len(&self) -> Result<usize, ApiError> {
+        Ok(api()?.read_list(self.0.as_raw())?.len())
+    }
+
+    /// # Errors
+    ///
+    /// If byondapi fails to read the list.
+    pub fn is_empty(&self) -> Result<bool, ApiError> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// The item at `index`, like `list[index + 1]`.
+    ///
+    /// # Errors
+    ///
+    /// If `index` is out of bounds.
+    pub fn get(&self, index: usize) -> Result<ByondValue, ApiError> {
+        self.read_index(&dm_index(index))
+    }
+
+    /// Replaces the item at `index`, like `list[index + 1] = value`.
+    ///
+    /// # Errors
+    ///
+    /// If `index` is out of bounds.
+    pub fn set(&self, index: usize, value: &ByondValue) -> Result<(), ApiError> {
+        self.write_index(&dm_index(index), value)
+    }
+
+    /// The value associated with `key`, like `list[key]`, or null if there is none.
+    ///
+    /// `key` can't be a number, which would be an index instead.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to read the list.
+    pub fn get_assoc(&self, key: &ByondValue) -> Result<ByondValue, ApiError> {
+        self.read_index(key)
+    }
+
+    /// Associates `value` with `key`, like `list[key] = value`, adding `key` to the list if it
+    /// isn't in it yet.
+    ///
+    /// `key` can't be a number, which would be an index instead.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to write the list.
+    pub fn set_assoc(&self, key: &ByondValue, value: &ByondValue) -> Result<(), ApiError> {
+        self.write_index(key, value)
+    }
+
+    /// The items of the list, as they are at the time of the call.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to read the list.
+    pub fn iter(&self) -> Result<vec::IntoIter<ByondValue>, ApiError> {
+        Ok(wrap_all(api()?.read_list(self.0.as_raw())?))
+    }
+
+    /// The items of the list with their associated values, as they are at the time of the call.
+    /// Items without one are paired with null.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to read the list.
+    pub fn pairs(&self) -> Result<vec::IntoIter<(ByondValue, ByondValue)>, ApiError> {
+        let pairs = api()?.read_list_assoc(self.0.as_raw())?;
+        Ok(pairs
+            .into_iter()
+            // SAFETY: received from byondapi during this call
+            .map(|(key, value)| unsafe { (ByondValue::from_raw(key), ByondValue::from_raw(value)) })
+            .collect::<Vec<_>>()
+            .into_iter())
+    }
+
+    /// Replaces every item of the list, dropping any associated values.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to write the list.
+    pub fn write(&self, items: &[ByondValue]) -> Result<(), ApiError> {
+        let items: Vec<_> = items.iter().map(|item| *item.as_raw()).collect();
+        api()?.write_list(self.0.as_raw(), &items)
+    }
+
+    /// Adds `value` to the end of the list, like `list.Add(value)`. As with `Add`, if `value` is a
+    /// list itself, its items are added instead.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to call `Add`.
+    pub fn push(&self, value: &ByondValue) -> Result<(), ApiError> {
+        api::call_proc(&self.0, "Add", std::slice::from_ref(value)).map(drop)
+    }
+
+    /// Removes the last occurrence of `value` from the list, like `list.Remove(value)`. Returns
+    /// whether it was in the list.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to call `Remove`.
+    pub fn remove(&self, value: &ByondValue) -> Result<bool, ApiError> {
+        let removed = api::call_proc(&self.0, "Remove", std::slice::from_ref(value))?;
+        Ok(removed.as_number().is_some_and(|removed| removed != 0.0))
+    }
+
+    /// Removes the item at `index`, like `list.Cut(index + 1, index + 2)`.
+    ///
+    /// # Errors
+    ///
+    /// If `index` is out of bounds.
+    pub fn remove_at(&self, index: usize) -> Result<(), ApiError> {
+        api::call_proc(&self.0, "Cut", &[dm_index(index), dm_index(index + 1)]).map(drop)
+    }
+
+    fn read_index(&self, index: &ByondValue) -> Result<ByondValue, ApiError> {
+        let value = api()?.read_list_index(self.0.as_raw(), index.as_raw())?;
+        // SAFETY: received from byondapi during this call
+        Ok(unsafe { ByondValue::from_raw(value) })
+    }
+
+    fn write_index(&self, index: &ByondValue, value: &ByondValue) -> Result<(), ApiError> {
+        api()?.write_list_index(self.0.as_raw(), index.as_raw(), value.as_raw())
+    }
+}
+
+impl TryFrom<ByondValue> for ByondList {
+    type Error = ApiError;
+
+    fn try_from(value: ByondValue) -> Result<Self, Self::Error> {
+        if value.value_type() == ValueType::LIST {
+            Ok(Self(value))
+        } else {
+            Err(ApiError::Convert {
+                expected: "a list",
+                actual: format!("{value:?}"),
+            })
+        }
+    }
+}
+
+impl From<ByondList> for ByondValue {
+    fn from(list: ByondList) -> Self {
+        list.0
+    }
+}
+
+impl FromByondValue for ByondList {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        Ok(Self::try_from(value.clone())?)
+    }
+}
+
+impl IntoByondValue for ByondList {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ffi_v2::api::mock::MockApi;
+    use crate::ffi_v2::api::with_api;
+
+    fn numbers(list: &ByondList) -> Vec<f32> {
+        Vec::from_byond_value(list.as_value()).unwrap()
+    }
+
+    #[test]
+    fn plain_lists_can_be_edited() {
+        with_api(Arc::new(MockApi::new()), || {
+            let list = ByondList::new().unwrap();
+            assert!(list.is_empty().unwrap());
+            for number in [1.0, 2.0, 3.0, 2.0] {
+                list.push(&ByondValue::number(number)).unwrap();
+            }
+            list.set(0, &ByondValue::number(5.0)).unwrap();
+            assert_eq!(numbers(&list), [5.0, 2.0, 3.0, 2.0]);
+
+            assert!(list.remove(&ByondValue::number(2.0)).unwrap());
+            assert!(!list.remove(&ByondValue::number(7.0)).unwrap());
+            assert_eq!(numbers(&list), [5.0, 2.0, 3.0]);
+            list.remove_at(1).unwrap();
+            assert_eq!(numbers(&list), [5.0, 3.0]);
+            assert!(list.get(2).is_err());
+
+            // like `Add`, pushing a list adds its items
+            let other: ByondValue = vec![8, 9].into_byond_value().unwrap();
+            list.push(&other).unwrap();
+            assert_eq!(numbers(&list), [5.0, 3.0, 8.0, 9.0]);
+        });
+    }
+
+    #[test]
+    fn assoc_lists_convert_to_maps() {
+        with_api(Arc::new(MockApi::new()), || {
+            let map = HashMap::from([("a".to_string(), 1u8), ("b".to_string(), 2)]);
+            let list =
+                ByondList::from_byond_value(&map.clone().into_byond_value().unwrap()).unwrap();
+            assert_eq!(list.len().unwrap(), 2);
+            let b = ByondValue::string("b").unwrap();
+            assert_eq!(list.get_assoc(&b).unwrap().as_number(), Some(2.0));
+            assert!(list
+                .get_assoc(&ByondValue::string("c").unwrap())
+                .unwrap()
+                .is_null());
+
+            list.set_assoc(&b, &ByondValue::number(3.0)).unwrap();
+            let converted: HashMap<String, u8> =
+                HashMap::from_byond_value(list.as_value()).unwrap();
+            assert_eq!(
+                converted,
+                HashMap::from([("a".to_string(), 1), ("b".to_string(), 3)])
+            );
+
+            // items without an associated value are paired with null
+            list.push(&ByondValue::string("c").unwrap()).unwrap();
+            let converted: HashMap<String, Option<u8>> =
+                HashMap::from_byond_value(list.as_value()).unwrap();
+            assert_eq!(converted["c"], None);
+        });
+    }
+
+    #[test]
+    fn conversions_check_types() {
+        with_api(Arc::new(MockApi::new()), || {
+            let not_a_list = ByondValue::number(1.0);
+            assert!(ByondList::from_byond_value(&not_a_list).is_err());
+            let list: ByondValue = vec![1.5].into_byond_value().unwrap();
+            let err = Vec::<u8>::from_byond_value(&list).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "@@ERR@@;API;CONVERT;Expected a whole number that fits a u8, got 1.5"
+            );
+            assert!(u8::from_byond_value(&ByondValue::number(256.0)).is_err());
+            assert_eq!(
+                u8::from_byond_value(&ByondValue::number(255.0)).unwrap(),
+                255
+            );
+            assert!(16_777_217u32.into_byond_value().is_err());
+        });
+    }
+}
@@ -0,0 +1,744 @@
+//! The v2 transport, enabled with the `ffi_v2` feature, which passes values through byondapi
+//! instead of strings. Requires BYOND 515 or later.
+//!
+//! Values from BYOND are [`ByondValue`]s. They are only valid on the main thread, and only during
+//! the call they were received or created in, so they are neither `Send` nor `Sync`. What can be
+//! done with them goes through byondapi, see [`api`].
+//!
+//! Functions exported with `#[byond_fn(v2)]` take arguments that implement [`FromByondValue`],
+//! and return a value that implements [`IntoByondValue`]. Errors are returned to BYOND as a string
+//! in the same format as with the str transport, or raised as DM runtime errors with
+//! `#[byond_fn(v2, errors = "runtime")]`, see [`runtime`].
+//!
+//! ```
+//! use std::collections::HashMap;
+//! use byond_fn::byond_fn;
+//! use byond_fn::ffi_v2::{ByondList, FromByondValue};
+//! use byond_fn::str_ffi::FFIError;
+//!
+//! #[byond_fn(v2)]
+//! pub fn total(amounts: HashMap<String, f32>) -> f32 {
+//!     amounts.values().sum()
+//! }
+//!
+//! #[byond_fn(v2)]
+//! pub fn pop(list: ByondList) -> Result<Option<f32>, FFIError> {
+//!     let Some(last) = list.len()?.checked_sub(1) else {
+//!         return Ok(None);
+//!     };
+//!     let number = f32::from_byond_value(&list.get(last)?)?;
+//!     list.remove_at(last)?;
+//!     Ok(Some(number))
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! `call_ext("example_name.dll", "byond:total")(list("a" = 1, "b" = 2)) // returns 3`
+
+pub mod api;
+pub mod byond_serde;
+pub mod list;
+pub mod refs;
+pub mod runtime;
+pub mod thread_sync;
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::slice;
+
+use crate::ffi_v2::api::ApiError;
+pub use crate::ffi_v2::list::ByondList;
+use crate::str_ffi::{error_bytes, FFIError, TransportError};
+
+/// The type of a [`ByondValue`], as byondapi reports it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValueType(pub u8);
+
+impl ValueType {
+    pub const NULL: Self = Self(0x00);
+    pub const TURF: Self = Self(0x01);
+    pub const OBJ: Self = Self(0x02);
+    pub const MOB: Self = Self(0x03);
+    pub const AREA: Self = Self(0x04);
+    pub const CLIENT: Self = Self(0x05);
+    pub const STRING: Self = Self(0x06);
+    pub const IMAGE: Self = Self(0x0D);
+    pub const WORLD: Self = Self(0x0E);
+    pub const LIST: Self = Self(0x0F);
+    pub const DATUM: Self = Self(0x21);
+    pub const NUMBER: Self = Self(0x2A);
+
+    /// Whether values of this type refer to something that lives in BYOND, rather than being
+    /// plain data
+    pub fn is_ref(self) -> bool {
+        !matches!(self, Self::NULL | Self::NUMBER)
+    }
+}
+
+impl Debug for ValueType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match *self {
+            Self::NULL => "NULL",
+            Self::TURF => "TURF",
+            Self::OBJ => "OBJ",
+            Self::MOB => "MOB",
+            Self::AREA => "AREA",
+            Self::CLIENT => "CLIENT",
+            Self::STRING => "STRING",
+            Self::IMAGE => "IMAGE",
+            Self::WORLD => "WORLD",
+            Self::LIST => "LIST",
+            Self::DATUM => "DATUM",
+            Self::NUMBER => "NUMBER",
+            Self(other) => return write!(f, "ValueType({other:#04x})"),
+        };
+        f.write_str(name)
+    }
+}
+
+/// A value as byondapi passes it across the FFI boundary.
+///
+/// `data` is either the ID of what the value refers to, or the bits of an `f32` for numbers.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CByondValue {
+    pub value_type: u8,
+    pub junk: [u8; 3],
+    pub data: u32,
+}
+
+/// A value received from or sent to BYOND.
+///
+/// Numbers and null are plain data, while anything else refers to something in BYOND that is
+/// only valid during the current call.
+#[repr(transparent)]
+#[derive(Clone)]
+pub struct ByondValue {
+    raw: CByondValue,
+    // values can only be used from the main thread
+    _not_send: PhantomData<*const ()>,
+}
+
+impl ByondValue {
+    pub const fn null() -> Self {
+        Self {
+            raw: CByondValue {
+                value_type: ValueType::NULL.0,
+                junk: [0; 3],
+                data: 0,
+            },
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn number(number: f32) -> Self {
+        Self {
+            raw: CByondValue {
+                value_type: ValueType::NUMBER.0,
+                junk: [0; 3],
+                data: number.to_bits(),
+            },
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Creates a string in BYOND.
+    ///
+    /// # Errors
+    ///
+    /// If `text` contains a NUL byte, or byondapi fails to create the string.
+    pub fn string(text: &str) -> Result<Self, ApiError> {
+        let text = api::c_name(text)?;
+        api::api()?
+            .create_string(&text)
+            .map(|raw| unsafe { Self::from_raw(raw) })
+    }
+
+    /// Wraps a value received from byondapi.
+    ///
+    /// # Safety
+    /// Unless it's a number or null, `raw` must be a value byondapi gave out during the current
+    /// call, and must only be used on the main thread.
+    pub unsafe fn from_raw(raw: CByondValue) -> Self {
+        Self {
+            raw: CByondValue {
+                junk: [0; 3],
+                ..raw
+            },
+            _not_send: PhantomData,
+        }
+    }
+
+    pub fn as_raw(&self) -> &CByondValue {
+        &self.raw
+    }
+
+    pub fn into_raw(self) -> CByondValue {
+        self.raw
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        ValueType(self.raw.value_type)
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.value_type() == ValueType::NULL
+    }
+
+    pub fn as_number(&self) -> Option<f32> {
+        (self.value_type() == ValueType::NUMBER).then(|| f32::from_bits(self.raw.data))
+    }
+
+    /// The value as DM would turn it into text, like `"[value]"`.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to convert the value.
+    pub fn text(&self) -> Result<String, ApiError> {
+        let bytes = api::api()?.to_string(&self.raw)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Reads the var `name`, like `value.name`, converted to `T`.
+    ///
+    /// ```no_run
+    /// use byond_fn::ffi_v2::ByondValue;
+    /// use byond_fn::str_ffi::FFIError;
+    ///
+    /// fn position(atom: &ByondValue) -> Result<(u16, u16, u16), FFIError> {
+    ///     Ok((atom.get_var("x")?, atom.get_var("y")?, atom.get_var("z")?))
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If there is no such var, or its value can't be converted to `T`.
+    pub fn get_var<T: FromByondValue>(&self, name: &str) -> Result<T, FFIError> {
+        T::from_byond_value(&api::read_var(self, name)?)
+    }
+
+    /// Sets the var `name`, like `value.name = new_value`.
+    ///
+    /// # Errors
+    ///
+    /// If there is no such var, or `new_value` can't be converted.
+    pub fn set_var(&self, name: &str, new_value: impl IntoByondValue) -> Result<(), FFIError> {
+        Ok(api::write_var(self, name, &new_value.into_byond_value()?)?)
+    }
+
+    /// Calls the proc `name`, like `value.name(args...)`. The args are a tuple or array of values
+    /// that implement [`IntoByondValue`], see [`IntoByondArgs`].
+    ///
+    /// ```no_run
+    /// use byond_fn::ffi_v2::ByondValue;
+    /// use byond_fn::str_ffi::FFIError;
+    ///
+    /// fn say(mob: &ByondValue, message: &str) -> Result<(), FFIError> {
+    ///     mob.call_proc("say", (message, 1.5))?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If there is no such proc, or the args can't be converted.
+    pub fn call_proc(&self, name: &str, args: impl IntoByondArgs) -> Result<ByondValue, FFIError> {
+        Ok(api::call_proc(self, name, &args.into_byond_args()?)?)
+    }
+
+    /// Calls the global proc `name`, like `global.name(args...)`. See
+    /// [`call_proc`](Self::call_proc) for the args.
+    ///
+    /// # Errors
+    ///
+    /// If there is no such proc, or the args can't be converted.
+    pub fn call_global_proc(name: &str, args: impl IntoByondArgs) -> Result<ByondValue, FFIError> {
+        Ok(api::call_global_proc(name, &args.into_byond_args()?)?)
+    }
+}
+
+impl Default for ByondValue {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl PartialEq for ByondValue {
+    /// Whether both values are the same number, or refer to the same thing
+    fn eq(&self, other: &Self) -> bool {
+        match (self.as_number(), other.as_number()) {
+            (Some(number), Some(other)) => number == other,
+            _ => self.raw.value_type == other.raw.value_type && self.raw.data == other.raw.data,
+        }
+    }
+}
+
+impl Debug for ByondValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.value_type() {
+            ValueType::NULL => f.write_str("null"),
+            ValueType::NUMBER => write!(f, "{}", f32::from_bits(self.raw.data)),
+            value_type => write!(f, "{value_type:?}({:#x})", self.raw.data),
+        }
+    }
+}
+
+/// Turns the `argc` and `argv` arguments of a v2 function into a slice of values.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Safety
+/// Unless `argv` is null, it must point to `argc` values, which byondapi gave out during the
+/// current call.
+pub unsafe fn parse_args<'a>(argc: u32, argv: *const CByondValue) -> &'a [ByondValue] {
+    if argv.is_null() {
+        return &[];
+    }
+    // SAFETY: `ByondValue` is a transparent wrapper around `CByondValue`
+    unsafe { slice::from_raw_parts(argv.cast(), argc as usize) }
+}
+
+/// A function to prep a value for returning to BYOND from a v2 function.
+///
+/// Errors are returned as a string, formatted like with the str transport. If even that string
+/// can't be created, null is returned instead.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub fn byond_return(value: impl IntoByondValue) -> CByondValue {
+    value
+        .into_byond_value()
+        .or_else(|err| ByondValue::string(&String::from_utf8_lossy(&error_bytes(&err))))
+        .map(ByondValue::into_raw)
+        .unwrap_or_default()
+}
+
+fn convert_error(expected: &'static str, actual: &ByondValue) -> FFIError {
+    ApiError::Convert {
+        expected,
+        actual: format!("{actual:?}"),
+    }
+    .into()
+}
+
+/// Represents a type that can be converted from a value received from BYOND
+pub trait FromByondValue: Sized {
+    /// Converts the value.
+    ///
+    /// # Errors
+    ///
+    /// If the value isn't of the expected type, this should return an `ApiError::Convert`.
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError>;
+
+    /// Maps an argument to a type. Handles error cases.
+    fn map_arg(
+        arg: Option<&ByondValue>,
+        expected_min: usize,
+        expected_max: usize,
+        arg_name: &str,
+        arg_num: usize,
+    ) -> Result<Self, FFIError> {
+        let Some(arg) = arg else {
+            return Err(FFIError::TransportError(TransportError::WrongArgCount {
+                expected_min,
+                expected_max,
+                got: arg_num,
+            }));
+        };
+        Self::from_byond_value(arg).map_err(|err| match err {
+            FFIError::ApiError(ApiError::Convert { .. }) => {
+                FFIError::TransportError(TransportError::ArgParse {
+                    arg_name: arg_name.to_string(),
+                    actual_content: format!("{arg:?}"),
+                })
+            }
+            err => err,
+        })
+    }
+}
+
+/// Represents a type that can be converted into a value returned to BYOND
+pub trait IntoByondValue {
+    /// Converts the value. Anything that isn't a number or null is created through byondapi.
+    ///
+    /// # Errors
+    ///
+    /// If BYOND can't hold the value, or byondapi fails to create it.
+    fn into_byond_value(self) -> Result<ByondValue, FFIError>;
+}
+
+impl FromByondValue for ByondValue {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoByondValue for ByondValue {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(self)
+    }
+}
+
+impl IntoByondValue for &ByondValue {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(self.clone())
+    }
+}
+
+impl IntoByondValue for () {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(ByondValue::null())
+    }
+}
+
+impl FromByondValue for f32 {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        value
+            .as_number()
+            .ok_or_else(|| convert_error("a number", value))
+    }
+}
+
+impl IntoByondValue for f32 {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(ByondValue::number(self))
+    }
+}
+
+impl FromByondValue for f64 {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        f32::from_byond_value(value).map(f64::from)
+    }
+}
+
+impl IntoByondValue for f64 {
+    #[allow(clippy::cast_possible_truncation)]
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        let number = self as f32;
+        if number.is_infinite() && self.is_finite() {
+            return Err(ApiError::Convert {
+                expected: "a number in the range of a BYOND number",
+                actual: self.to_string(),
+            }
+            .into());
+        }
+        Ok(ByondValue::number(number))
+    }
+}
+
+macro_rules! impl_byond_value_int {
+    ($($ty:ty),*) => {
+        $(
+            impl FromByondValue for $ty {
+                #[allow(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_precision_loss,
+                    clippy::cast_sign_loss,
+                    clippy::float_cmp,
+                )]
+                fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+                    let number = value.as_number().map(f64::from);
+                    // `MAX + 1` is a power of two, so unlike `MAX` it's exact as a float
+                    match number {
+                        Some(number)
+                            if number.fract() == 0.0
+                                && number >= <$ty>::MIN as f64
+                                && number < <$ty>::MAX as f64 + 1.0 =>
+                        {
+                            Ok(number as $ty)
+                        }
+                        _ => Err(convert_error(concat!("a whole number that fits a ", stringify!($ty)), value)),
+                    }
+                }
+            }
+
+            impl IntoByondValue for $ty {
+                #[allow(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_precision_loss,
+                    clippy::cast_lossless,
+                    clippy::cast_possible_wrap,
+                )]
+                fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+                    let number = self as f32;
+                    if number as i128 != self as i128 {
+                        return Err(ApiError::Convert {
+                            expected: "an integer BYOND can hold exactly",
+                            actual: self.to_string(),
+                        }
+                        .into());
+                    }
+                    Ok(ByondValue::number(number))
+                }
+            }
+        )*
+    };
+}
+
+impl_byond_value_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromByondValue for bool {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        match value.as_number() {
+            Some(number) => Ok(number != 0.0),
+            None if value.is_null() => Ok(false),
+            None => Err(convert_error("a number or null", value)),
+        }
+    }
+}
+
+impl IntoByondValue for bool {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(ByondValue::number(if self { 1.0 } else { 0.0 }))
+    }
+}
+
+impl FromByondValue for String {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        if value.value_type() != ValueType::STRING {
+            return Err(convert_error("a string", value));
+        }
+        Ok(value.text()?)
+    }
+}
+
+impl IntoByondValue for String {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        self.as_str().into_byond_value()
+    }
+}
+
+impl IntoByondValue for &str {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Ok(ByondValue::string(self)?)
+    }
+}
+
+impl<T: FromByondValue> FromByondValue for Option<T> {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        if value.is_null() {
+            Ok(None)
+        } else {
+            T::from_byond_value(value).map(Some)
+        }
+    }
+
+    fn map_arg(
+        arg: Option<&ByondValue>,
+        expected_min: usize,
+        expected_max: usize,
+        arg_name: &str,
+        arg_num: usize,
+    ) -> Result<Self, FFIError> {
+        match arg {
+            Some(arg) if !arg.is_null() => {
+                T::map_arg(Some(arg), expected_min, expected_max, arg_name, arg_num).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<T: IntoByondValue> IntoByondValue for Option<T> {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        self.map_or_else(|| Ok(ByondValue::null()), IntoByondValue::into_byond_value)
+    }
+}
+
+impl<T: FromByondValue> FromByondValue for Vec<T> {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        ByondList::from_byond_value(value)?
+            .iter()?
+            .map(|item| T::from_byond_value(&item))
+            .collect()
+    }
+}
+
+impl<T: IntoByondValue> IntoByondValue for Vec<T> {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        let items = self
+            .into_iter()
+            .map(IntoByondValue::into_byond_value)
+            .collect::<Result<Vec<_>, _>>()?;
+        let list = ByondList::new()?;
+        list.write(&items)?;
+        Ok(list.into_value())
+    }
+}
+
+impl<K, V> FromByondValue for HashMap<K, V>
+where
+    K: FromByondValue + Eq + Hash,
+    V: FromByondValue,
+{
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        ByondList::from_byond_value(value)?
+            .pairs()?
+            .map(|(key, value)| Ok((K::from_byond_value(&key)?, V::from_byond_value(&value)?)))
+            .collect()
+    }
+}
+
+impl<K: IntoByondValue, V: IntoByondValue> IntoByondValue for HashMap<K, V> {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        let list = ByondList::new()?;
+        for (key, value) in self {
+            list.set_assoc(&key.into_byond_value()?, &value.into_byond_value()?)?;
+        }
+        Ok(list.into_value())
+    }
+}
+
+impl IntoByondValue for FFIError {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Err(self)
+    }
+}
+
+impl IntoByondValue for TransportError {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        Err(FFIError::TransportError(self))
+    }
+}
+
+impl<T, E> IntoByondValue for Result<T, E>
+where
+    T: IntoByondValue,
+    E: std::error::Error + 'static,
+{
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        match self {
+            Ok(inner) => inner.into_byond_value(),
+            Err(err) => Err(FFIError::OtherError(Box::new(err))),
+        }
+    }
+}
+
+impl<T: IntoByondValue> IntoByondValue for Result<T, FFIError> {
+    fn into_byond_value(self) -> Result<ByondValue, FFIError> {
+        self.and_then(IntoByondValue::into_byond_value)
+    }
+}
+
+/// Represents the args of a proc call: a tuple of values that implement [`IntoByondValue`], with
+/// `()` for none, or an array or `Vec` of them.
+pub trait IntoByondArgs {
+    /// Converts each arg.
+    ///
+    /// # Errors
+    ///
+    /// If any of them can't be converted.
+    fn into_byond_args(self) -> Result<Vec<ByondValue>, FFIError>;
+}
+
+macro_rules! impl_byond_args_tuple {
+    ($(($($arg:ident),*)),*) => {
+        $(
+            impl<$($arg: IntoByondValue),*> IntoByondArgs for ($($arg,)*) {
+                #[allow(non_snake_case)]
+                fn into_byond_args(self) -> Result<Vec<ByondValue>, FFIError> {
+                    let ($($arg,)*) = self;
+                    Ok(vec![$($arg.into_byond_value()?),*])
+                }
+            }
+        )*
+    };
+}
+
+impl_byond_args_tuple!(
+    (),
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F)
+);
+
+impl<T: IntoByondValue, const N: usize> IntoByondArgs for [T; N] {
+    fn into_byond_args(self) -> Result<Vec<ByondValue>, FFIError> {
+        self.into_iter()
+            .map(IntoByondValue::into_byond_value)
+            .collect()
+    }
+}
+
+impl<T: IntoByondValue> IntoByondArgs for Vec<T> {
+    fn into_byond_args(self) -> Result<Vec<ByondValue>, FFIError> {
+        self.into_iter()
+            .map(IntoByondValue::into_byond_value)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::byond_fn;
+    use crate::ffi_v2::api::mock::MockApi;
+    use crate::ffi_v2::api::with_api;
+
+    #[byond_fn(v2)]
+    fn scale(numbers: Vec<f32>, by: Option<f32>) -> Vec<f32> {
+        let by = by.unwrap_or(2.0);
+        numbers.into_iter().map(|number| number * by).collect()
+    }
+
+    fn call(args: &[ByondValue]) -> ByondValue {
+        let argc = u32::try_from(args.len()).unwrap();
+        // SAFETY: the mock isn't tied to a thread or call
+        unsafe { ByondValue::from_raw(__byond_fn_scale::scale(argc, args.as_ptr().cast())) }
+    }
+
+    #[test]
+    fn shims_convert_args_and_returns() {
+        with_api(Arc::new(MockApi::new()), || {
+            let numbers = vec![1.0, 2.0].into_byond_value().unwrap();
+            let scaled = call(&[numbers.clone(), ByondValue::number(3.0)]);
+            assert_eq!(Vec::<f32>::from_byond_value(&scaled).unwrap(), [3.0, 6.0]);
+            let scaled = call(&[numbers.clone(), ByondValue::null()]);
+            assert_eq!(Vec::<f32>::from_byond_value(&scaled).unwrap(), [2.0, 4.0]);
+
+            let err = call(&[]).text().unwrap();
+            assert_eq!(err, "@@ERR@@;FFI;Expected 1-2 args, got 0");
+            let err = call(&[ByondValue::number(1.0)]).text().unwrap();
+            assert_eq!(
+                err,
+                "@@ERR@@;FFI;ARG_PARSE;Failed to parse argument \"numbers\" (content was \"1\")"
+            );
+        });
+    }
+
+    #[test]
+    fn vars_and_procs_convert_values() {
+        let mock = Arc::new(MockApi::new());
+        mock.define_proc("step", |src, args| {
+            // mock procs can only fail with an `ApiError`
+            let x: u16 = src.get_var("x").unwrap();
+            let by = u16::from_byond_value(&args[0]).unwrap();
+            src.set_var("x", x + by).unwrap();
+            Ok(ByondValue::null())
+        });
+        mock.define_global_proc("get_turf", {
+            let mock = mock.clone();
+            move |_, _| {
+                let turf = mock.new_datum("/turf");
+                turf.set_var("x", 5).unwrap();
+                Ok(turf)
+            }
+        });
+        with_api(mock, || {
+            let turf = ByondValue::call_global_proc("get_turf", ()).unwrap();
+            turf.call_proc("step", (2,)).unwrap();
+            turf.call_proc("step", [&ByondValue::number(1.0)]).unwrap();
+            assert_eq!(turf.get_var::<u16>("x").unwrap(), 8);
+            let err = turf.call_proc("step", (u64::MAX,)).unwrap_err();
+            assert!(err.to_string().contains("CONVERT"), "{err}");
+            assert!(turf.get_var::<String>("x").is_err());
+            assert!(turf.get_var::<u16>("y").is_err());
+        });
+    }
+}
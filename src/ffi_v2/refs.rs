@@ -0,0 +1,278 @@
+//! Keeping references to BYOND values beyond the current call.
+//!
+//! A [`ByondValue`] is only valid during the call it was received in, as BYOND may delete what it
+//! refers to once DM holds no references to it. A [`ByondRefGuard`] holds a reference of its own,
+//! keeping the value alive until the guard is dropped, and can be stored anywhere, including
+//! other threads. The value itself can only be taken out of the guard on the thread that created
+//! it, so other threads have to do so in a [`thread_sync`](crate::ffi_v2::thread_sync) closure.
+//!
+//! A [`ByondWeakRef`] doesn't keep the value alive, and instead detects when it was deleted.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use byond_fn::ffi_v2::api::{self, mock::MockApi};
+//! use byond_fn::ffi_v2::refs::ByondRefGuard;
+//!
+//! let mock = Arc::new(MockApi::new());
+//! api::with_api(mock.clone(), || {
+//!     let mob = mock.new_datum("/mob");
+//!     let guard = ByondRefGuard::new(&mob).unwrap();
+//!     assert_eq!(mock.ref_count(&mob), 1);
+//!     drop(guard);
+//!     assert_eq!(mock.ref_count(&mob), 0);
+//! });
+//! ```
+//!
+//! In debug builds, every guard that is alive is tracked, see [`live_refs`].
+
+use std::fmt::{Debug, Formatter};
+use std::thread::{self, ThreadId};
+
+use crate::ffi_v2::api::{api, ApiError, SharedApi};
+use crate::ffi_v2::{ByondValue, CByondValue, FromByondValue, ValueType};
+use crate::str_ffi::FFIError;
+
+/// An owned reference to a BYOND value, which keeps it alive until dropped.
+///
+/// The reference is released on the thread the guard was created on, which should be the main
+/// thread. If the guard is dropped on any other thread, releasing it is queued to run on the
+/// main thread.
+///
+/// Guards can't be cloned, as that would have to add a reference off the main thread. Share one
+/// with an `Arc` instead.
+pub struct ByondRefGuard {
+    raw: CByondValue,
+    api: SharedApi,
+    owner: ThreadId,
+    #[cfg(debug_assertions)]
+    id: u64,
+}
+
+impl ByondRefGuard {
+    /// Adds a reference to `value`. Numbers and null don't need one, and are held as-is.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to add the reference.
+    pub fn new(value: &ByondValue) -> Result<Self, ApiError> {
+        let api = api()?;
+        let raw = *value.as_raw();
+        if value.value_type().is_ref() {
+            api.inc_ref(&raw)?;
+        }
+        Ok(Self {
+            raw,
+            api,
+            owner: thread::current().id(),
+            #[cfg(debug_assertions)]
+            id: leaks::track(raw),
+        })
+    }
+
+    /// The value, on the thread the guard was created on.
+    ///
+    /// # Errors
+    ///
+    /// [`ApiError::NotMainThread`] on any other thread.
+    pub fn value(&self) -> Result<ByondValue, ApiError> {
+        if thread::current().id() != self.owner {
+            return Err(ApiError::NotMainThread);
+        }
+        // SAFETY: the reference held by the guard keeps the value valid
+        Ok(unsafe { ByondValue::from_raw(self.raw) })
+    }
+
+    /// A weak reference to the same value.
+    pub fn downgrade(&self) -> ByondWeakRef {
+        ByondWeakRef { raw: self.raw }
+    }
+}
+
+impl Debug for ByondRefGuard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ByondRefGuard").field(&self.raw).finish()
+    }
+}
+
+impl Drop for ByondRefGuard {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        leaks::untrack(self.id);
+        if !ValueType(self.raw.value_type).is_ref() {
+            return;
+        }
+        if thread::current().id() == self.owner {
+            let _ = self.api.dec_ref(&self.raw);
+            return;
+        }
+        let raw = self.raw;
+        let api = self.api.clone();
+        // if even this fails, BYOND is shutting down and the reference doesn't matter anymore
+        let _ = self.api.thread_sync(
+            Box::new(move || {
+                let _ = api.dec_ref(&raw);
+            }),
+            false,
+        );
+    }
+}
+
+impl FromByondValue for ByondRefGuard {
+    fn from_byond_value(value: &ByondValue) -> Result<Self, FFIError> {
+        Ok(Self::new(value)?)
+    }
+}
+
+/// A reference to a BYOND value that doesn't keep it alive, but can tell whether it was deleted.
+///
+/// BYOND reuses the IDs of deleted values, so a value created after the original was deleted may
+/// be mistaken for it, if it's of the same type and got the same ID.
+#[derive(Clone, Copy, Debug)]
+pub struct ByondWeakRef {
+    raw: CByondValue,
+}
+
+impl ByondWeakRef {
+    pub fn new(value: &ByondValue) -> Self {
+        Self {
+            raw: *value.as_raw(),
+        }
+    }
+
+    /// The value, if it still exists. Must only be used on the main thread.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to test the reference.
+    pub fn get(&self) -> Result<Option<ByondValue>, ApiError> {
+        if !api()?.test_ref(&self.raw)? {
+            return Ok(None);
+        }
+        // SAFETY: byondapi reported the value still exists
+        Ok(Some(unsafe { ByondValue::from_raw(self.raw) }))
+    }
+
+    /// A guard keeping the value alive, if it still exists.
+    ///
+    /// # Errors
+    ///
+    /// If byondapi fails to test the reference, or to add one.
+    pub fn upgrade(&self) -> Result<Option<ByondRefGuard>, ApiError> {
+        self.get()?
+            .map(|value| ByondRefGuard::new(&value))
+            .transpose()
+    }
+}
+
+#[cfg(debug_assertions)]
+pub use leaks::{live_refs, LiveRef};
+
+#[cfg(debug_assertions)]
+mod leaks {
+    use std::backtrace::Backtrace;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+    use crate::ffi_v2::CByondValue;
+
+    /// A [`ByondRefGuard`](super::ByondRefGuard) that hasn't been dropped yet.
+    #[derive(Clone, Debug)]
+    pub struct LiveRef {
+        pub value: CByondValue,
+        /// Where the guard was created, if backtraces are enabled with `RUST_BACKTRACE`
+        pub created_at: Arc<Backtrace>,
+        pub(super) id: u64,
+    }
+
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    fn live() -> MutexGuard<'static, HashMap<u64, LiveRef>> {
+        static LIVE: OnceLock<Mutex<HashMap<u64, LiveRef>>> = OnceLock::new();
+        LIVE.get_or_init(Mutex::default)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(super) fn track(value: CByondValue) -> u64 {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let created_at = Arc::new(Backtrace::capture());
+        live().insert(
+            id,
+            LiveRef {
+                value,
+                created_at,
+                id,
+            },
+        );
+        id
+    }
+
+    pub(super) fn untrack(id: u64) {
+        live().remove(&id);
+    }
+
+    /// Every guard that is alive, in debug builds only. Any still alive when they shouldn't be,
+    /// such as after the world was rebooted, are leaks.
+    pub fn live_refs() -> Vec<LiveRef> {
+        let mut refs: Vec<_> = live().values().cloned().collect();
+        refs.sort_by_key(|live| live.id);
+        refs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ffi_v2::api::mock::MockApi;
+    use crate::ffi_v2::api::with_api;
+
+    #[test]
+    fn off_thread_drops_are_released_on_the_main_thread() {
+        let mock = Arc::new(MockApi::new());
+        with_api(mock.clone(), || {
+            let mob = mock.new_datum("/mob");
+            let guard = ByondRefGuard::new(&mob).unwrap();
+            let other = ByondRefGuard::new(&mob).unwrap();
+            assert_eq!(mock.ref_count(&mob), 2);
+            #[cfg(debug_assertions)]
+            assert!(live_refs().iter().any(|live| live.id == guard.id));
+
+            drop(other);
+            assert_eq!(mock.ref_count(&mob), 1);
+            #[cfg(debug_assertions)]
+            let id = guard.id;
+            std::thread::spawn(move || {
+                assert!(matches!(guard.value(), Err(ApiError::NotMainThread)));
+                drop(guard);
+            })
+            .join()
+            .unwrap();
+            // still referenced until the main thread gets to it
+            assert_eq!(mock.ref_count(&mob), 1);
+            #[cfg(debug_assertions)]
+            assert!(!live_refs().iter().any(|live| live.id == id));
+            assert_eq!(mock.run_jobs(), 1);
+            assert_eq!(mock.ref_count(&mob), 0);
+        });
+    }
+
+    #[test]
+    fn weak_refs_detect_deletion() {
+        let mock = Arc::new(MockApi::new());
+        with_api(mock.clone(), || {
+            let mob = mock.new_datum("/mob");
+            let weak = ByondWeakRef::new(&mob);
+            assert_eq!(mock.ref_count(&mob), 0);
+            let guard = weak.upgrade().unwrap().unwrap();
+            assert_eq!(guard.value().unwrap(), mob);
+            drop(guard);
+
+            mock.delete(&mob);
+            assert!(weak.get().unwrap().is_none());
+            assert!(weak.upgrade().unwrap().is_none());
+        });
+    }
+}
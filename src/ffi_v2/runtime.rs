@@ -0,0 +1,126 @@
+//! Raising errors as DM runtime errors.
+//!
+//! By default, errors are returned to BYOND as a string, which DM code has to check for. With
+//! `#[byond_fn(v2, errors = "runtime")]`, returned errors, argument errors and panics are instead
+//! raised as a runtime error in the proc that called the function, through byondapi's
+//! `Byond_CRASH`. The runtime shows up in the runtime log with the DM stack trace, and can be
+//! caught with `try`/`catch`. Its message is the error string that would otherwise have been
+//! returned.
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//! use byond_fn::ffi_v2::ByondValue;
+//! use byond_fn::str_ffi::FFIError;
+//!
+//! // `call_ext("example_name.dll", "byond:health")(1)` raises a runtime instead of returning an error
+//! #[byond_fn(v2, errors = "runtime")]
+//! pub fn health(mob: ByondValue) -> Result<f32, FFIError> {
+//!     mob.get_var("health")
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! Hosts without `Byond_CRASH` get the error returned as a string instead.
+
+use std::any::Any;
+use std::ffi::CString;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::ffi_v2::api::api;
+use crate::ffi_v2::{ByondValue, CByondValue, IntoByondValue};
+use crate::str_ffi::{error_bytes, FFIError};
+
+/// Converts a value to return to BYOND, keeping any error to be raised by [`call_or_crash`].
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If `value` is an error, or can't be converted.
+pub fn try_return(value: impl IntoByondValue) -> Result<CByondValue, FFIError> {
+    value.into_byond_value().map(ByondValue::into_raw)
+}
+
+/// Runs the body of a function, raising a runtime error if it returns an error or panics.
+///
+/// This is used internally, but is exposed in case you want the same functionality. As the runtime
+/// unwinds back into BYOND, it must only be called from an `extern "C-unwind"` function BYOND
+/// called.
+pub fn call_or_crash(f: impl FnOnce() -> Result<CByondValue, FFIError>) -> CByondValue {
+    let err = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => return value,
+        Ok(Err(err)) => err,
+        Err(panic) => FFIError::OtherError(format!("panicked: {}", panic_message(&*panic)).into()),
+    };
+    // NUL bytes are already dropped from the error string
+    let message = CString::new(error_bytes(&err)).unwrap_or_default();
+    drop(err);
+    if let Ok(api) = api() {
+        // only returns if the host can't raise runtimes
+        api.crash(&message);
+    }
+    ByondValue::string(&message.to_string_lossy())
+        .map(ByondValue::into_raw)
+        .unwrap_or_default()
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::byond_fn;
+    use crate::ffi_v2::api::mock::{MockApi, MockRuntime};
+    use crate::ffi_v2::api::with_api;
+    use crate::ffi_v2::FromByondValue;
+
+    #[byond_fn(v2, errors = "runtime")]
+    fn halve(number: u32) -> Result<u32, FFIError> {
+        if number == 13 {
+            panic!("unlucky number");
+        }
+        if number.is_multiple_of(2) {
+            Ok(number / 2)
+        } else {
+            Err(FFIError::OtherError(format!("{number} is odd").into()))
+        }
+    }
+
+    fn call(args: &[ByondValue]) -> Result<ByondValue, String> {
+        let argc = u32::try_from(args.len()).unwrap();
+        catch_unwind(AssertUnwindSafe(|| {
+            // SAFETY: the mock isn't tied to a thread or call
+            unsafe { ByondValue::from_raw(__byond_fn_halve::halve(argc, args.as_ptr().cast())) }
+        }))
+        .map_err(|panic| panic.downcast::<MockRuntime>().unwrap().message)
+    }
+
+    #[test]
+    fn errors_are_raised_as_runtimes() {
+        with_api(Arc::new(MockApi::new()), || {
+            let halved = call(&[ByondValue::number(4.0)]).unwrap();
+            assert_eq!(u32::from_byond_value(&halved).unwrap(), 2);
+
+            assert_eq!(
+                call(&[ByondValue::number(3.0)]).unwrap_err(),
+                "@@ERR@@;3 is odd"
+            );
+            assert_eq!(call(&[]).unwrap_err(), "@@ERR@@;FFI;Expected 1 args, got 0");
+            assert_eq!(
+                call(&[ByondValue::number(13.0)]).unwrap_err(),
+                "@@ERR@@;panicked: unlucky number"
+            );
+        });
+    }
+}
@@ -0,0 +1,149 @@
+//! Running code on BYOND's main thread from other threads.
+//!
+//! BYOND only allows byondapi to be used from its main thread, so a background thread has to hand
+//! anything that touches BYOND over to it, either waiting for the result with [`run`], or not with
+//! [`queue`]. BYOND runs the handed over closures between ticks.
+//!
+//! [`ByondValue`](crate::ffi_v2::ByondValue)s can't be sent between threads, so they can't leave
+//! the closure, and must be turned into plain Rust data first:
+//!
+//! ```compile_fail
+//! use byond_fn::ffi_v2::{api, thread_sync};
+//!
+//! std::thread::spawn(|| {
+//!     // doesn't compile, the value would be used off the main thread
+//!     let value = thread_sync::run(|| api::call_global_proc("get_mob", &[]).unwrap());
+//! });
+//! ```
+//!
+//! ```no_run
+//! use byond_fn::ffi_v2::{api, thread_sync};
+//!
+//! std::thread::spawn(|| {
+//!     let health = thread_sync::run(|| {
+//!         let mob = api::call_global_proc("get_mob", &[])?;
+//!         api::read_var(&mob, "health").map(|health| health.as_number())
+//!     });
+//! });
+//! ```
+//!
+//! With a [`MockApi`](crate::ffi_v2::api::mock::MockApi), set for the background threads too with
+//! [`with_api`](crate::ffi_v2::api::with_api), the closures are queued until the test runs them
+//! with [`MockApi::run_jobs`](crate::ffi_v2::api::mock::MockApi::run_jobs).
+
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::mpsc;
+
+use crate::ffi_v2::api::{any_thread_api, ApiError};
+
+/// Runs `f` on the main thread, and waits for it to return.
+///
+/// A panic in `f` is passed on to the calling thread. Must not be called from the main thread
+/// itself, which would wait for itself forever.
+///
+/// # Errors
+///
+/// If byondapi isn't available, or BYOND dropped `f` without running it.
+pub fn run<R, F>(f: F) -> Result<R, ApiError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (sender, receiver) = mpsc::sync_channel(1);
+    any_thread_api()?.thread_sync(
+        Box::new(move || {
+            let _ = sender.send(catch_unwind(AssertUnwindSafe(f)));
+        }),
+        true,
+    )?;
+    match receiver.recv() {
+        Ok(Ok(returned)) => Ok(returned),
+        Ok(Err(panic)) => resume_unwind(panic),
+        Err(_) => Err(ApiError::Failed {
+            function: "Byond_ThreadSync",
+            message: "the closure was dropped without running".to_string(),
+        }),
+    }
+}
+
+/// Queues `f` to run on the main thread, without waiting for it.
+///
+/// A panic in `f` is discarded, as there is nothing to pass it on to.
+///
+/// # Errors
+///
+/// If byondapi isn't available.
+pub fn queue<F>(f: F) -> Result<(), ApiError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    any_thread_api()?.thread_sync(
+        Box::new(move || {
+            let _ = catch_unwind(AssertUnwindSafe(f));
+        }),
+        false,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::ffi_v2::api::mock::MockApi;
+    use crate::ffi_v2::api::{read_var, with_api, write_var};
+    use crate::ffi_v2::ByondValue;
+
+    #[test]
+    fn background_threads_wait_for_the_main_thread() {
+        let mock = Arc::new(MockApi::new());
+        let queued = Arc::new(AtomicUsize::new(0));
+        with_api(mock.clone(), || {
+            let mob = mock.new_datum("/mob");
+            write_var(&mob, "health", &ByondValue::number(10.0)).unwrap();
+            let mob = mob.into_raw();
+
+            let background = thread::spawn({
+                let mock = mock.clone();
+                let queued = queued.clone();
+                move || {
+                    with_api(mock, || {
+                        queue(move || {
+                            queued.fetch_add(1, Ordering::Relaxed);
+                        })
+                        .unwrap();
+                        run(move || {
+                            // SAFETY: the mock doesn't care which thread values are used on
+                            let mob = unsafe { ByondValue::from_raw(mob) };
+                            read_var(&mob, "health").unwrap().as_number()
+                        })
+                    })
+                }
+            });
+
+            // stand in for BYOND's ticks until the background thread is done
+            while !background.is_finished() {
+                mock.run_jobs();
+                thread::yield_now();
+            }
+            assert_eq!(background.join().unwrap().unwrap(), Some(10.0));
+            assert_eq!(queued.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn dropped_jobs_are_an_error() {
+        let mock = Arc::new(MockApi::new());
+        let background = thread::spawn({
+            let mock = mock.clone();
+            move || with_api(mock, || run(|| 1))
+        });
+        // drop the job once it's queued, instead of running it
+        while mock.drop_jobs() == 0 {
+            thread::yield_now();
+        }
+        assert!(background.join().unwrap().is_err());
+    }
+}
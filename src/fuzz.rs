@@ -0,0 +1,59 @@
+//! Fuzzing support, enabled with the `fuzzing` feature.
+//!
+//! With the feature enabled, every `#[byond_fn]` also generates a fuzz entry point next to it,
+//! named after its generated module with a `_fuzz` suffix (`__byond_fn_add_fuzz` for `add`). The
+//! entry point takes arbitrary bytes, feeds them to the real exported function as its arguments,
+//! and asserts that it returns a NUL terminated string that is valid in the function's encoding. A
+//! function returning raw bytes that aren't should wrap them in
+//! [`Base64`](crate::str_ffi::binary::Base64) or [`Hex`](crate::str_ffi::binary::Hex). Since a panic can't unwind out of an exported
+//! function, any panic aborts, which the fuzzer reports as a crash.
+//!
+//! A `cargo fuzz` target only needs to forward its input:
+//!
+//! ```ignore
+//! #![no_main]
+//!
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| my_crate::__byond_fn_add_fuzz(data));
+//! ```
+//!
+//! The `fuzz` directory of this repository has targets for the transport itself.
+
+use std::ffi::{c_char, c_int, CString};
+
+use crate::str_ffi::encoding::Encoding;
+use crate::str_ffi::returned_bytes;
+
+/// The signature of every function generated by `#[byond_fn]` for string transport
+pub type StrShim = unsafe extern "C" fn(c_int, *const *const c_char) -> *const c_char;
+
+/// Splits fuzzer input into arguments on NUL bytes, since arguments from BYOND can't contain them.
+///
+/// Empty input is no arguments at all.
+pub fn split_args(data: &[u8]) -> Vec<CString> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    data.split(|&byte| byte == 0)
+        .map(|arg| CString::new(arg).expect("split on NUL bytes"))
+        .collect()
+}
+
+/// Calls `shim` the way BYOND would, with `data` split into arguments by [`split_args`].
+///
+/// # Panics
+///
+/// If `shim` returns a pointer to anything but the string it was given to return, which may not be
+/// NUL terminated, or if the returned string isn't valid in `encoding`.
+pub fn call_shim(shim: StrShim, data: &[u8], encoding: Encoding) -> Vec<u8> {
+    let args = split_args(data);
+    let argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+    let argc = c_int::try_from(argv.len()).expect("fuzzer input too large");
+    // SAFETY: `argv` holds `argc` pointers to NUL terminated strings, which outlive the call
+    let returned = unsafe { shim(argc, argv.as_ptr()) };
+    let bytes = returned_bytes(returned)
+        .expect("exported function returned a pointer to a string it didn't return");
+    if let Err(err) = encoding.decode(&bytes) {
+        panic!("exported function returned a string that isn't valid {encoding:?}: {err}");
+    }
+    bytes
+}
@@ -0,0 +1,373 @@
+//! Opaque handles for keeping Rust objects alive across calls from BYOND.
+//!
+//! Returning a [`Handle<T>`] from a `#[byond_fn]` stores the wrapped value in a global registry and
+//! sends an opaque ID string back to BYOND instead. Taking a `&Handle<T>` or `&mut Handle<T>`
+//! argument looks the value back up from that ID.
+//!
+//! Values live until they are freed by calling the `byond_fn_free_handle` export with their ID.
+//! Types with a [`byond_impl(handle)`](crate::byond_impl) block also get a `<Type>_free` export,
+//! which only frees handles to that type.
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//! use byond_fn::handle::Handle;
+//!
+//! pub struct Buffer {
+//!     contents: String,
+//! }
+//!
+//! #[byond_fn]
+//! pub fn buffer_new() -> Handle<Buffer> {
+//!     Handle::new(Buffer { contents: String::new() })
+//! }
+//!
+//! #[byond_fn]
+//! pub fn buffer_push(buffer: &mut Handle<Buffer>, text: String) {
+//!     buffer.contents.push_str(&text);
+//! }
+//!
+//! #[byond_fn]
+//! pub fn buffer_read(buffer: &Handle<Buffer>) -> String {
+//!     buffer.contents.clone()
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! ```dm
+//! var/buffer = call_ext("example.dll", "buffer_new")()
+//! call_ext("example.dll", "buffer_push")(buffer, "hello")
+//! call_ext("example.dll", "byond_fn_free_handle")(buffer)
+//! ```
+
+use std::any::{type_name, Any};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, TryLockError};
+
+use crate::byond_fn;
+use crate::str_ffi::{FFIError, StrArg, StrReturn, TransportError};
+
+/// Wraps a value that is kept on the Rust side, with only an opaque ID passed to BYOND.
+///
+/// Derefs to the wrapped value.
+#[derive(Debug, Default)]
+pub struct Handle<T>(pub T);
+
+impl<T> Handle<T> {
+    pub fn new(value: T) -> Self {
+        Handle(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Handle<T> {
+    fn from(value: T) -> Self {
+        Handle(value)
+    }
+}
+
+impl<T> Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Handle<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: Send + 'static> StrReturn for Handle<T> {
+    fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
+        Ok(Some(insert(self).into_bytes()))
+    }
+}
+
+type Stored = Arc<dyn Any + Send + Sync>;
+
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    value: Option<Stored>,
+}
+
+/// A slab of stored values, where each slot's generation is bumped on reuse so that old IDs
+/// never resolve to a newer value.
+#[derive(Default)]
+struct Registry {
+    slots: Vec<Slot>,
+    vacant: Vec<usize>,
+}
+
+impl Registry {
+    fn insert(&mut self, value: Stored) -> String {
+        let index = self.vacant.pop().unwrap_or_else(|| {
+            self.slots.push(Slot::default());
+            self.slots.len() - 1
+        });
+        let slot = &mut self.slots[index];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.value = Some(value);
+        format!("{index}:{}", slot.generation)
+    }
+
+    fn slot(&self, id: &str) -> Option<(usize, &Slot)> {
+        let (index, generation) = parse_id(id)?;
+        self.slots
+            .get(index)
+            .filter(|slot| slot.generation == generation && slot.value.is_some())
+            .map(|slot| (index, slot))
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Stored> {
+        self.remove_if(id, |_| true)
+    }
+
+    /// Removes the value stored under `id`, if `remove` accepts it
+    fn remove_if(&mut self, id: &str, remove: impl FnOnce(&Stored) -> bool) -> Option<Stored> {
+        let (index, slot) = self.slot(id)?;
+        if !slot.value.as_ref().is_some_and(remove) {
+            return None;
+        }
+        self.vacant.push(index);
+        self.slots[index].value.take()
+    }
+}
+
+fn parse_id(id: &str) -> Option<(usize, u32)> {
+    let (index, generation) = id.split_once(':')?;
+    Some((index.parse().ok()?, generation.parse().ok()?))
+}
+
+fn registry() -> MutexGuard<'static, Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Stores `handle` in the registry, returning its ID.
+///
+/// This is used internally when returning a `Handle`, but is exposed in case you want the same
+/// functionality.
+pub fn insert<T: Send + 'static>(handle: Handle<T>) -> String {
+    registry().insert(Arc::new(Mutex::new(handle)))
+}
+
+/// Looks up the value of type `T` stored under `id`.
+///
+/// Returns `None` if the ID was freed, never existed, or refers to a value of another type.
+pub fn get<T: Send + 'static>(id: &str) -> Option<HandleRef<T>> {
+    let stored = registry().slot(id)?.1.value.clone()?;
+    stored
+        .downcast::<Mutex<Handle<T>>>()
+        .ok()
+        .map(|slot| HandleRef { slot })
+}
+
+/// Drops the value stored under `id`. Returns `false` if there was no such value.
+///
+/// If the value is still borrowed by an in-flight call, it is dropped once that call finishes.
+pub fn free(id: &str) -> bool {
+    registry().remove(id).is_some()
+}
+
+/// Drops the value of type `T` stored under `id`, like the `<Type>_free` export generated for
+/// `#[byond_impl(handle)]` blocks.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If there is no value of type `T` stored under `id`, this will return a
+/// `TransportError::StaleHandle`, and nothing is freed.
+pub fn free_typed<T: Send + 'static>(id: &str, arg_name: &str) -> Result<(), FFIError> {
+    registry()
+        .remove_if(id, |stored| stored.is::<Mutex<Handle<T>>>())
+        .map(drop)
+        .ok_or_else(|| {
+            TransportError::StaleHandle {
+                arg_name: arg_name.to_string(),
+                handle: id.to_string(),
+                expected_type: type_name::<T>(),
+            }
+            .into()
+        })
+}
+
+/// A reference to a value in the handle registry, used for `&Handle<T>` and `&mut Handle<T>`
+/// arguments.
+pub struct HandleRef<T> {
+    slot: Arc<Mutex<Handle<T>>>,
+}
+
+impl<T> HandleRef<T> {
+    /// Borrows the referenced value for the duration of a call.
+    ///
+    /// # Errors
+    ///
+    /// If the value is already borrowed, e.g. when the same handle is passed for two arguments,
+    /// this will return a `TransportError::HandleInUse`.
+    pub fn lock(&self, arg_name: &str) -> Result<MutexGuard<'_, Handle<T>>, FFIError> {
+        match self.slot.try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(poisoned)) => Ok(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(TransportError::HandleInUse {
+                arg_name: arg_name.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl<'a, T: Send + 'static> StrArg<'a> for HandleRef<T> {
+    fn from_arg(arg: &'a str, arg_name: &str) -> Result<Self, FFIError> {
+        get(arg).ok_or_else(|| {
+            TransportError::StaleHandle {
+                arg_name: arg_name.to_string(),
+                handle: arg.to_string(),
+                expected_type: type_name::<T>(),
+            }
+            .into()
+        })
+    }
+}
+
+/// Drops the value stored under a handle, so its ID can no longer be used.
+#[byond_fn(builtin, name = "byond_fn_free_handle")]
+fn free_handle(handle: &str) -> Result<(), FFIError> {
+    if free(handle) {
+        Ok(())
+    } else {
+        Err(TransportError::StaleHandle {
+            arg_name: "handle".to_string(),
+            handle: handle.to_string(),
+            expected_type: "any",
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{c_int, CStr, CString};
+
+    use super::*;
+    use crate::byond_impl;
+
+    struct Parser {
+        format: &'static str,
+    }
+    struct Connection;
+
+    pub struct Regex {
+        pattern: String,
+    }
+
+    #[byond_impl(handle)]
+    impl Regex {
+        #[byond_fn]
+        fn compile(pattern: String) -> Handle<Regex> {
+            Handle::new(Regex { pattern })
+        }
+    }
+
+    fn call_free(id: &str) -> String {
+        let arg = CString::new(id).unwrap();
+        let argv = [arg.as_ptr()];
+        let argc = c_int::try_from(argv.len()).unwrap();
+        let returned = unsafe {
+            __byond_fn___byond_impl_regex_free::__byond_impl_regex_free(argc, argv.as_ptr())
+        };
+        unsafe { CStr::from_ptr(returned) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn stale(result: Result<impl Sized, FFIError>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn freed_handles_are_stale() {
+        let id = insert(Handle::new(Parser { format: "json" }));
+        let parser = HandleRef::<Parser>::from_arg(&id, "parser").unwrap();
+        assert_eq!(parser.lock("parser").unwrap().format, "json");
+
+        assert!(free(&id));
+        assert!(!free(&id));
+        assert_eq!(
+            stale(HandleRef::<Parser>::from_arg(&id, "parser")),
+            format!(
+                "@@ERR@@;FFI;STALE_HANDLE;Argument \"parser\" is not a live handle to \"{}\" (content was \"{id}\")",
+                type_name::<Parser>()
+            )
+        );
+        // a reference taken before the free keeps the value alive until it's dropped
+        assert_eq!(parser.lock("parser").unwrap().format, "json");
+    }
+
+    #[test]
+    fn reused_slots_bump_their_generation() {
+        let mut registry = Registry::default();
+        let first = registry.insert(Arc::new(Mutex::new(Handle::new(Connection))));
+        assert!(registry.remove(&first).is_some());
+        let second = registry.insert(Arc::new(Mutex::new(Handle::new(Connection))));
+
+        assert_eq!(first, "0:1");
+        assert_eq!(second, "0:2");
+        assert!(registry.slot(&first).is_none());
+        assert!(registry.slot(&second).is_some());
+        assert!(registry.slot("0").is_none());
+        assert!(registry.slot("1:1").is_none());
+    }
+
+    #[test]
+    fn handles_are_typed() {
+        let id = insert(Handle::new(Parser { format: "xml" }));
+        assert!(get::<Connection>(&id).is_none());
+        assert!(stale(free_typed::<Connection>(&id, "handle")).contains("STALE_HANDLE"));
+        assert!(get::<Parser>(&id).is_some());
+        free_typed::<Parser>(&id, "handle").unwrap();
+        assert!(get::<Parser>(&id).is_none());
+    }
+
+    #[test]
+    fn generated_free_exports_free_their_type() {
+        let regex = insert(Regex::compile("a+".to_string()));
+        let parser = insert(Handle::new(Parser { format: "yaml" }));
+        assert_eq!(
+            get::<Regex>(&regex).unwrap().lock("regex").unwrap().pattern,
+            "a+"
+        );
+
+        assert!(call_free(&parser).contains("STALE_HANDLE"));
+        assert!(get::<Parser>(&parser).is_some());
+        assert_eq!(call_free(&regex), "");
+        assert!(get::<Regex>(&regex).is_none());
+        assert!(call_free(&regex).contains("STALE_HANDLE"));
+        free(&parser);
+    }
+
+    #[test]
+    fn handles_can_only_be_locked_once() {
+        let id = insert(Handle::new(Parser { format: "toml" }));
+        let first = get::<Parser>(&id).unwrap();
+        let second = get::<Parser>(&id).unwrap();
+        let _guard = first.lock("first").unwrap();
+        assert_eq!(
+            stale(second.lock("second")),
+            "@@ERR@@;FFI;HANDLE_IN_USE;Argument \"second\" refers to a handle that is already in use"
+        );
+        free(&id);
+    }
+}
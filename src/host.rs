@@ -0,0 +1,218 @@
+//! The BYOND version of the host process, enabled with the `ffi_v2` feature.
+//!
+//! The version is asked from byondapi when it's first looked up, see [`api`](crate::ffi_v2::api).
+//! Hosts that are too old to have byondapi, like 514, can't be asked, so DM reports the version
+//! itself through the `byond_fn_set_version` export, once at startup:
+//!
+//! ```dm
+//! /world/New()
+//!     call("lib.dll", "byond_fn_set_version")("[world.byond_version]", "[world.byond_build]")
+//!     ..()
+//! ```
+//!
+//! Or from Rust with [`set_version`]. byondapi's version is used when there is one. A host that
+//! reports no version at all is treated as older than any version.
+//!
+//! Functions can require a version with `#[byond_fn(min_version = "515.1609")]`, which returns an
+//! `UNSUPPORTED_HOST` error instead of calling the function on older hosts. A v2 function can also
+//! be exported with the str transport as well, for hosts that can't call it with the v2 transport,
+//! with `#[byond_fn(v2, fallback = "str")]`. The fallback is exported under the function's name
+//! with `_str` appended:
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//!
+//! #[byond_fn(v2, fallback = "str")]
+//! pub fn add(a: i32, b: i32) -> i32 {
+//!     a + b
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! Both are separate exports, and a host can't tell which one it's able to call, so DM has to
+//! pick the export itself. `call_ext` and the `byond:` prefix don't exist before 515, so the
+//! choice is made when the code is compiled:
+//!
+//! ```dm
+//! /proc/add(a, b)
+//! #if DM_VERSION >= 515
+//!     return call_ext("lib.dll", "byond:add")(a, b)
+//! #else
+//!     return text2num(call("lib.dll", "add_str")("[a]", "[b]"))
+//! #endif
+//! ```
+//!
+//! Code that works differently on different hosts can check the version itself:
+//!
+//! ```no_run
+//! use byond_fn::host::{self, ByondVersion};
+//!
+//! if host::supports(ByondVersion::new(516, 1648)) {
+//!     // use something only 516 has
+//! }
+//! ```
+
+use std::fmt::{Display, Formatter};
+use std::sync::{PoisonError, RwLock};
+
+use crate::byond_fn;
+use crate::ffi_v2::api;
+use crate::str_ffi::TransportError;
+
+/// A BYOND version, like `515.1609`. Versions compare by major version, then build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByondVersion {
+    pub major: u32,
+    pub build: u32,
+}
+
+impl ByondVersion {
+    /// The first version with byondapi and the v2 transport
+    pub const FFI_V2: Self = Self::new(515, 0);
+
+    pub const fn new(major: u32, build: u32) -> Self {
+        Self { major, build }
+    }
+}
+
+impl Display for ByondVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.build)
+    }
+}
+
+static REPORTED: RwLock<Option<ByondVersion>> = RwLock::new(None);
+
+/// Sets the version of the host, for hosts where byondapi can't report it.
+pub fn set_version(version: ByondVersion) {
+    *REPORTED.write().unwrap_or_else(PoisonError::into_inner) = Some(version);
+}
+
+/// Sets the version of the host from `world.byond_version` and `world.byond_build`.
+#[byond_fn(builtin, name = "byond_fn_set_version")]
+fn report_version(major: u32, build: u32) {
+    set_version(ByondVersion::new(major, build));
+}
+
+/// The version of the host process, from byondapi or else as reported with [`set_version`], or
+/// `None` if neither has one.
+pub fn version() -> Option<ByondVersion> {
+    api::any_thread_api()
+        .ok()
+        .and_then(|api| api.version().ok())
+        .or_else(|| *REPORTED.read().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Whether the host is at least version `min`.
+pub fn supports(min: ByondVersion) -> bool {
+    version().is_some_and(|version| version >= min)
+}
+
+/// Whether the host can call functions exported with the v2 transport.
+pub fn supports_v2() -> bool {
+    supports(ByondVersion::FFI_V2)
+}
+
+/// Checks the host is at least version `min`.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If the host is older than `min`, or doesn't report its version.
+pub fn require(min: ByondVersion) -> Result<(), TransportError> {
+    match version() {
+        Some(version) if version >= min => Ok(()),
+        actual => Err(TransportError::UnsupportedHost {
+            required: min,
+            actual,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{c_int, CStr, CString};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::byond_fn;
+    use crate::ffi_v2::api::mock::MockApi;
+    use crate::ffi_v2::api::{with_api, DynamicApi};
+    use crate::ffi_v2::{ByondValue, FromByondValue};
+
+    #[byond_fn(v2, fallback = "str", min_version = "515.1609")]
+    fn double(number: i32) -> i32 {
+        number * 2
+    }
+
+    fn call_str(args: &[&str]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| CString::new(*arg).unwrap()).collect();
+        let argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let argc = c_int::try_from(argv.len()).unwrap();
+        let returned = unsafe { __byond_fn_double_str::double(argc, argv.as_ptr()) };
+        unsafe { CStr::from_ptr(returned) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn call_v2(args: &[ByondValue]) -> ByondValue {
+        let argc = u32::try_from(args.len()).unwrap();
+        // SAFETY: the mock isn't tied to a thread or call
+        unsafe { ByondValue::from_raw(__byond_fn_double::double(argc, args.as_ptr().cast())) }
+    }
+
+    #[test]
+    fn versions_compare_by_build() {
+        assert!(ByondVersion::new(515, 1609) > ByondVersion::new(514, 1589));
+        assert!(ByondVersion::new(515, 1609) < ByondVersion::new(515, 1610));
+        assert_eq!(ByondVersion::new(516, 1648).to_string(), "516.1648");
+    }
+
+    #[test]
+    fn versions_come_from_the_host() {
+        let mock = Arc::new(MockApi::new());
+        mock.set_version(Some(ByondVersion::new(515, 1609)));
+        with_api(mock.clone(), || {
+            assert_eq!(version(), Some(ByondVersion::new(515, 1609)));
+            assert!(supports_v2());
+            assert!(require(ByondVersion::new(515, 1609)).is_ok());
+            let err = require(ByondVersion::new(516, 0)).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "FFI;UNSUPPORTED_HOST;Requires BYOND 516.0, but the host is 515.1609"
+            );
+        });
+
+        // this test binary is the "host", which has no byondapi
+        with_api(Arc::new(DynamicApi::from_host().unwrap()), || {
+            assert_eq!(version(), None);
+            assert!(!supports_v2());
+            let err = require(ByondVersion::FFI_V2).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "FFI;UNSUPPORTED_HOST;Requires BYOND 515.0, but the host doesn't report its version"
+            );
+        });
+    }
+
+    #[test]
+    fn shims_check_the_host_version() {
+        let mock = Arc::new(MockApi::new());
+        with_api(mock.clone(), || {
+            let doubled = call_v2(&[ByondValue::number(2.0)]);
+            assert_eq!(i32::from_byond_value(&doubled).unwrap(), 4);
+
+            mock.set_version(Some(ByondVersion::new(515, 1608)));
+            let err = call_v2(&[ByondValue::number(2.0)]).text().unwrap();
+            assert_eq!(
+                err,
+                "@@ERR@@;FFI;UNSUPPORTED_HOST;Requires BYOND 515.1609, but the host is 515.1608"
+            );
+
+            // the fallback is for older hosts, so it doesn't check the version
+            mock.set_version(None);
+            assert_eq!(call_str(&["2"]), "4");
+        });
+    }
+}
@@ -0,0 +1,189 @@
+//! Global instances used as the receiver of methods exported through
+//! [`byond_impl`](crate::byond_impl).
+//!
+//! A method taking `&self` or `&mut self` can't be called from BYOND directly, since BYOND has no
+//! way to hold a Rust value. Instead, an instance of the type is registered here ahead of time
+//! (usually from an init function), and every call of the method is made on that instance.
+//!
+//! ```
+//! use byond_fn::{byond_fn, byond_impl};
+//!
+//! #[derive(Default)]
+//! pub struct Counter {
+//!     count: u32,
+//! }
+//!
+//! #[byond_impl]
+//! impl Counter {
+//!     // exported as `Counter_init`
+//!     #[byond_fn]
+//!     pub fn init() {
+//!         byond_fn::instance::set_instance(Counter::default());
+//!     }
+//!
+//!     // exported as `Counter_increment`
+//!     #[byond_fn]
+//!     pub fn increment(&mut self, by: u32) -> u32 {
+//!         self.count += by;
+//!         self.count
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use std::any::{type_name, Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+use crate::str_ffi::{FFIError, TransportError};
+
+type Instance = Arc<Mutex<Box<dyn Any + Send>>>;
+
+fn instances() -> &'static Mutex<HashMap<TypeId, Instance>> {
+    static INSTANCES: OnceLock<Mutex<HashMap<TypeId, Instance>>> = OnceLock::new();
+    INSTANCES.get_or_init(Default::default)
+}
+
+/// Registers `value` as the global instance of `T`.
+///
+/// Returns the previously registered instance, if there was one. An instance that a call is
+/// still using can't be handed back, so `None` is returned and it's dropped once the call returns.
+pub fn set_instance<T: Send + 'static>(value: T) -> Option<T> {
+    let previous = instances()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(TypeId::of::<T>(), Arc::new(Mutex::new(Box::new(value))));
+    previous.and_then(unwrap_instance)
+}
+
+/// Removes the global instance of `T`, returning it if one was registered.
+///
+/// As with [`set_instance`], an instance that a call is still using is dropped once the call
+/// returns instead of being returned.
+pub fn take_instance<T: Send + 'static>() -> Option<T> {
+    let previous = instances()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&TypeId::of::<T>());
+    previous.and_then(unwrap_instance)
+}
+
+/// Returns `true` if a global instance of `T` is registered.
+pub fn has_instance<T: Send + 'static>() -> bool {
+    instances()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .contains_key(&TypeId::of::<T>())
+}
+
+/// Runs `f` with exclusive access to the global instance of `T`.
+///
+/// This is used by the functions generated for `#[byond_impl]` methods, but is exposed in case you
+/// want the same functionality.
+///
+/// Calls from other threads wait for the instance, but a call from inside `f` on the same thread,
+/// such as a method calling another method of the same type, would never get it.
+///
+/// # Errors
+///
+/// If no instance of `T` is registered, this will return a `TransportError::NoInstance`. If the
+/// instance is already in use on this thread, this will return a `TransportError::InstanceInUse`.
+pub fn with_instance<T: Send + 'static, R>(f: impl FnOnce(&mut T) -> R) -> Result<R, FFIError> {
+    let _borrow = ThreadBorrow::new::<T>()?;
+    // clone the instance out so the registry isn't locked while `f` runs
+    let instance = instances()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&TypeId::of::<T>())
+        .cloned()
+        .ok_or(TransportError::NoInstance(type_name::<T>()))?;
+    let mut guard = instance.lock().unwrap_or_else(PoisonError::into_inner);
+    let value = guard
+        .downcast_mut::<T>()
+        .expect("instances are always stored under their own TypeId");
+    Ok(f(value))
+}
+
+thread_local! {
+    // the instances in use by a call on this thread
+    static BORROWED: RefCell<Vec<TypeId>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks the instance of a type as in use on this thread, until it's dropped
+struct ThreadBorrow(TypeId);
+
+impl ThreadBorrow {
+    fn new<T: 'static>() -> Result<Self, TransportError> {
+        let type_id = TypeId::of::<T>();
+        BORROWED.with_borrow_mut(|borrowed| {
+            if borrowed.contains(&type_id) {
+                return Err(TransportError::InstanceInUse(type_name::<T>()));
+            }
+            borrowed.push(type_id);
+            Ok(Self(type_id))
+        })
+    }
+}
+
+impl Drop for ThreadBorrow {
+    fn drop(&mut self) {
+        BORROWED.with_borrow_mut(|borrowed| borrowed.retain(|type_id| *type_id != self.0));
+    }
+}
+
+fn unwrap_instance<T: 'static>(instance: Instance) -> Option<T> {
+    let instance = match Arc::try_unwrap(instance) {
+        Ok(instance) => instance,
+        // still borrowed by an in-flight call, so it can't be handed back
+        Err(_) => return None,
+    };
+    let boxed = instance
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    boxed.downcast::<T>().ok().map(|boxed| *boxed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Counter(u32);
+
+    #[test]
+    fn reentrant_calls_are_an_error() {
+        set_instance(Counter(1));
+        let inner = with_instance(|outer: &mut Counter| {
+            outer.0 += 1;
+            with_instance(|inner: &mut Counter| inner.0).map_err(|err| err.to_string())
+        })
+        .unwrap();
+        assert_eq!(
+            inner.unwrap_err(),
+            format!(
+                "@@ERR@@;FFI;INSTANCE_IN_USE;The instance of \"{}\" is already in use by this call",
+                type_name::<Counter>()
+            )
+        );
+        // the instance is usable again once the outer call returns
+        assert_eq!(with_instance(|counter: &mut Counter| counter.0).unwrap(), 2);
+    }
+
+    #[test]
+    fn instances_in_use_are_not_returned() {
+        struct Config(&'static str);
+
+        assert!(set_instance(Config("first")).is_none());
+        let previous = with_instance(|_: &mut Config| set_instance(Config("second"))).unwrap();
+        assert!(previous.is_none());
+        assert_eq!(
+            set_instance(Config("third")).map(|config| config.0),
+            Some("second")
+        );
+        assert_eq!(
+            take_instance::<Config>().map(|config| config.0),
+            Some("third")
+        );
+        assert!(!has_instance::<Config>());
+    }
+}
@@ -10,6 +10,7 @@
 //! pub fn add(arg1: u8, arg2: u8) -> u8 {
 //!     arg1 + arg2
 //! }
+//! # fn main() {}
 //! ```
 //! This will generate a extern "C" function called `add` that can be called from BYOND:
 //!
@@ -21,13 +22,200 @@
 //!
 //! All optional parameters must be at the end of the parameter list.
 //!
+//! ## Variadic Parameters
+//!
+//! If the last parameter is a [`Rest`](crate::str_ffi::Rest), it will collect every remaining
+//! argument passed from BYOND, so the function can be called with any number of arguments.
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//! use byond_fn::str_ffi::Rest;
+//!
+//! #[byond_fn]
+//! pub fn concat(sep: String, parts: Rest<String>) -> String {
+//!     parts.join(&sep)
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! `call_ext("example_name.dll", "concat")("-", "a", "b", "c") // returns "a-b-c"`
+//!
+//! ## Argument Structs
+//!
+//! Groups of arguments can be collected into a struct with `#[derive(ByondArgs)]`, which maps its
+//! fields to consecutive arguments. The struct is then taken as the only parameter of a function,
+//! marked with `#[byond_args]`. See [`ByondArgs`](crate::str_ffi::ByondArgs) for more information.
+//!
+//! ## Attribute Arguments
+//!
+//! `#[byond_fn]` optionally takes a list of arguments:
+//! - `name = "..."` - export the function under this exact symbol name instead of the function's
+//!   own name. It does not have to be a valid rust identifier.
+//! - `prefix = "..."` - prepend this to the exported name. Usually set through
+//!   [`byond_prefix`](crate::byond_prefix) rather than per function.
+//! - `transport = "..."`, or the shorthand `str`/`v2` - select the FFI transport. Defaults to `str`.
+//! - `chunked`, or `chunked = <bytes>` - return the result in chunks fetched separately, for results
+//!   too large for a single string. See [`chunked`](crate::str_ffi::chunked).
+//! - `encoding = "..."` - the text encoding of arguments and returns, for BYOND versions that don't
+//!   use UTF-8. See [`encoding`](crate::str_ffi::encoding).
+//! - `nul = "..."` - what to do with NUL bytes in the returned value, which BYOND can't receive.
+//!   See [`binary`](crate::str_ffi::binary).
+//! - `min_version = "..."` - the oldest BYOND version the function can be called on, like
+//!   `"515.1609"`. Older hosts get an `UNSUPPORTED_HOST` error instead. Requires `ffi_v2`.
+//! - `fallback = "str"` - with `v2`, also export the function with the str transport under its
+//!   name with `_str` appended, for hosts older than 515. DM has to pick which export to call. See
+//!   [`host`](crate::host).
+//! - `errors = "runtime"` - with `v2`, raise errors and panics as DM runtime errors instead of
+//!   returning them as strings. See [`runtime`](crate::ffi_v2::runtime).
+//! - `max_arg_bytes = <bytes>`, `max_total_bytes = <bytes>`, `max_json_depth = <depth>`,
+//!   `max_collection_len = <len>` - reject oversized arguments with an `ARG_TOO_LARGE` error before
+//!   they're parsed, overriding the library defaults. See [`limits`](crate::str_ffi::limits).
+//!
+//! ```
+//! use byond_fn::{byond_fn, byond_prefix};
+//!
+//! #[byond_fn(name = "rust-add")]
+//! pub fn add(arg1: u8, arg2: u8) -> u8 {
+//!     arg1 + arg2
+//! }
+//!
+//! #[byond_prefix("mylib_")]
+//! mod exports {
+//!     use byond_fn::byond_fn;
+//!
+//!     // exported as `mylib_sub`
+//!     #[byond_fn]
+//!     pub fn sub(arg1: u8, arg2: u8) -> u8 {
+//!         arg1 - arg2
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! To prefix every export of a crate instead, set `BYOND_FN_PREFIX` from its build script. A macro
+//! can't see a declaration elsewhere in the crate, and attributes on the crate itself are unstable,
+//! so the prefix is read from the environment when `#[byond_fn]` expands. It applies to functions
+//! without a `name` or a prefix of their own, including those of impl blocks:
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     println!("cargo:rustc-env=BYOND_FN_PREFIX=mylib_");
+//! }
+//! ```
+//!
+//! ## Impl Blocks
+//!
+//! Associated functions can be exported by marking them with `#[byond_fn]` inside an impl block
+//! marked with [`byond_impl`](crate::byond_impl). They are exported with the type name as a
+//! prefix, e.g. `Counter_increment`.
+//!
+//! Methods taking `&self` or `&mut self` are called on the global instance of the type, registered
+//! with [`set_instance`](crate::instance::set_instance). See [`instance`](crate::instance) for an
+//! example.
+//! With `#[byond_impl(handle)]`, they are instead called on the value behind a handle passed as
+//! the first argument, and a `<Type>_free` export is generated to free such a handle.
+//!
+//! ## Handles
+//!
+//! Rust values can be kept alive across calls by returning them wrapped in a
+//! [`Handle`](crate::handle::Handle), which sends an opaque ID to BYOND instead. Arguments of type
+//! `&Handle<T>` or `&mut Handle<T>` take such an ID and resolve it back to the value. See
+//! [`handle`](crate::handle) for more information.
+//!
+//! ## Sandboxed Paths
+//!
+//! Path arguments that come from user input can be taken as a `SandboxedPath<Root>`, which only
+//! accepts paths inside the directory given by `Root`, and returns a `PATH_ESCAPE` error for any
+//! other. See [`sandbox`](crate::str_ffi::sandbox) for more information.
+//!
+//! ## Metrics
+//!
+//! With the `metrics` feature, every function records its call count, error count and latency,
+//! which can be read from DM as JSON through the `byond_fn_stats` export. See
+//! [`metrics`](crate::metrics) for more information.
+//!
+//! ## Profiling
+//!
+//! With the `profiling` feature, every call can be traced into a Chrome trace file, with spans for
+//! parsing arguments, running the function and returning its value. Tracing is started and
+//! stopped from DM through the `byond_fn_profile_start` and `byond_fn_profile_stop` exports. See
+//! [`profiling`](crate::profiling) for more information.
+//!
+//! ## Recording
+//!
+//! With the `recording` feature, every call to a function with the str transport can be recorded into
+//! a log file, with its arguments, return value and timing. Recording is started and stopped from DM
+//! through the `byond_fn_record_start` and `byond_fn_record_stop` exports, and the `replay` crate of
+//! this repository replays a recording against a local build, reporting the calls that returned
+//! something different. See [`recording`](crate::recording) for more information.
+//!
+//! ## Fuzzing
+//!
+//! With the `fuzzing` feature, every `#[byond_fn]` also generates a fuzz entry point that calls it
+//! with arbitrary arguments. See [`fuzz`](crate::fuzz) for more information.
+//!
+//! ## Calling Into BYOND
+//!
+//! With the `ffi_v2` feature, Rust can call back into BYOND through byondapi, which BYOND 515 and
+//! later export: reading and writing vars, and calling procs. See [`api`](crate::ffi_v2::api) for more information.
+//!
+//! Functions exported with `#[byond_fn(v2)]` are called with `call_ext("lib.dll", "byond:name")`,
+//! and take and return BYOND values directly instead of strings, including lists, which convert
+//! to and from `Vec` and `HashMap`. See [`ffi_v2`](crate::ffi_v2) for more information.
+//! Any other serde type can be taken or returned as a `ByondSerde<T>`, see [`byond_serde`](crate::ffi_v2::byond_serde).
+//! Values can be kept past the call they were received in with a `ByondRefGuard`, see [`refs`](crate::ffi_v2::refs).
+//! The BYOND version of the host is available from [`host::version`](crate::host).
+//!
+//! ## Hot Reloading
+//!
+//! With the `hot_reload` feature, a small loader library can be loaded by BYOND in place of the real
+//! one, forwarding every call to it, so a new build can be swapped in through the loader's
+//! `byond_fn_reload` export without restarting the server. The `example_loader` crate of this
+//! repository is a loader for `example_crate`. See [`reload`](crate::reload) for more information.
+//!
 
 pub use byond_fn_impl::*;
 
+// lets the macros, which always refer to `byond_fn::`, be used within this crate
+extern crate self as byond_fn;
+
 #[cfg(feature = "ffi_v2")]
 pub mod ffi_v2;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub mod handle;
+#[cfg(feature = "ffi_v2")]
+pub mod host;
+pub mod instance;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+#[cfg(feature = "recording")]
+pub mod recording;
+#[cfg(feature = "hot_reload")]
+pub mod reload;
 pub mod str_ffi;
 
+/// A small, stable ID for the current thread, as trace viewers expect numeric thread IDs
+#[cfg(any(feature = "profiling", feature = "recording"))]
+pub(crate) fn thread_id() -> u64 {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static ID: Cell<u64> = const { Cell::new(0) };
+    }
+    ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
 #[cfg(all(not(target_pointer_width = "32"), not(feature = "allow_other_arch")))]
 compile_error!(
     r#"
@@ -0,0 +1,261 @@
+//! Per-function call metrics, enabled with the `metrics` feature.
+//!
+//! With the feature enabled, every function generated by `#[byond_fn]` records its call count,
+//! its error count by error class, and a histogram of how long its calls took. Recording only
+//! touches atomic counters, so it never blocks a call.
+//!
+//! The metrics are read from DM through the `byond_fn_stats` export, which returns them as JSON
+//! keyed by export name, and cleared with `byond_fn_stats_reset`:
+//!
+//! ```json
+//! {"add":{"calls":2,"errors":{"FFI":1,"JSON":0,"FN":0,"API":0},"total_us":3,"max_us":2,
+//!   "histogram_us":{"1":1,"10":1,"100":0,"1000":0,"10000":0,"100000":0,"1000000":0,"inf":0}}}
+//! ```
+//!
+//! Functions show up once they have been called for the first time.
+
+use std::cell::Cell;
+use std::fmt::Write;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::byond_fn;
+use crate::str_ffi::{error_keys, json_string, FFIError};
+
+/// Upper bounds of the latency histogram buckets, in microseconds. The last bucket holds
+/// everything slower.
+pub const HISTOGRAM_BOUNDS_US: [u64; 7] = [1, 10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// The error classes errors are counted under, in the order of `FnStats::errors`
+const ERROR_CLASSES: [&str; 4] = [
+    error_keys::CLASS_FFI,
+    error_keys::CLASS_JSON,
+    error_keys::CLASS_FN,
+    error_keys::CLASS_API,
+];
+
+/// The metrics of a single exported function.
+///
+/// Each generated function keeps one of these in a static, which links itself into the global list
+/// on its first call.
+pub struct FnStats {
+    name: &'static str,
+    calls: AtomicU64,
+    errors: [AtomicU64; ERROR_CLASSES.len()],
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+    histogram: [AtomicU64; HISTOGRAM_BOUNDS_US.len() + 1],
+    registered: AtomicBool,
+    next: AtomicPtr<FnStats>,
+}
+
+/// The most recently registered `FnStats`, forming a list through `FnStats::next`
+static REGISTERED: AtomicPtr<FnStats> = AtomicPtr::new(ptr::null_mut());
+
+impl FnStats {
+    pub const fn new(name: &'static str) -> Self {
+        FnStats {
+            name,
+            calls: AtomicU64::new(0),
+            errors: [const { AtomicU64::new(0) }; ERROR_CLASSES.len()],
+            total_us: AtomicU64::new(0),
+            max_us: AtomicU64::new(0),
+            histogram: [const { AtomicU64::new(0) }; HISTOGRAM_BOUNDS_US.len() + 1],
+            registered: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn register(&'static self) {
+        if self.registered.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let this = ptr::from_ref(self).cast_mut();
+        let mut head = REGISTERED.load(Ordering::Acquire);
+        loop {
+            self.next.store(head, Ordering::Relaxed);
+            match REGISTERED.compare_exchange_weak(head, this, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    fn record(&self, elapsed_us: u64, error_class: Option<usize>) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        if let Some(class) = error_class {
+            self.errors[class].fetch_add(1, Ordering::Relaxed);
+        }
+        self.total_us.fetch_add(elapsed_us, Ordering::Relaxed);
+        self.max_us.fetch_max(elapsed_us, Ordering::Relaxed);
+        let bucket = HISTOGRAM_BOUNDS_US
+            .iter()
+            .position(|&bound| elapsed_us <= bound)
+            .unwrap_or(HISTOGRAM_BOUNDS_US.len());
+        self.histogram[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        let counters = [&self.calls, &self.total_us, &self.max_us]
+            .into_iter()
+            .chain(&self.errors)
+            .chain(&self.histogram);
+        for counter in counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn write_json(&self, out: &mut String) -> std::fmt::Result {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        write!(
+            out,
+            "{}:{{\"calls\":{},",
+            json_string(self.name),
+            load(&self.calls)
+        )?;
+        out.push_str("\"errors\":{");
+        for (idx, (class, count)) in ERROR_CLASSES.iter().zip(&self.errors).enumerate() {
+            let sep = if idx == 0 { "" } else { "," };
+            write!(out, "{sep}\"{class}\":{}", load(count))?;
+        }
+        write!(
+            out,
+            "}},\"total_us\":{},\"max_us\":{},\"histogram_us\":{{",
+            load(&self.total_us),
+            load(&self.max_us)
+        )?;
+        for (idx, count) in self.histogram.iter().enumerate() {
+            let sep = if idx == 0 { "" } else { "," };
+            match HISTOGRAM_BOUNDS_US.get(idx) {
+                Some(bound) => write!(out, "{sep}\"{bound}\":{}", load(count))?,
+                None => write!(out, "{sep}\"inf\":{}", load(count))?,
+            }
+        }
+        out.push_str("}}");
+        Ok(())
+    }
+}
+
+fn registered() -> impl Iterator<Item = &'static FnStats> {
+    let mut next = REGISTERED.load(Ordering::Acquire);
+    std::iter::from_fn(move || {
+        // SAFETY: only `&'static FnStats` are ever linked in, and they are never unlinked
+        let stats = unsafe { next.as_ref()? };
+        next = stats.next.load(Ordering::Acquire);
+        Some(stats)
+    })
+}
+
+thread_local! {
+    /// The class of the error returned by the current call, if any
+    static ERROR_CLASS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Notes that the current call is returning `err`, so it is counted under its error class.
+pub(crate) fn record_error(err: &FFIError) {
+    let class = match err {
+        FFIError::TransportError(_) => error_keys::CLASS_FFI,
+        #[cfg(feature = "json_transport")]
+        FFIError::JsonError(_) => error_keys::CLASS_JSON,
+        FFIError::OtherError(_) => error_keys::CLASS_FN,
+        #[cfg(feature = "ffi_v2")]
+        FFIError::ApiError(_) => error_keys::CLASS_API,
+    };
+    let class = ERROR_CLASSES.iter().position(|&known| known == class);
+    ERROR_CLASS.with(|cell| cell.set(class));
+}
+
+/// Times a call, recording it into its function's metrics when dropped.
+///
+/// This is used by the generated functions, but is exposed in case you want the same
+/// functionality.
+pub struct CallGuard {
+    stats: &'static FnStats,
+    start: Instant,
+}
+
+impl CallGuard {
+    pub fn start(stats: &'static FnStats) -> Self {
+        stats.register();
+        ERROR_CLASS.with(|cell| cell.set(None));
+        CallGuard {
+            stats,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        let elapsed_us = u64::try_from(self.start.elapsed().as_micros()).unwrap_or(u64::MAX);
+        let error_class = ERROR_CLASS.with(Cell::take);
+        self.stats.record(elapsed_us, error_class);
+    }
+}
+
+/// The metrics of every function called so far, as JSON keyed by export name.
+pub fn stats_json() -> String {
+    let mut out = String::from("{");
+    for (idx, stats) in registered().enumerate() {
+        if idx > 0 {
+            out.push(',');
+        }
+        // writing to a String can't fail
+        let _ = stats.write_json(&mut out);
+    }
+    out.push('}');
+    out
+}
+
+/// Clears the metrics of every function.
+pub fn reset_stats() {
+    registered().for_each(FnStats::reset);
+}
+
+/// Returns the metrics of every exported function as JSON.
+#[byond_fn(builtin, name = "byond_fn_stats")]
+fn stats() -> String {
+    stats_json()
+}
+
+/// Clears the metrics of every exported function.
+#[byond_fn(builtin, name = "byond_fn_stats_reset")]
+fn stats_reset() {
+    reset_stats();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::str_ffi::TransportError;
+
+    static TEST_STATS: FnStats = FnStats::new("test_fn");
+
+    #[test]
+    fn records_calls_and_errors() {
+        drop(CallGuard::start(&TEST_STATS));
+        {
+            let _call = CallGuard::start(&TEST_STATS);
+            record_error(&TransportError::ReturnStr(String::new()).into());
+        }
+
+        assert_eq!(TEST_STATS.calls.load(Ordering::Relaxed), 2);
+        assert_eq!(TEST_STATS.errors[0].load(Ordering::Relaxed), 1);
+        let histogram_total: u64 = TEST_STATS
+            .histogram
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum();
+        assert_eq!(histogram_total, 2);
+
+        let json = stats_json();
+        assert!(json.contains("\"test_fn\":{\"calls\":2,\"errors\":{\"FFI\":1,"));
+
+        TEST_STATS.reset();
+        assert_eq!(TEST_STATS.calls.load(Ordering::Relaxed), 0);
+        // still registered, so it keeps showing up
+        assert!(stats_json().contains("\"test_fn\":{\"calls\":0,"));
+    }
+}
@@ -0,0 +1,169 @@
+//! Chrome trace profiling of calls, enabled with the `profiling` feature.
+//!
+//! With the feature enabled, every function generated by `#[byond_fn]` emits a span covering the
+//! whole call, with nested spans for each phase of it:
+//!
+//! - `args` - decoding and parsing the arguments
+//! - `call` - the function itself
+//! - `return` - converting the return value and handing it back to BYOND
+//!
+//! Spans are only recorded between calls to the `byond_fn_profile_start` export, which takes the
+//! path of the trace file to write, and `byond_fn_profile_stop`. The file is in the Chrome trace
+//! event format, which can be opened in [Perfetto](https://ui.perfetto.dev) or `chrome://tracing`.
+//!
+//! ```dm
+//! call_ext("example.dll", "byond_fn_profile_start")("data/byond_fn_trace.json")
+//! // ...
+//! call_ext("example.dll", "byond_fn_profile_stop")()
+//! ```
+//!
+//! Without the feature, none of this is generated.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::Instant;
+
+use crate::byond_fn;
+use crate::str_ffi::json_string;
+use crate::thread_id;
+
+struct Trace {
+    out: BufWriter<File>,
+    epoch: Instant,
+    first_event: bool,
+}
+
+impl Trace {
+    fn write_event(&mut self, name: &str, start: Instant, end: Instant) -> io::Result<()> {
+        let ts = start.saturating_duration_since(self.epoch).as_secs_f64() * 1e6;
+        let dur = end.saturating_duration_since(start).as_secs_f64() * 1e6;
+        let sep = if self.first_event { "" } else { ",\n" };
+        self.first_event = false;
+        write!(
+            self.out,
+            "{sep}{{\"name\":{},\"cat\":\"byond_fn\",\"ph\":\"X\",\"ts\":{ts:.3},\"dur\":{dur:.3},\"pid\":{},\"tid\":{}}}",
+            json_string(name),
+            std::process::id(),
+            thread_id(),
+        )
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.out.write_all(b"\n]\n")?;
+        self.out.flush()
+    }
+}
+
+/// Fast path for spans, so nothing is locked while no trace is being recorded
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+fn trace() -> MutexGuard<'static, Option<Trace>> {
+    static TRACE: OnceLock<Mutex<Option<Trace>>> = OnceLock::new();
+    TRACE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Starts recording spans into a new trace file at `path`, finishing any trace already being
+/// recorded.
+///
+/// # Errors
+///
+/// If either trace file can't be written.
+pub fn start_profiling(path: impl Into<PathBuf>) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path.into())?);
+    out.write_all(b"[\n")?;
+    let previous = trace().replace(Trace {
+        out,
+        epoch: Instant::now(),
+        first_event: true,
+    });
+    RECORDING.store(true, Ordering::Release);
+    previous.map_or(Ok(()), Trace::finish)
+}
+
+/// Stops recording spans and finishes the trace file. Does nothing if no trace is being
+/// recorded.
+///
+/// # Errors
+///
+/// If the trace file can't be written.
+pub fn stop_profiling() -> io::Result<()> {
+    RECORDING.store(false, Ordering::Release);
+    trace().take().map_or(Ok(()), Trace::finish)
+}
+
+/// A span that is written to the trace once dropped.
+///
+/// This is used by the generated functions, but is exposed in case you want to trace parts of
+/// your own functions.
+#[must_use = "the span ends when it is dropped"]
+pub struct Span {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+/// Starts a span named `name`, if a trace is being recorded.
+pub fn span(name: &'static str) -> Span {
+    Span {
+        name,
+        start: RECORDING.load(Ordering::Acquire).then(Instant::now),
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let end = Instant::now();
+        if let Some(trace) = trace().as_mut() {
+            // profiling is best effort, and a failed write shows up when the trace is stopped
+            let _ = trace.write_event(self.name, start, end);
+        }
+    }
+}
+
+/// Starts recording a trace of every call into the file at `path`.
+#[byond_fn(builtin, name = "byond_fn_profile_start")]
+fn profile_start(path: PathBuf) -> io::Result<()> {
+    start_profiling(path)
+}
+
+/// Stops recording the trace, and finishes its file.
+#[byond_fn(builtin, name = "byond_fn_profile_stop")]
+fn profile_stop() -> io::Result<()> {
+    stop_profiling()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writes_nested_spans() {
+        let path = std::env::temp_dir().join(format!("byond_fn_trace_{}.json", std::process::id()));
+        drop(span("before"));
+
+        start_profiling(&path).unwrap();
+        {
+            let _outer = span("outer");
+            drop(span("inner"));
+        }
+        stop_profiling().unwrap();
+        drop(span("after"));
+
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(trace.starts_with('[') && trace.trim_end().ends_with(']'));
+        let names: Vec<_> = trace
+            .lines()
+            .filter_map(|line| line.split("\"name\":\"").nth(1)?.split('"').next())
+            .collect();
+        assert_eq!(names, ["inner", "outer"]);
+    }
+}
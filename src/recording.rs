@@ -0,0 +1,448 @@
+//! Recording of calls for replaying them later, enabled with the `recording` feature.
+//!
+//! With the feature enabled, every function generated by `#[byond_fn]` with the str transport can
+//! record each of its calls: the export name, the raw bytes of its arguments and of the returned
+//! string, when the call started and how long it took, and the thread it ran on.
+//!
+//! Calls are only recorded between calls to the `byond_fn_record_start` export, which takes the
+//! path of the file to record into and optionally its maximum size in bytes, and
+//! `byond_fn_record_stop`. Once the file would grow past its maximum size, it is rotated: it's
+//! renamed with `.1` appended, shifting older files up to `.4`, and recording continues in a new
+//! file.
+//!
+//! ```dm
+//! call_ext("example.dll", "byond_fn_record_start")("data/byond_fn_calls.bin", "67108864")
+//! // ...
+//! call_ext("example.dll", "byond_fn_record_stop")()
+//! ```
+//!
+//! Recordings are read with [`read_records`]. The `byond_fn_replay` binary in the `replay`
+//! directory of this repository calls every recorded call again on a locally built library, and
+//! reports the calls that returned something different. It's a workspace of its own, so it's run
+//! by its manifest:
+//!
+//! ```text
+//! cargo run --manifest-path replay/Cargo.toml -- target/debug/example.dll byond_fn_calls.bin.1 byond_fn_calls.bin
+//! ```
+//!
+//! Without the feature, none of this is generated.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::byond_fn;
+use crate::thread_id;
+
+/// The start of every recording file, identifying its format
+const MAGIC: &[u8; 8] = b"BFNREC1\n";
+
+/// The maximum size of a recording file before it is rotated, unless another is given
+pub const DEFAULT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// How many rotated files are kept, besides the one being recorded into
+pub const ROTATED_FILES: usize = 4;
+
+/// A single recorded call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallRecord {
+    /// The name the function is exported under
+    pub name: String,
+    /// The raw bytes of each argument
+    pub args: Vec<Vec<u8>>,
+    /// The raw bytes of the returned string
+    pub returned: Vec<u8>,
+    pub started: SystemTime,
+    pub duration: Duration,
+    /// A small ID for the thread the call ran on, unique within the recording process
+    pub thread: u64,
+}
+
+impl CallRecord {
+    /// Encodes the record as it's written to a recording file.
+    ///
+    /// Every number is little endian, and every string and list is prefixed with its length as a
+    /// `u32`.
+    fn encode(&self) -> Vec<u8> {
+        fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+            // BYOND strings can't get anywhere near 4 GiB
+            out.extend(u32::try_from(bytes.len()).unwrap_or(u32::MAX).to_le_bytes());
+            out.extend(bytes);
+        }
+
+        let mut out = Vec::new();
+        put_bytes(&mut out, self.name.as_bytes());
+        out.extend(
+            u32::try_from(self.args.len())
+                .unwrap_or(u32::MAX)
+                .to_le_bytes(),
+        );
+        for arg in &self.args {
+            put_bytes(&mut out, arg);
+        }
+        put_bytes(&mut out, &self.returned);
+        let started = self
+            .started
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        out.extend(
+            u64::try_from(started.as_micros())
+                .unwrap_or(u64::MAX)
+                .to_le_bytes(),
+        );
+        out.extend(
+            u64::try_from(self.duration.as_nanos())
+                .unwrap_or(u64::MAX)
+                .to_le_bytes(),
+        );
+        out.extend(self.thread.to_le_bytes());
+        out
+    }
+
+    /// Decodes the next record, or returns `None` at the end of the recording.
+    fn decode(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        fn get_u32(reader: &mut impl Read) -> io::Result<u32> {
+            let mut buf = [0; 4];
+            reader.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        }
+
+        fn get_u64(reader: &mut impl Read) -> io::Result<u64> {
+            let mut buf = [0; 8];
+            reader.read_exact(&mut buf)?;
+            Ok(u64::from_le_bytes(buf))
+        }
+
+        fn get_n_bytes(reader: &mut impl Read, len: u32) -> io::Result<Vec<u8>> {
+            let mut bytes = Vec::new();
+            reader.take(u64::from(len)).read_to_end(&mut bytes)?;
+            if bytes.len() != len as usize {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            Ok(bytes)
+        }
+
+        fn get_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+            let len = get_u32(reader)?;
+            get_n_bytes(reader, len)
+        }
+
+        // a record starts with the length of the name, so an end here is the end of the recording
+        let mut name_len = [0; 4];
+        if reader.read(&mut name_len[..1])? == 0 {
+            return Ok(None);
+        }
+        reader.read_exact(&mut name_len[1..])?;
+        let name = get_n_bytes(reader, u32::from_le_bytes(name_len))?;
+        let name = String::from_utf8(name)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let arg_count = get_u32(reader)?;
+        let args = (0..arg_count)
+            .map(|_| get_bytes(reader))
+            .collect::<io::Result<_>>()?;
+        let returned = get_bytes(reader)?;
+        let started = SystemTime::UNIX_EPOCH + Duration::from_micros(get_u64(reader)?);
+        let duration = Duration::from_nanos(get_u64(reader)?);
+        let thread = get_u64(reader)?;
+        Ok(Some(Self {
+            name,
+            args,
+            returned,
+            started,
+            duration,
+            thread,
+        }))
+    }
+}
+
+/// The records of a recording file, see [`read_records`].
+pub struct Records<R> {
+    reader: R,
+    failed: bool,
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = io::Result<CallRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let record = CallRecord::decode(&mut self.reader).transpose();
+        // nothing after a broken record can be read, such as the last one of a recording cut
+        // short by a crash
+        self.failed = matches!(record, Some(Err(_)));
+        record
+    }
+}
+
+/// Reads the records of a recording, in the order they were recorded.
+///
+/// # Errors
+///
+/// If the recording can't be read, or isn't a recording. Records that can't be read are returned
+/// as errors by the iterator, which ends after the first one.
+pub fn read_records<R: Read>(mut reader: R) -> io::Result<Records<R>> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a byond_fn recording",
+        ));
+    }
+    Ok(Records {
+        reader,
+        failed: false,
+    })
+}
+
+struct Recording {
+    out: BufWriter<File>,
+    path: PathBuf,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl Recording {
+    fn create(path: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(&path)?);
+        out.write_all(MAGIC)?;
+        out.flush()?;
+        Ok(Self {
+            out,
+            path,
+            written: MAGIC.len() as u64,
+            max_bytes,
+        })
+    }
+
+    fn write_record(&mut self, record: &CallRecord) -> io::Result<()> {
+        let encoded = record.encode();
+        // a record bigger than the limit still goes into a file of its own
+        if self.written > MAGIC.len() as u64 && self.written + encoded.len() as u64 > self.max_bytes
+        {
+            self.rotate()?;
+        }
+        self.out.write_all(&encoded)?;
+        // flushed every call, so a crash loses at most the call that crashed
+        self.out.flush()?;
+        self.written += encoded.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.out.flush()?;
+        for index in (1..ROTATED_FILES).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        *self = Self::create(self.path.clone(), self.max_bytes)?;
+        Ok(())
+    }
+}
+
+/// The path of the `index`th rotated file of the recording at `path`
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{index}"));
+    rotated.into()
+}
+
+/// Fast path for calls, so nothing is copied or locked while nothing is being recorded
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+fn recording() -> MutexGuard<'static, Option<Recording>> {
+    static RECORDING: OnceLock<Mutex<Option<Recording>>> = OnceLock::new();
+    RECORDING
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Starts recording calls into a new file at `path`, which is rotated once it would grow past
+/// `max_bytes`. Stops any recording already in progress.
+///
+/// # Errors
+///
+/// If the file can't be written.
+pub fn start_recording(path: impl Into<PathBuf>, max_bytes: u64) -> io::Result<()> {
+    let new = Recording::create(path.into(), max_bytes)?;
+    let previous = recording().replace(new);
+    RECORDING.store(true, Ordering::Release);
+    previous.map_or(Ok(()), |mut previous| previous.out.flush())
+}
+
+/// Stops recording calls. Does nothing if nothing is being recorded.
+///
+/// # Errors
+///
+/// If the file can't be written.
+pub fn stop_recording() -> io::Result<()> {
+    RECORDING.store(false, Ordering::Release);
+    recording()
+        .take()
+        .map_or(Ok(()), |mut recording| recording.out.flush())
+}
+
+/// Records a single call, from the arguments it got to the string it returned.
+///
+/// This is used by the generated functions, but is exposed in case you want the same
+/// functionality.
+#[must_use = "the call is recorded by `finish`"]
+pub struct Recorder {
+    name: &'static str,
+    /// The arguments and start of the call, if calls are being recorded
+    call: Option<(Vec<Vec<u8>>, SystemTime, Instant)>,
+}
+
+impl Recorder {
+    /// Starts recording a call to the function exported as `name`, if calls are being recorded.
+    ///
+    /// # Safety
+    /// Unless `argv` is null, it must point to `argc` valid C strings.
+    pub unsafe fn start(name: &'static str, argc: c_int, argv: *const *const c_char) -> Self {
+        let call = RECORDING.load(Ordering::Acquire).then(|| {
+            let args = match usize::try_from(argc) {
+                Ok(argc) if !argv.is_null() => unsafe { slice::from_raw_parts(argv, argc) }
+                    .iter()
+                    .map(|arg| unsafe { CStr::from_ptr(*arg) }.to_bytes().to_vec())
+                    .collect(),
+                _ => Vec::new(),
+            };
+            (args, SystemTime::now(), Instant::now())
+        });
+        Self { name, call }
+    }
+
+    /// Records the call, with the string it returned, and passes the string on.
+    ///
+    /// # Safety
+    /// `returned` must be a valid C string.
+    pub unsafe fn finish(self, returned: *const c_char) -> *const c_char {
+        let Some((args, started, start)) = self.call else {
+            return returned;
+        };
+        let record = CallRecord {
+            name: self.name.to_string(),
+            args,
+            returned: unsafe { CStr::from_ptr(returned) }.to_bytes().to_vec(),
+            started,
+            duration: start.elapsed(),
+            thread: thread_id(),
+        };
+        if let Some(recording) = recording().as_mut() {
+            // recording is best effort, so a failed write doesn't fail the call
+            let _ = recording.write_record(&record);
+        }
+        returned
+    }
+}
+
+/// Starts recording every call into the file at `path`, rotated once it would grow past
+/// `max_bytes`.
+#[byond_fn(builtin, name = "byond_fn_record_start")]
+fn record_start(path: PathBuf, max_bytes: Option<u64>) -> io::Result<()> {
+    start_recording(path, max_bytes.unwrap_or(DEFAULT_MAX_BYTES))
+}
+
+/// Stops recording calls.
+#[byond_fn(builtin, name = "byond_fn_record_stop")]
+fn record_stop() -> io::Result<()> {
+    stop_recording()
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::str_ffi::byond_return;
+
+    fn record(name: &str, args: &[&[u8]], returned: &[u8]) -> CallRecord {
+        CallRecord {
+            name: name.to_string(),
+            args: args.iter().map(|arg| arg.to_vec()).collect(),
+            returned: returned.to_vec(),
+            started: SystemTime::UNIX_EPOCH + Duration::from_micros(1_700_000_000_000_000),
+            duration: Duration::from_nanos(1234),
+            thread: 1,
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = [
+            record("add", &[b"1", b"2"], b"3"),
+            record("empty", &[], b""),
+            record("latin", &[b"caf\xe9"], b"\xff"),
+        ];
+        let mut bytes = MAGIC.to_vec();
+        for record in &records {
+            bytes.extend(record.encode());
+        }
+        let read: Vec<_> = read_records(&bytes[..])
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(read, records);
+
+        // a recording cut short ends with an error
+        let cut = &bytes[..bytes.len() - 3];
+        let read: Vec<_> = read_records(cut).unwrap().collect();
+        assert_eq!(read.len(), 3);
+        assert!(read[2].is_err());
+
+        assert!(read_records(&b"not a recording"[..]).is_err());
+    }
+
+    #[test]
+    fn records_calls_and_rotates() {
+        let dir = std::env::temp_dir().join(format!("byond_fn_recording_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("calls.bin");
+
+        let call = |arg: &str| {
+            let arg = CString::new(arg).unwrap();
+            let argv = [arg.as_ptr()];
+            unsafe {
+                let recorder = Recorder::start("echo", 1, argv.as_ptr());
+                recorder.finish(byond_return(arg.to_str().unwrap().to_string()))
+            }
+        };
+
+        call("before");
+        // only fits two of these calls per file
+        start_recording(&path, 100).unwrap();
+        for arg in ["a", "b", "c", "d", "e"] {
+            call(arg);
+        }
+        stop_recording().unwrap();
+        call("after");
+
+        let read = |path: &Path| -> Vec<String> {
+            read_records(File::open(path).unwrap())
+                .unwrap()
+                .map(|record| {
+                    let record = record.unwrap();
+                    assert_eq!(record.name, "echo");
+                    assert_eq!(record.args, std::slice::from_ref(&record.returned));
+                    String::from_utf8(record.returned).unwrap()
+                })
+                .collect()
+        };
+        assert_eq!(read(&rotated_path(&path, 2)), ["a", "b"]);
+        assert_eq!(read(&rotated_path(&path, 1)), ["c", "d"]);
+        assert_eq!(read(&path), ["e"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
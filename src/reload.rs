@@ -0,0 +1,391 @@
+//! Hot reloading, enabled with the `hot_reload` feature.
+//!
+//! BYOND keeps a library locked once it has loaded it, so a new build normally means restarting
+//! the server. Instead, BYOND can load a small loader library, which exports the same functions as
+//! the real library and forwards every call to it. Calling `byond_fn_reload` on the loader then
+//! swaps in a new build of the real library without BYOND noticing.
+//!
+//! A loader is a `cdylib` crate with the `hot_reload` feature enabled, which lists the functions
+//! to forward with [`forward!`](crate::reload::forward):
+//!
+//! ```ignore
+//! byond_fn::reload::forward! {
+//!     // loaded on the first call, until `byond_fn_reload` is called with another path
+//!     library = "example_crate";
+//!     add, add_optional, sum, "rust-sub", v2 lists, v2 "byond-lists"
+//! }
+//! ```
+//!
+//! Functions are listed by their exported name, as an ident or as a string for names that aren't
+//! valid idents, like ones given with `name = "..."` or with a prefix. Functions exported with the
+//! v2 transport are listed with `v2` in front, which requires the `ffi_v2` feature, as they're
+//! called differently. The loader can't tell from the library how a function is exported, so
+//! listing one with the wrong transport is undefined behavior. Functions with
+//! `errors = "runtime"` can't be forwarded, as their runtime errors would unwind through the
+//! loader.
+//!
+//! `call_ext("loader.dll", "byond_fn_reload")("example_crate.dll")` then reloads the library:
+//! 1. The library is copied to a versioned file next to it, like `example_crate.1234-1.dll`, so
+//!    the original is never locked and the next build can overwrite it.
+//! 2. The copy is loaded. If that fails, the error is returned and the old library stays in use.
+//! 3. New calls are held back, and in-flight calls on other threads are waited for.
+//! 4. `byond_fn_shutdown` is called on the old library, if it exports one.
+//! 5. The old library is unloaded and replaced with the new one.
+//!
+//! byond_fn's own exports, like `byond_fn_fetch_chunk` and `byond_fn_free_handle`, are exported by
+//! the loader too, as it's built with byond_fn. The loader forwards those to the loaded library
+//! without them being listed, so chunked returns, handles, metrics and the rest keep working
+//! through it.
+//!
+//! Nothing of the old library survives a reload, including its [handles](crate::handle), so a
+//! library built with the `hot_reload` feature can register hooks with [`on_shutdown`] to stop its
+//! threads and save whatever it needs. The hooks also run when DM calls `byond_fn_shutdown`
+//! directly, e.g. before a reboot.
+
+use std::ffi::{c_char, c_int, CStr};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError, RwLock};
+
+use libloading::Library;
+
+use crate::byond_fn;
+#[cfg(feature = "ffi_v2")]
+use crate::ffi_v2::{self, CByondValue};
+use crate::str_ffi::{byond_return, FFIError, TransportError};
+
+type StrShim = unsafe extern "C" fn(c_int, *const *const c_char) -> *const c_char;
+
+/// Forwards calls to a library that can be reloaded, see the [module docs](self).
+///
+/// Takes the path of the library to load on the first call, as passed to
+/// `libloading::library_filename` (so without a platform prefix or extension), followed by the
+/// exported names of the functions to forward, with `v2` in front of those exported with the v2
+/// transport. Also exports `byond_fn_reload`, and forwards byond_fn's own exports.
+#[macro_export]
+macro_rules! forward {
+    (@name $symbol:ident) => { stringify!($symbol) };
+    (@name $symbol:literal) => { $symbol };
+    (@shims) => {};
+    (@shims v2 $symbol:tt $(, $($rest:tt)*)?) => {
+        const _: () = {
+            #[export_name = $crate::reload::forward!(@name $symbol)]
+            unsafe extern "C" fn forwarded(
+                argc: u32,
+                argv: *const $crate::ffi_v2::CByondValue,
+            ) -> $crate::ffi_v2::CByondValue {
+                $crate::reload::forward_call_v2(
+                    concat!($crate::reload::forward!(@name $symbol), "\0").as_bytes(),
+                    library,
+                    argc,
+                    argv,
+                )
+            }
+        };
+        $crate::reload::forward!(@shims $($($rest)*)?);
+    };
+    (@shims $symbol:tt $(, $($rest:tt)*)?) => {
+        const _: () = {
+            #[export_name = $crate::reload::forward!(@name $symbol)]
+            unsafe extern "C" fn forwarded(
+                argc: ::std::os::raw::c_int,
+                argv: *const *const ::std::os::raw::c_char,
+            ) -> *const ::std::os::raw::c_char {
+                $crate::reload::forward_call(
+                    concat!($crate::reload::forward!(@name $symbol), "\0").as_bytes(),
+                    library,
+                    argc,
+                    argv,
+                )
+            }
+        };
+        $crate::reload::forward!(@shims $($($rest)*)?);
+    };
+    (library = $library:expr; $($symbols:tt)*) => {
+        #[doc(hidden)]
+        mod __byond_fn_forward {
+            fn library() -> ::std::path::PathBuf {
+                $crate::reload::default_path($library)
+            }
+
+            // registered as soon as the loader is loaded, as byond_fn's own exports may be called
+            // before anything listed here
+            #[used]
+            #[cfg_attr(
+                any(target_os = "linux", target_os = "android", target_os = "freebsd"),
+                link_section = ".init_array"
+            )]
+            #[cfg_attr(windows, link_section = ".CRT$XCU")]
+            #[cfg_attr(target_vendor = "apple", link_section = "__DATA,__mod_init_func")]
+            static REGISTER_LOADER: extern "C" fn() = {
+                extern "C" fn register() {
+                    $crate::reload::register_loader(library);
+                }
+                register
+            };
+
+            $crate::reload::forward!(@shims $($symbols)*);
+
+            /// Loads the library at `path` in place of the current one.
+            #[$crate::byond_fn(name = "byond_fn_reload")]
+            fn reload(path: ::std::path::PathBuf) -> Result<(), $crate::str_ffi::FFIError> {
+                $crate::reload::reload(&path)
+            }
+        }
+    };
+}
+
+pub use forward;
+
+struct Loaded {
+    library: Library,
+    copy: PathBuf,
+}
+
+fn loaded() -> &'static RwLock<Option<Loaded>> {
+    static LOADED: OnceLock<RwLock<Option<Loaded>>> = OnceLock::new();
+    LOADED.get_or_init(Default::default)
+}
+
+type Hooks = Mutex<Vec<fn()>>;
+
+fn shutdown_hooks() -> &'static Hooks {
+    static HOOKS: OnceLock<Hooks> = OnceLock::new();
+    HOOKS.get_or_init(Default::default)
+}
+
+/// Registers a function to run when this library is shut down, before a reload or when DM calls
+/// `byond_fn_shutdown`. Hooks run once, most recently registered first.
+pub fn on_shutdown(hook: fn()) {
+    shutdown_hooks()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(hook);
+}
+
+/// Runs the shutdown hooks of this library and frees its chunked transfers, and shuts down the
+/// library loaded by it, if any.
+#[byond_fn(name = "byond_fn_shutdown")]
+fn shutdown() {
+    let hooks = std::mem::take(
+        &mut *shutdown_hooks()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner),
+    );
+    for hook in hooks.into_iter().rev() {
+        hook();
+    }
+    crate::str_ffi::chunked::clear_transfers();
+    if let Some(current) = &*loaded().read().unwrap_or_else(PoisonError::into_inner) {
+        shutdown_library(&current.library);
+    }
+}
+
+fn shutdown_library(library: &Library) {
+    // SAFETY: `byond_fn_shutdown` is generated by `#[byond_fn]`, which gives it this signature
+    if let Ok(shutdown) = unsafe { library.get::<StrShim>(b"byond_fn_shutdown\0") } {
+        unsafe { shutdown(0, std::ptr::null()) };
+    }
+}
+
+/// The path of a library named `name`, with the platform's prefix and extension.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub fn default_path(name: &str) -> PathBuf {
+    PathBuf::from(libloading::library_filename(name))
+}
+
+/// The file `path` is copied to before loading, which is unique to this process and reload.
+fn versioned_path(path: &Path) -> PathBuf {
+    static GENERATION: AtomicU64 = AtomicU64::new(1);
+    let generation = GENERATION.fetch_add(1, Ordering::Relaxed);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}.{}-{generation}", std::process::id());
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+fn load(path: &Path) -> Result<Loaded, FFIError> {
+    let load_error = |reason: String| TransportError::LibraryLoad {
+        path: path.display().to_string(),
+        reason,
+    };
+    let copy = versioned_path(path);
+    fs::copy(path, &copy).map_err(|err| load_error(err.to_string()))?;
+    let absolute = fs::canonicalize(&copy).unwrap_or_else(|_| copy.clone());
+    // SAFETY: loading a library runs its initializers, which is up to the library being sound
+    match unsafe { Library::new(&absolute) } {
+        Ok(library) => Ok(Loaded { library, copy }),
+        Err(err) => {
+            let _ = fs::remove_file(&copy);
+            Err(load_error(err.to_string()).into())
+        }
+    }
+}
+
+fn unload(old: Loaded) {
+    shutdown_library(&old.library);
+    drop(old.library);
+    // may still be locked, if the library couldn't actually be unloaded
+    let _ = fs::remove_file(old.copy);
+}
+
+/// Loads the library at `path`, and swaps it in for the current one once no calls are in flight.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If the library can't be copied or loaded, in which case the current library stays loaded.
+pub fn reload(path: &Path) -> Result<(), FFIError> {
+    let new = load(path)?;
+    // taking the write lock waits for every call holding the read lock to return, and holding it
+    // keeps new calls out until the old library is gone
+    let mut current = loaded().write().unwrap_or_else(PoisonError::into_inner);
+    if let Some(old) = current.replace(new) {
+        unload(old);
+    }
+    Ok(())
+}
+
+/// Calls the function exported as `symbol` by the loaded library, loading the library from
+/// `library()` first if nothing is loaded yet. The returned string is copied before any reload can
+/// unload the library it belongs to.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Safety
+/// Same as [`parse_str_args`](crate::str_ffi::parse_str_args). `symbol` must be NUL terminated.
+pub unsafe fn forward_call(
+    symbol: &[u8],
+    library: fn() -> PathBuf,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> *const c_char {
+    with_loaded(library, byond_return, |loaded| unsafe {
+        call(loaded, symbol, argc, argv)
+    })
+}
+
+/// Same as [`forward_call`], for a function exported with the v2 transport. The returned value
+/// belongs to BYOND, so it stays valid through a reload.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Safety
+/// Same as [`parse_args`](crate::ffi_v2::parse_args). `symbol` must be NUL terminated, and be a
+/// function exported with the v2 transport.
+#[cfg(feature = "ffi_v2")]
+pub unsafe fn forward_call_v2(
+    symbol: &[u8],
+    library: fn() -> PathBuf,
+    argc: u32,
+    argv: *const CByondValue,
+) -> CByondValue {
+    with_loaded(library, ffi_v2::byond_return, |loaded| {
+        type V2Shim = unsafe extern "C" fn(u32, *const CByondValue) -> CByondValue;
+        // SAFETY: the caller lists `symbol` as exported with the v2 transport
+        match unsafe { loaded.get::<V2Shim>(symbol) } {
+            Ok(shim) => unsafe { shim(argc, argv) },
+            Err(_) => ffi_v2::byond_return(TransportError::MissingSymbol(symbol_name(symbol))),
+        }
+    })
+}
+
+/// Runs `f` with the loaded library, loading it from `library()` first if nothing is loaded yet.
+/// No reload can happen until `f` returns.
+fn with_loaded<R>(
+    library: fn() -> PathBuf,
+    on_error: impl FnOnce(FFIError) -> R,
+    f: impl FnOnce(&Library) -> R,
+) -> R {
+    let lock = loaded();
+    loop {
+        let current = lock.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(current) = &*current {
+            return f(&current.library);
+        }
+        drop(current);
+        let mut empty = lock.write().unwrap_or_else(PoisonError::into_inner);
+        if empty.is_none() {
+            match load(&library()) {
+                Ok(new) => *empty = Some(new),
+                Err(err) => return on_error(err),
+            }
+        }
+    }
+}
+
+static LOADER: OnceLock<fn() -> PathBuf> = OnceLock::new();
+
+/// Makes this copy of byond_fn a loader's, which forwards byond_fn's own exports to the library
+/// loaded from `library()`. Called by [`forward!`] as soon as the loader is loaded.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub fn register_loader(library: fn() -> PathBuf) {
+    let _ = LOADER.set(library);
+}
+
+/// Forwards a call to one of byond_fn's own exports to the loaded library, if this copy of
+/// byond_fn is a loader's. Returns `None` otherwise, in which case the call is handled here.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Safety
+/// Same as [`forward_call`].
+pub unsafe fn forward_builtin(
+    symbol: &[u8],
+    argc: c_int,
+    argv: *const *const c_char,
+) -> Option<*const c_char> {
+    let library = *LOADER.get()?;
+    Some(unsafe { forward_call(symbol, library, argc, argv) })
+}
+
+unsafe fn call(
+    library: &Library,
+    symbol: &[u8],
+    argc: c_int,
+    argv: *const *const c_char,
+) -> *const c_char {
+    // SAFETY: every function forwarded is generated by `#[byond_fn]`, which gives it this signature
+    let Ok(shim) = (unsafe { library.get::<StrShim>(symbol) }) else {
+        return byond_return(TransportError::MissingSymbol(symbol_name(symbol)));
+    };
+    let returned = unsafe { shim(argc, argv) };
+    if returned.is_null() {
+        return byond_return(());
+    }
+    // SAFETY: functions generated by `#[byond_fn]` always return a NUL terminated string
+    byond_return(unsafe { CStr::from_ptr(returned) }.to_bytes().to_vec())
+}
+
+fn symbol_name(symbol: &[u8]) -> String {
+    String::from_utf8_lossy(symbol.strip_suffix(b"\0").unwrap_or(symbol)).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn copies_are_versioned() {
+        let first = versioned_path(Path::new("bin/libexample.so"));
+        let second = versioned_path(Path::new("bin/libexample.so"));
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(Path::new("bin")));
+        let name = first.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("libexample."), "{name}");
+        assert!(name.ends_with(".so"), "{name}");
+    }
+
+    #[test]
+    fn failed_reload_is_an_error() {
+        let err = reload(Path::new("does/not/exist.dll")).unwrap_err();
+        assert!(err.to_string().contains("LIBRARY_LOAD"), "{err}");
+    }
+}
@@ -0,0 +1,339 @@
+//! Binary data over string transport.
+//!
+//! BYOND reads returned strings up to the first NUL byte, so a return containing one would be cut
+//! short. Rather than truncating silently, returns are checked against a [`NulPolicy`], set for the
+//! whole library with [`set_nul_policy`] or for a single function with
+//! `#[byond_fn(nul = "...")]`:
+//!
+//! - `"error"` (the default) - return a `RETURN_NUL` error instead of the value
+//! - `"escape"` - escape NUL as `\0`, and `\` as `\\` so the escaping can be reversed
+//! - `"strip"` - drop NUL bytes from the value
+//!
+//! To send arbitrary bytes in either direction, wrap them in [`Base64`] or [`Hex`] instead:
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//! use byond_fn::str_ffi::binary::{Base64, Hex};
+//!
+//! #[byond_fn]
+//! pub fn checksum(data: Base64<Vec<u8>>) -> Hex<[u8; 4]> {
+//!     let sum = data.iter().fold(0u32, |sum, &byte| sum.wrapping_add(u32::from(byte)));
+//!     Hex(sum.to_be_bytes())
+//! }
+//!
+//! #[byond_fn(nul = "strip")]
+//! pub fn read_raw() -> Vec<u8> {
+//!     b"\0padded\0".to_vec()
+//! }
+//! # fn main() {}
+//! ```
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::str_ffi::encoding::Encoding;
+use crate::str_ffi::{FFIError, StrArg, StrReturn, TransportError};
+
+/// What to do with NUL bytes in a value returned to BYOND
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum NulPolicy {
+    /// Return a `TransportError::ReturnNul` instead of the value
+    #[default]
+    Error,
+    /// Escape NUL as `\0`, and `\` as `\\`
+    Escape,
+    /// Drop NUL bytes
+    Strip,
+}
+
+impl NulPolicy {
+    /// Parses a policy from its name, as used in `#[byond_fn(nul = "...")]`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "error" => Some(Self::Error),
+            "escape" => Some(Self::Escape),
+            "strip" => Some(Self::Strip),
+            _ => None,
+        }
+    }
+
+    /// Applies the policy to returned bytes.
+    ///
+    /// # Errors
+    ///
+    /// Under `NulPolicy::Error`, a NUL byte in `bytes` returns a `TransportError::ReturnNul`.
+    pub fn apply(self, mut bytes: Vec<u8>) -> Result<Vec<u8>, TransportError> {
+        match self {
+            Self::Error => match bytes.iter().position(|&byte| byte == 0) {
+                Some(position) => Err(TransportError::ReturnNul { position }),
+                None => Ok(bytes),
+            },
+            Self::Escape => Ok(escape_nul(&bytes)),
+            Self::Strip => {
+                bytes.retain(|&byte| byte != 0);
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+fn escape_nul(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            0 => escaped.extend_from_slice(b"\\0"),
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            _ => escaped.push(byte),
+        }
+    }
+    escaped
+}
+
+static NUL_POLICY: AtomicU8 = AtomicU8::new(NulPolicy::Error as u8);
+
+/// Sets the policy used by every function that doesn't set its own with `#[byond_fn(nul = "...")]`.
+pub fn set_nul_policy(policy: NulPolicy) {
+    NUL_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// The policy used by every function that doesn't set its own. Defaults to `NulPolicy::Error`.
+pub fn nul_policy() -> NulPolicy {
+    match NUL_POLICY.load(Ordering::Relaxed) {
+        1 => NulPolicy::Escape,
+        2 => NulPolicy::Strip,
+        _ => NulPolicy::Error,
+    }
+}
+
+/// Wraps a return value to apply a NUL policy to it.
+///
+/// This is what `#[byond_fn(nul = "...")]` wraps results in, but is exposed in case you want the
+/// same functionality.
+pub struct NulChecked<T> {
+    value: T,
+    policy: NulPolicy,
+}
+
+impl<T: StrReturn> NulChecked<T> {
+    pub fn new(value: T, policy: NulPolicy) -> Self {
+        NulChecked { value, policy }
+    }
+}
+
+impl<T: StrReturn> StrReturn for NulChecked<T> {
+    fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
+        let bytes = self.value.to_return()?;
+        Ok(bytes.map(|bytes| self.policy.apply(bytes)).transpose()?)
+    }
+
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError> {
+        let bytes = self.value.to_encoded_return(encoding)?;
+        Ok(bytes.map(|bytes| self.policy.apply(bytes)).transpose()?)
+    }
+}
+
+macro_rules! binary_wrapper {
+    ($(#[$doc:meta])* $name:ident, $encode:ident, $decode:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name<T>(pub T);
+
+        impl<T> $name<T> {
+            pub fn into_inner(self) -> T {
+                self.0
+            }
+        }
+
+        impl<T> From<T> for $name<T> {
+            fn from(value: T) -> Self {
+                $name(value)
+            }
+        }
+
+        impl<T> Deref for $name<T> {
+            type Target = T;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $name<T> {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+
+        impl<T: AsRef<[u8]>> StrReturn for $name<T> {
+            fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
+                Ok(Some($encode(self.0.as_ref()).into_bytes()))
+            }
+        }
+
+        impl<'a> StrArg<'a> for $name<Vec<u8>> {
+            fn from_arg(arg: &'a str, arg_name: &str) -> Result<Self, FFIError> {
+                $decode(arg)
+                    .map($name)
+                    .ok_or_else(|| arg_parse_error(arg, arg_name))
+            }
+        }
+
+        impl<'a, const N: usize> StrArg<'a> for $name<[u8; N]> {
+            fn from_arg(arg: &'a str, arg_name: &str) -> Result<Self, FFIError> {
+                $decode(arg)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map($name)
+                    .ok_or_else(|| arg_parse_error(arg, arg_name))
+            }
+        }
+    };
+}
+
+fn arg_parse_error(arg: &str, arg_name: &str) -> FFIError {
+    TransportError::ArgParse {
+        arg_name: arg_name.to_string(),
+        actual_content: arg.to_string(),
+    }
+    .into()
+}
+
+binary_wrapper! {
+    /// Binary data passed as standard, padded base64.
+    ///
+    /// Can be returned for anything that is `AsRef<[u8]>`, and taken as an argument for `Vec<u8>`
+    /// or `[u8; N]`. Arrays fail to parse unless the decoded length is exactly `N`.
+    Base64, encode_base64, decode_base64
+}
+
+binary_wrapper! {
+    /// Binary data passed as hex. Returns are lowercase, and arguments are accepted in either case.
+    ///
+    /// Can be returned for anything that is `AsRef<[u8]>`, and taken as an argument for `Vec<u8>`
+    /// or `[u8; N]`. Arrays fail to parse unless the decoded length is exactly `N`.
+    Hex, encode_hex, decode_hex
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `bytes` as base64.
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (idx, &byte)| {
+            group | u32::from(byte) << (16 - idx * 8)
+        });
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                let sextet = (group >> (18 - idx * 6)) & 0x3F;
+                encoded.push(char::from(BASE64_ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes base64, with or without padding. Returns `None` if `text` isn't valid base64.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    let unpadded = text
+        .strip_suffix(b"==")
+        .or_else(|| text.strip_suffix(b"="))
+        .unwrap_or(text);
+    if unpadded.len() % 4 == 1 || (unpadded.len() != text.len() && !text.len().is_multiple_of(4)) {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(unpadded.len() * 3 / 4);
+    for chunk in unpadded.chunks(4) {
+        let mut group = 0u32;
+        for (idx, &char) in chunk.iter().enumerate() {
+            let sextet = BASE64_ALPHABET.iter().position(|&valid| valid == char)?;
+            group |= (sextet as u32) << (18 - idx * 6);
+        }
+        let group = group.to_be_bytes();
+        decoded.extend_from_slice(&group[1..chunk.len()]);
+    }
+    Some(decoded)
+}
+
+/// Encodes `bytes` as lowercase hex.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Decodes hex in either case. Returns `None` if `text` isn't valid hex.
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            // from_str_radix would also accept a sign
+            if !pair.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nul_policies() {
+        let value = b"a\0b\\c".to_vec();
+        assert!(matches!(
+            NulPolicy::Error.apply(value.clone()),
+            Err(TransportError::ReturnNul { position: 1 })
+        ));
+        assert_eq!(
+            NulPolicy::Escape.apply(value.clone()).unwrap(),
+            b"a\\0b\\\\c"
+        );
+        assert_eq!(NulPolicy::Strip.apply(value).unwrap(), b"ab\\c");
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"\0\xFF\x80", "AP+A"),
+        ] {
+            assert_eq!(encode_base64(bytes), encoded);
+            assert_eq!(decode_base64(encoded).unwrap(), bytes);
+        }
+        assert_eq!(decode_base64("Zm8").unwrap(), b"fo");
+        assert!(decode_base64("Zm9vY").is_none());
+        assert!(decode_base64("Zm$v").is_none());
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(encode_hex(b"\0\xAB\x10"), "00ab10");
+        assert_eq!(decode_hex("00AB10").unwrap(), b"\0\xAB\x10");
+        assert!(decode_hex("abc").is_none());
+        assert!(decode_hex("zz").is_none());
+        assert!(decode_hex("+f").is_none());
+    }
+
+    #[test]
+    fn array_args_check_length() {
+        assert_eq!(
+            Hex::<[u8; 2]>::from_arg("beef", "arg").unwrap(),
+            Hex([0xBE, 0xEF])
+        );
+        assert!(Hex::<[u8; 2]>::from_arg("be", "arg").is_err());
+        assert!(Base64::<[u8; 2]>::from_arg("Zm9v", "arg").is_err());
+    }
+}
@@ -0,0 +1,1026 @@
+//! JSON as produced by BYOND's `json_encode`, which [`Json`](crate::str_ffi::json::Json) is too
+//! strict for.
+//!
+//! [`ByondJson<T>`] accepts the quirks of `json_encode` output:
+//! - an empty assoc list encodes as `[]`, which is accepted wherever a map or struct is expected
+//! - numbers may be written like `1e+006`, which is accepted for integers if the value is whole
+//! - non-finite numbers come out as bare `1.#INF`, `-1.#IND` and the like, which are accepted for
+//!   floats (as strings too)
+//! - datum refs come out as `"[0x...]"` strings, which can be taken as a [`DatumRef`]
+//!
+//! Returns are plain JSON, which `json_decode` reads back as-is, apart from non-finite numbers.
+//! JSON has no way to represent them, so they are written the way `json_encode` writes them
+//! (`1.#INF`, `-1.#INF` and `-1.#IND`), where `Json<T>` would write `null`.
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//! use byond_fn::str_ffi::byond_json::{ByondJson, DatumRef};
+//! use std::collections::HashMap;
+//!
+//! #[derive(serde::Deserialize)]
+//! pub struct Mob {
+//!     owner: DatumRef,
+//!     health: u32,
+//!     // `list()` comes out as `[]`
+//!     vars: HashMap<String, String>,
+//! }
+//!
+//! #[byond_fn]
+//! pub fn mob_health(mob: ByondJson<Mob>) -> u32 {
+//!     mob.0.health
+//! }
+//! # fn main() {}
+//! ```
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::marker::PhantomData;
+
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, EnumAccess, Error as _, IgnoredAny, MapAccess, SeqAccess,
+    Unexpected, VariantAccess, Visitor,
+};
+use serde::ser::{
+    SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::str_ffi::encoding::Encoding;
+use crate::str_ffi::json::JsonError;
+use crate::str_ffi::limits::current_limits;
+use crate::str_ffi::{FFIError, StrArg, StrReturn};
+
+/// Wraps another type to represent it should be parsed from, or returned as, JSON compatible with
+/// BYOND's `json_encode` and `json_decode`. See the [module docs](self) for the differences from
+/// [`Json`](crate::str_ffi::json::Json).
+///
+/// Unlike `Json<T>`, arguments can't borrow from the string passed from BYOND, since it may need
+/// to be rewritten before parsing.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct ByondJson<T>(pub T);
+
+impl<T> ByondJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for ByondJson<T> {
+    fn from(t: T) -> Self {
+        ByondJson(t)
+    }
+}
+
+impl<T: Serialize> StrReturn for ByondJson<T> {
+    fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
+        to_vec(&self.0)
+            .map_err(JsonError::ReturnSerialize)
+            .map_err(FFIError::JsonError)
+            .map(Some)
+    }
+
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError> {
+        let json = self.to_return()?.unwrap_or_default();
+        // JSON is always written as UTF-8
+        Ok(Some(match String::from_utf8(json) {
+            Ok(json) => encoding.encode_string(json),
+            Err(err) => err.into_bytes(),
+        }))
+    }
+}
+
+impl<'a, T: DeserializeOwned> StrArg<'a> for ByondJson<T> {
+    fn from_arg(arg: &'a str, arg_name: &str) -> Result<Self, FFIError> {
+        current_limits().check_json(arg, arg_name)?;
+        from_str(arg).map(ByondJson).map_err(FFIError::JsonError)
+    }
+}
+
+/// Parses `json_encode` output into a `T`.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If `text` isn't valid JSON once the quirks are accounted for, or doesn't match `T`.
+pub fn from_str<T: DeserializeOwned>(text: &str) -> Result<T, JsonError> {
+    let normalized = normalize(text);
+    let mut deserializer = serde_json::Deserializer::from_str(&normalized);
+    let deserialized =
+        serde_path_to_error::deserialize(Compat(&mut deserializer)).map_err(|err| {
+            JsonError::ArgDeserialize {
+                path: err.path().to_string(),
+                source: err.into_inner(),
+            }
+        })?;
+    deserializer
+        .end()
+        .map_err(|source| JsonError::ArgDeserialize {
+            path: ".".to_string(),
+            source,
+        })?;
+    Ok(deserialized)
+}
+
+/// Writes `value` as JSON for `json_decode`, with non-finite numbers written the way
+/// `json_encode` writes them.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If `value` can't be serialized as JSON.
+pub fn to_vec<T: ?Sized + Serialize>(value: &T) -> Result<Vec<u8>, serde_json::Error> {
+    let raw = Cell::new(false);
+    let mut writer = Vec::with_capacity(128);
+    let mut serializer =
+        serde_json::Serializer::with_formatter(&mut writer, CompatFormatter { raw: &raw });
+    value.serialize(CompatSerializer {
+        inner: &mut serializer,
+        raw: &raw,
+    })?;
+    Ok(writer)
+}
+
+/// A reference to a datum, as `json_encode` writes it: `"[0x2000001]"`.
+///
+/// Resolve it in DM with `locate()` on the same string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DatumRef(pub u32);
+
+impl DatumRef {
+    /// Parses a ref in the `[0x...]` form.
+    pub fn parse(text: &str) -> Option<Self> {
+        let hex = text.strip_prefix("[0x")?.strip_suffix(']')?;
+        u32::from_str_radix(hex, 16).ok().map(DatumRef)
+    }
+}
+
+impl Display for DatumRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[0x{:x}]", self.0)
+    }
+}
+
+impl Serialize for DatumRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DatumRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = Cow::<str>::deserialize(deserializer)?;
+        DatumRef::parse(&text).ok_or_else(|| {
+            D::Error::invalid_value(Unexpected::Str(&text), &"a datum ref like \"[0x2000001]\"")
+        })
+    }
+}
+
+/// Non-finite numbers as BYOND and C runtimes write them
+fn non_finite(token: &str) -> Option<f64> {
+    match token.to_ascii_lowercase().as_str() {
+        "1.#inf" | "inf" | "+inf" | "infinity" | "+infinity" => Some(f64::INFINITY),
+        "-1.#inf" | "-inf" | "-infinity" => Some(f64::NEG_INFINITY),
+        "1.#ind" | "-1.#ind" | "1.#qnan" | "-1.#qnan" | "nan" | "-nan" | "-nan(ind)" => {
+            Some(f64::NAN)
+        }
+        _ => None,
+    }
+}
+
+/// Quotes the bare non-finite numbers in `text`, so it parses as JSON. Numbers too large for an
+/// `f64` are quoted as infinities, as serde_json rejects them.
+fn normalize(text: &str) -> Cow<'_, str> {
+    let bytes = text.as_bytes();
+    let mut normalized = String::new();
+    let mut copied = 0;
+    let mut idx = 0;
+    let mut in_string = false;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        if in_string {
+            match byte {
+                b'\\' => idx += 1,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            idx += 1;
+            continue;
+        }
+        if byte == b'"' {
+            in_string = true;
+            idx += 1;
+            continue;
+        }
+        if !(byte == b'-' || byte == b'+' || byte.is_ascii_alphanumeric()) {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        while idx < bytes.len()
+            && (bytes[idx].is_ascii_alphanumeric() || b".#+-()".contains(&bytes[idx]))
+        {
+            idx += 1;
+        }
+        let token = &text[start..idx];
+        let replacement = non_finite(token).or_else(|| {
+            token
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_infinite())
+        });
+        if let Some(number) = replacement {
+            normalized.push_str(&text[copied..start]);
+            normalized.push_str(match number {
+                number if number.is_nan() => "\"1.#IND\"",
+                number if number > 0.0 => "\"1.#INF\"",
+                _ => "\"-1.#INF\"",
+            });
+            copied = idx;
+        }
+    }
+    if copied == 0 {
+        return Cow::Borrowed(text);
+    }
+    normalized.push_str(&text[copied..]);
+    Cow::Owned(normalized)
+}
+
+/// What the type being deserialized asked for, for the quirks that depend on it
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Want {
+    Integer,
+    Float,
+    Map,
+    Other,
+}
+
+/// A deserializer that applies the `json_encode` quirks to everything deserialized through it
+struct Compat<D>(D);
+
+/// A visitor that applies the quirks to the values it's given, and wraps nested deserializers
+struct CompatVisitor<V> {
+    inner: V,
+    want: Want,
+}
+
+/// A seed that deserializes its value through `Compat`
+struct CompatSeed<S>(S);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for CompatSeed<S> {
+    type Value = S::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.0.deserialize(Compat(deserializer))
+    }
+}
+
+macro_rules! forward_want {
+    ($want:expr, $via:ident => $($method:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.0.$via(CompatVisitor { inner: visitor, want: $want })
+            }
+        )*
+    };
+}
+
+impl<'de, D: Deserializer<'de>> Deserializer<'de> for Compat<D> {
+    type Error = D::Error;
+
+    // integers keep their own method, which serde_json also uses to parse them out of map keys
+    forward_want!(Want::Integer, deserialize_i8 => deserialize_i8);
+    forward_want!(Want::Integer, deserialize_i16 => deserialize_i16);
+    forward_want!(Want::Integer, deserialize_i32 => deserialize_i32);
+    forward_want!(Want::Integer, deserialize_i64 => deserialize_i64);
+    forward_want!(Want::Integer, deserialize_i128 => deserialize_i128);
+    forward_want!(Want::Integer, deserialize_u8 => deserialize_u8);
+    forward_want!(Want::Integer, deserialize_u16 => deserialize_u16);
+    forward_want!(Want::Integer, deserialize_u32 => deserialize_u32);
+    forward_want!(Want::Integer, deserialize_u64 => deserialize_u64);
+    forward_want!(Want::Integer, deserialize_u128 => deserialize_u128);
+    // floats and maps go through `deserialize_any`, as they may be given a string or a list
+    forward_want!(Want::Float, deserialize_any => deserialize_f32, deserialize_f64);
+    forward_want!(Want::Map, deserialize_any => deserialize_map);
+    forward_want!(Want::Other, deserialize_any => deserialize_any);
+    forward_want!(Want::Other, deserialize_bool => deserialize_bool);
+    forward_want!(Want::Other, deserialize_char => deserialize_char);
+    forward_want!(Want::Other, deserialize_str => deserialize_str);
+    forward_want!(Want::Other, deserialize_string => deserialize_string);
+    forward_want!(Want::Other, deserialize_bytes => deserialize_bytes);
+    forward_want!(Want::Other, deserialize_byte_buf => deserialize_byte_buf);
+    forward_want!(Want::Other, deserialize_option => deserialize_option);
+    forward_want!(Want::Other, deserialize_unit => deserialize_unit);
+    forward_want!(Want::Other, deserialize_seq => deserialize_seq);
+    forward_want!(Want::Other, deserialize_identifier => deserialize_identifier);
+    forward_want!(Want::Other, deserialize_ignored_any => deserialize_ignored_any);
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_unit_struct(
+            name,
+            CompatVisitor {
+                inner: visitor,
+                want: Want::Other,
+            },
+        )
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_newtype_struct(
+            name,
+            CompatVisitor {
+                inner: visitor,
+                want: Want::Other,
+            },
+        )
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_tuple(
+            len,
+            CompatVisitor {
+                inner: visitor,
+                want: Want::Other,
+            },
+        )
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_tuple_struct(
+            name,
+            len,
+            CompatVisitor {
+                inner: visitor,
+                want: Want::Other,
+            },
+        )
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_any(CompatVisitor {
+            inner: visitor,
+            want: Want::Map,
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(
+            name,
+            variants,
+            CompatVisitor {
+                inner: visitor,
+                want: Want::Other,
+            },
+        )
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty)),*) => {
+        $(
+            fn $method<E: serde::de::Error>(self, value: $ty) -> Result<Self::Value, E> {
+                self.inner.$method(value)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for CompatVisitor<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        self.inner.expecting(formatter)
+    }
+
+    forward_visit!(
+        visit_bool(bool),
+        visit_i8(i8),
+        visit_i16(i16),
+        visit_i32(i32),
+        visit_i64(i64),
+        visit_i128(i128),
+        visit_u8(u8),
+        visit_u16(u16),
+        visit_u32(u32),
+        visit_u64(u64),
+        visit_u128(u128),
+        visit_f32(f32),
+        visit_char(char),
+        visit_bytes(&[u8]),
+        visit_byte_buf(Vec<u8>)
+    );
+
+    fn visit_f64<E: serde::de::Error>(self, value: f64) -> Result<Self::Value, E> {
+        // `1e+006` is a float to serde_json, but a whole number to BYOND
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        if self.want == Want::Integer && value.fract() == 0.0 {
+            if (0.0..=u64::MAX as f64).contains(&value) {
+                return self.inner.visit_u64(value as u64);
+            }
+            if (i64::MIN as f64..0.0).contains(&value) {
+                return self.inner.visit_i64(value as i64);
+            }
+        }
+        self.inner.visit_f64(value)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        match (self.want, non_finite(value)) {
+            (Want::Float, Some(number)) => self.inner.visit_f64(number),
+            _ => self.inner.visit_str(value),
+        }
+    }
+
+    fn visit_borrowed_str<E: serde::de::Error>(self, value: &'de str) -> Result<Self::Value, E> {
+        match (self.want, non_finite(value)) {
+            (Want::Float, Some(number)) => self.inner.visit_f64(number),
+            _ => self.inner.visit_borrowed_str(value),
+        }
+    }
+
+    fn visit_string<E: serde::de::Error>(self, value: String) -> Result<Self::Value, E> {
+        match (self.want, non_finite(&value)) {
+            (Want::Float, Some(number)) => self.inner.visit_f64(number),
+            _ => self.inner.visit_string(value),
+        }
+    }
+
+    fn visit_borrowed_bytes<E: serde::de::Error>(self, value: &'de [u8]) -> Result<Self::Value, E> {
+        self.inner.visit_borrowed_bytes(value)
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        self.inner.visit_none()
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.inner.visit_some(Compat(deserializer))
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+        self.inner.visit_unit()
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        self.inner.visit_newtype_struct(Compat(deserializer))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        if self.want != Want::Map {
+            return self.inner.visit_seq(Compat(seq));
+        }
+        // an empty assoc list is written as `[]`, anything else can't be a map
+        if seq.next_element::<IgnoredAny>()?.is_some() {
+            return Err(A::Error::invalid_type(Unexpected::Seq, &self.inner));
+        }
+        self.inner.visit_map(EmptyMap(PhantomData))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.inner.visit_map(Compat(map))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.inner.visit_enum(Compat(data))
+    }
+}
+
+/// The map an empty `[]` stands in for
+struct EmptyMap<E>(PhantomData<E>);
+
+impl<'de, E: serde::de::Error> MapAccess<'de> for EmptyMap<E> {
+    type Error = E;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        _seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        Ok(None)
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        _seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        Err(E::custom("an empty map has no values"))
+    }
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Compat<A> {
+    type Error = A::Error;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, Self::Error> {
+        self.0.next_element_seed(CompatSeed(seed))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Compat<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        self.0.next_key_seed(CompatSeed(seed))
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        self.0.next_value_seed(CompatSeed(seed))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for Compat<A> {
+    type Error = A::Error;
+    type Variant = Compat<A::Variant>;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Self::Variant), Self::Error> {
+        self.0
+            .variant_seed(CompatSeed(seed))
+            .map(|(value, variant)| (value, Compat(variant)))
+    }
+}
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for Compat<A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        self.0.unit_variant()
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        self.0.newtype_variant_seed(CompatSeed(seed))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.tuple_variant(
+            len,
+            CompatVisitor {
+                inner: visitor,
+                want: Want::Other,
+            },
+        )
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.struct_variant(
+            fields,
+            CompatVisitor {
+                inner: visitor,
+                want: Want::Map,
+            },
+        )
+    }
+}
+
+/// The way `json_encode` writes a non-finite number, or `None` if `number` is finite
+fn non_finite_token(number: f64) -> Option<&'static str> {
+    match number {
+        number if number.is_nan() => Some("-1.#IND"),
+        number if number == f64::INFINITY => Some("1.#INF"),
+        number if number == f64::NEG_INFINITY => Some("-1.#INF"),
+        _ => None,
+    }
+}
+
+/// A formatter that writes the next string without its quotes while `raw` is set, which is how
+/// non-finite numbers get through serde_json, as it writes them as `null` itself.
+struct CompatFormatter<'a> {
+    raw: &'a Cell<bool>,
+}
+
+impl serde_json::ser::Formatter for CompatFormatter<'_> {
+    fn begin_string<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.raw.get() {
+            return Ok(());
+        }
+        writer.write_all(b"\"")
+    }
+
+    fn end_string<W: ?Sized + io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.raw.replace(false) {
+            return Ok(());
+        }
+        writer.write_all(b"\"")
+    }
+}
+
+/// A serializer that writes non-finite numbers through `CompatFormatter`, and wraps the values of
+/// compound types so theirs are too
+struct CompatSerializer<'a, S> {
+    inner: S,
+    raw: &'a Cell<bool>,
+}
+
+/// A value serialized through `CompatSerializer`
+struct CompatValue<'a, T: ?Sized> {
+    value: &'a T,
+    raw: &'a Cell<bool>,
+}
+
+impl<T: ?Sized + Serialize> Serialize for CompatValue<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(CompatSerializer {
+            inner: serializer,
+            raw: self.raw,
+        })
+    }
+}
+
+impl<S: Serializer> CompatSerializer<'_, S> {
+    fn serialize_float(self, number: f64) -> Result<S::Ok, S::Error> {
+        match non_finite_token(number) {
+            Some(token) => {
+                self.raw.set(true);
+                self.inner.serialize_str(token)
+            }
+            None => self.inner.serialize_f64(number),
+        }
+    }
+}
+
+macro_rules! forward_serialize {
+    ($($method:ident($ty:ty)),*) => {
+        $(
+            fn $method(self, value: $ty) -> Result<S::Ok, S::Error> {
+                self.inner.$method(value)
+            }
+        )*
+    };
+}
+
+impl<'a, S: Serializer> Serializer for CompatSerializer<'a, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = CompatSerializer<'a, S::SerializeSeq>;
+    type SerializeTuple = CompatSerializer<'a, S::SerializeTuple>;
+    type SerializeTupleStruct = CompatSerializer<'a, S::SerializeTupleStruct>;
+    type SerializeTupleVariant = CompatSerializer<'a, S::SerializeTupleVariant>;
+    type SerializeMap = CompatSerializer<'a, S::SerializeMap>;
+    type SerializeStruct = CompatSerializer<'a, S::SerializeStruct>;
+    type SerializeStructVariant = CompatSerializer<'a, S::SerializeStructVariant>;
+
+    forward_serialize!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_unit_struct(&'static str)
+    );
+
+    fn serialize_f32(self, value: f32) -> Result<S::Ok, S::Error> {
+        if value.is_finite() {
+            return self.inner.serialize_f32(value);
+        }
+        self.serialize_float(f64::from(value))
+    }
+
+    fn serialize_f64(self, value: f64) -> Result<S::Ok, S::Error> {
+        self.serialize_float(value)
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<S::Ok, S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_some(&CompatValue { value, raw })
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_unit_variant(name, variant_index, variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let raw = self.raw;
+        self.inner
+            .serialize_newtype_struct(name, &CompatValue { value, raw })
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_newtype_variant(
+            name,
+            variant_index,
+            variant,
+            &CompatValue { value, raw },
+        )
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        let raw = self.raw;
+        let inner = self.inner.serialize_seq(len)?;
+        Ok(CompatSerializer { inner, raw })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        let raw = self.raw;
+        let inner = self.inner.serialize_tuple(len)?;
+        Ok(CompatSerializer { inner, raw })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        let raw = self.raw;
+        let inner = self.inner.serialize_tuple_struct(name, len)?;
+        Ok(CompatSerializer { inner, raw })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        let raw = self.raw;
+        let inner = self
+            .inner
+            .serialize_tuple_variant(name, variant_index, variant, len)?;
+        Ok(CompatSerializer { inner, raw })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        let raw = self.raw;
+        let inner = self.inner.serialize_map(len)?;
+        Ok(CompatSerializer { inner, raw })
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        let raw = self.raw;
+        let inner = self.inner.serialize_struct(name, len)?;
+        Ok(CompatSerializer { inner, raw })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        let raw = self.raw;
+        let inner = self
+            .inner
+            .serialize_struct_variant(name, variant_index, variant, len)?;
+        Ok(CompatSerializer { inner, raw })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+impl<S: SerializeSeq> SerializeSeq for CompatSerializer<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_element(&CompatValue { value, raw })
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeTuple> SerializeTuple for CompatSerializer<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_element(&CompatValue { value, raw })
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeTupleStruct> SerializeTupleStruct for CompatSerializer<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_field(&CompatValue { value, raw })
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeTupleVariant> SerializeTupleVariant for CompatSerializer<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_field(&CompatValue { value, raw })
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeMap> SerializeMap for CompatSerializer<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    // keys are always strings in JSON, so they're left to serde_json
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), S::Error> {
+        self.inner.serialize_key(key)
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_value(&CompatValue { value, raw })
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeStruct> SerializeStruct for CompatSerializer<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_field(key, &CompatValue { value, raw })
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeStructVariant> SerializeStructVariant for CompatSerializer<'_, S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        let raw = self.raw;
+        self.inner.serialize_field(key, &CompatValue { value, raw })
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quotes_bare_non_finite_numbers() {
+        assert_eq!(
+            normalize(r#"[1.#INF,-1.#IND,"1.#INF",1e999,-nan(ind),null]"#),
+            r#"["1.#INF","1.#IND","1.#INF","1.#INF","1.#IND",null]"#
+        );
+        assert!(matches!(normalize("[1e+006,true]"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn writes_non_finite_numbers_like_json_encode() {
+        let returned = to_vec(&(
+            f64::INFINITY,
+            f32::NEG_INFINITY,
+            Some(f64::NAN),
+            "1.#INF",
+            1.5,
+        ))
+        .unwrap();
+        assert_eq!(returned, br#"[1.#INF,-1.#INF,-1.#IND,"1.#INF",1.5]"#);
+    }
+
+    #[test]
+    fn datum_refs_round_trip() {
+        let datum_ref = DatumRef::parse("[0x2000001]").unwrap();
+        assert_eq!(datum_ref, DatumRef(0x200_0001));
+        assert_eq!(datum_ref.to_string(), "[0x2000001]");
+        assert!(DatumRef::parse("0x2000001").is_none());
+    }
+}
@@ -0,0 +1,322 @@
+//! Chunked returns, for results too large to send back to BYOND as a single string.
+//!
+//! A function marked `#[byond_fn(chunked)]` (or `#[byond_fn(chunked = <chunk size in bytes>)]`)
+//! doesn't return its result directly. Instead, the result is stored, and a header describing the
+//! transfer is returned:
+//!
+//! `@@CHUNKED@@;<transfer id>;<chunk count>`
+//!
+//! Each chunk is then fetched with the `byond_fn_fetch_chunk` export, taking the transfer id and
+//! the index of the chunk. Once every chunk has been fetched, the transfer is freed. A transfer that
+//! won't be fetched to the end can be freed early with `byond_fn_cancel_transfer`.
+//!
+//! Transfers DM stops fetching partway, like after a runtime in the fetch loop or a reboot, would
+//! otherwise be kept forever. At most [`DEFAULT_MAX_TRANSFERS`] transfers are kept (see
+//! [`set_max_transfers`]), and the oldest is freed to make room for a new one, after which fetching
+//! its chunks fails with `BAD_CHUNK`. With the `hot_reload` feature, `byond_fn_shutdown` frees
+//! every transfer as well.
+//!
+//! Errors are returned as usual, without a header.
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//!
+//! #[byond_fn(chunked = 1024)]
+//! pub fn big_log() -> String {
+//!     "a very long line\n".repeat(10_000)
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! ```dm
+//! /proc/fetch_chunked(header)
+//!     var/list/parts = splittext(header, ";")
+//!     if(parts[1] != "@@CHUNKED@@")
+//!         return header
+//!     var/result = ""
+//!     for(var/i in 0 to text2num(parts[3]) - 1)
+//!         result += call_ext("example.dll", "byond_fn_fetch_chunk")(parts[2], "[i]")
+//!     return result
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+use crate::byond_fn;
+use crate::str_ffi::{FFIError, StrReturn, TransportError};
+
+/// Returned in place of a chunked result
+pub const HEADER: &str = "@@CHUNKED@@";
+
+/// The chunk size used by `#[byond_fn(chunked)]` when none is given
+pub const DEFAULT_CHUNK_SIZE: usize = 32 * 1024;
+
+/// How many transfers are kept by default before the oldest is freed
+pub const DEFAULT_MAX_TRANSFERS: usize = 1024;
+
+static MAX_TRANSFERS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_TRANSFERS);
+
+/// Sets how many transfers are kept before the oldest is freed. At least one is always kept.
+pub fn set_max_transfers(max: usize) {
+    MAX_TRANSFERS.store(max.max(1), Ordering::Relaxed);
+}
+
+struct Transfer {
+    chunks: Vec<Option<Vec<u8>>>,
+    remaining: usize,
+}
+
+/// Transfers by id, which count up, so the first is the oldest
+type Transfers = BTreeMap<u64, Transfer>;
+
+fn transfers() -> MutexGuard<'static, Transfers> {
+    static TRANSFERS: OnceLock<Mutex<Transfers>> = OnceLock::new();
+    TRANSFERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Stores `transfer`, freeing the oldest transfers to keep at most `max`
+fn insert_transfer(transfers: &mut Transfers, id: u64, transfer: Transfer, max: usize) {
+    while transfers.len() >= max {
+        transfers.pop_first();
+    }
+    transfers.insert(id, transfer);
+}
+
+/// Frees every transfer, along with any chunks not yet taken.
+pub fn clear_transfers() {
+    transfers().clear();
+}
+
+/// Wraps a return value to be sent back to BYOND in chunks of at most `chunk_size` bytes.
+///
+/// This is what `#[byond_fn(chunked)]` wraps results in, but is exposed in case you want the same
+/// functionality.
+pub struct Chunked<T> {
+    value: T,
+    chunk_size: usize,
+}
+
+impl<T: StrReturn> Chunked<T> {
+    pub fn new(value: T, chunk_size: usize) -> Self {
+        Chunked {
+            value,
+            chunk_size: chunk_size.max(1),
+        }
+    }
+}
+
+impl<T: StrReturn> StrReturn for Chunked<T> {
+    fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
+        let bytes = self.value.to_return()?.unwrap_or_default();
+        let chunks = split_chunks(&bytes, self.chunk_size);
+
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let count = chunks.len();
+        if count > 0 {
+            insert_transfer(
+                &mut transfers(),
+                id,
+                Transfer {
+                    chunks: chunks.into_iter().map(Some).collect(),
+                    remaining: count,
+                },
+                MAX_TRANSFERS.load(Ordering::Relaxed),
+            );
+        }
+        Ok(Some(format!("{HEADER};{id};{count}").into_bytes()))
+    }
+}
+
+/// Splits `bytes` into chunks of at most `chunk_size` bytes.
+///
+/// If `bytes` is valid UTF-8, chunks are only split on character boundaries, so each chunk is valid
+/// UTF-8 by itself.
+fn split_chunks(bytes: &[u8], chunk_size: usize) -> Vec<Vec<u8>> {
+    let text = std::str::from_utf8(bytes).ok();
+    let mut chunks = Vec::with_capacity(bytes.len().div_ceil(chunk_size));
+    let mut start = 0;
+    while start < bytes.len() {
+        let mut end = (start + chunk_size).min(bytes.len());
+        if let Some(text) = text {
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            // a chunk size smaller than a single character, so it can't be kept whole
+            if end == start {
+                end = start + text[start..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        chunks.push(bytes[start..end].to_vec());
+        start = end;
+    }
+    chunks
+}
+
+/// Takes chunk `index` of transfer `id`, freeing the transfer once every chunk has been taken.
+///
+/// # Errors
+///
+/// If there is no such transfer, or the chunk doesn't exist or was already taken, this will return
+/// a `TransportError::BadChunk`.
+pub fn take_chunk(id: u64, index: usize) -> Result<Vec<u8>, FFIError> {
+    let mut transfers = transfers();
+    let transfer = transfers
+        .get_mut(&id)
+        .ok_or(TransportError::BadChunk { id, index })?;
+    let chunk = transfer
+        .chunks
+        .get_mut(index)
+        .and_then(Option::take)
+        .ok_or(TransportError::BadChunk { id, index })?;
+    transfer.remaining -= 1;
+    if transfer.remaining == 0 {
+        transfers.remove(&id);
+    }
+    Ok(chunk)
+}
+
+/// Frees transfer `id` along with any chunks not yet taken. Returns `false` if there was no such
+/// transfer.
+pub fn cancel_transfer(id: u64) -> bool {
+    transfers().remove(&id).is_some()
+}
+
+/// Returns chunk `index` of a chunked transfer.
+// chunks are already encoded and NUL checked by the function that returned them, so they are
+// passed as-is
+#[byond_fn(
+    builtin,
+    name = "byond_fn_fetch_chunk",
+    encoding = "utf8",
+    nul = "error"
+)]
+fn fetch_chunk(id: u64, index: usize) -> Result<Vec<u8>, FFIError> {
+    take_chunk(id, index)
+}
+
+/// Frees a chunked transfer that won't be fetched to the end.
+#[byond_fn(builtin, name = "byond_fn_cancel_transfer")]
+fn cancel(id: u64) -> bool {
+    cancel_transfer(id)
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::{c_int, CStr, CString};
+
+    use super::*;
+
+    #[byond_fn(chunked = 4)]
+    fn chunked_halve(number: u32) -> Result<String, FFIError> {
+        if number.is_multiple_of(2) {
+            Ok((number / 2).to_string().repeat(3))
+        } else {
+            Err(FFIError::OtherError(format!("{number} is odd").into()))
+        }
+    }
+
+    fn call_halve(args: &[&str]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| CString::new(*arg).unwrap()).collect();
+        let argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let argc = c_int::try_from(argv.len()).unwrap();
+        let returned = unsafe { __byond_fn_chunked_halve::chunked_halve(argc, argv.as_ptr()) };
+        unsafe { CStr::from_ptr(returned) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn header(returned: &[u8]) -> (u64, usize) {
+        let returned = std::str::from_utf8(returned).unwrap();
+        let mut parts = returned.split(';');
+        assert_eq!(parts.next(), Some(HEADER));
+        (
+            parts.next().unwrap().parse().unwrap(),
+            parts.next().unwrap().parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn round_trips_chunks() {
+        let value = "ab£cd€".repeat(50);
+        let returned = Chunked::new(value.clone(), 7).to_return().unwrap().unwrap();
+        let (id, count) = header(&returned);
+
+        let mut reassembled = Vec::new();
+        for index in 0..count {
+            let chunk = take_chunk(id, index).unwrap();
+            assert!(chunk.len() <= 7);
+            assert!(std::str::from_utf8(&chunk).is_ok());
+            reassembled.extend(chunk);
+        }
+        assert_eq!(reassembled, value.into_bytes());
+
+        // the transfer is freed once fully fetched
+        assert!(take_chunk(id, 0).is_err());
+    }
+
+    #[test]
+    fn chunks_can_only_be_taken_once() {
+        let returned = Chunked::new("abcdef", 2).to_return().unwrap().unwrap();
+        let (id, count) = header(&returned);
+        assert_eq!(count, 3);
+
+        assert_eq!(take_chunk(id, 1).unwrap(), b"cd");
+        assert!(take_chunk(id, 1).is_err());
+        assert!(take_chunk(id, 3).is_err());
+        assert!(cancel_transfer(id));
+        assert!(take_chunk(id, 0).is_err());
+    }
+
+    #[test]
+    fn errors_are_not_chunked() {
+        let (id, count) = header(call_halve(&["200"]).as_bytes());
+        assert_eq!(count, 3);
+        assert!(cancel_transfer(id));
+
+        assert_eq!(call_halve(&["3"]), "@@ERR@@;3 is odd");
+        assert_eq!(
+            call_halve(&["three"]),
+            "@@ERR@@;FFI;ARG_PARSE;Failed to parse argument \"number\" (content was \"three\")"
+        );
+        assert_eq!(call_halve(&[]), "@@ERR@@;FFI;Expected 1 args, got 0");
+    }
+
+    #[test]
+    fn empty_results_have_no_chunks() {
+        let returned = Chunked::new((), 16).to_return().unwrap().unwrap();
+        let (id, count) = header(&returned);
+        assert_eq!(count, 0);
+        assert!(!cancel_transfer(id));
+    }
+
+    #[test]
+    fn oldest_transfers_are_freed_first() {
+        // a map of its own, as other tests use the global one at the same time
+        let mut transfers = Transfers::new();
+        let transfer = || Transfer {
+            chunks: vec![Some(b"a".to_vec())],
+            remaining: 1,
+        };
+        for id in 1..=3 {
+            insert_transfer(&mut transfers, id, transfer(), 2);
+        }
+        assert_eq!(transfers.keys().copied().collect::<Vec<_>>(), [2, 3]);
+
+        insert_transfer(&mut transfers, 4, transfer(), 1);
+        assert_eq!(transfers.keys().copied().collect::<Vec<_>>(), [4]);
+    }
+
+    #[test]
+    fn tiny_chunk_sizes_keep_characters_whole() {
+        let chunks = split_chunks("a€b".as_bytes(), 1);
+        assert_eq!(
+            chunks,
+            vec![b"a".to_vec(), "€".as_bytes().to_vec(), b"b".to_vec()]
+        );
+    }
+}
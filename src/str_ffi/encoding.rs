@@ -0,0 +1,328 @@
+//! Text encodings for string transport.
+//!
+//! BYOND 515 and later pass arguments to `call_ext` as UTF-8, but BYOND 514 passes them in the
+//! server's legacy codepage, usually Windows-1252. Under UTF-8, any non-ASCII character from a 514
+//! server fails the whole call with `BAD_UTF8`.
+//!
+//! The encoding used to decode arguments and encode returns can be set for the whole library with
+//! [`set_default_encoding`] (or from DM through the `byond_fn_set_encoding` export), or for a single
+//! function with `#[byond_fn(encoding = "...")]`:
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//!
+//! #[byond_fn(encoding = "windows1252")]
+//! pub fn shout(text: String) -> String {
+//!     text.to_uppercase()
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! Text returns, like `String` and JSON, are encoded into the same encoding. Byte returns, like
+//! `Vec<u8>`, are passed through as-is, even if they happen to be valid UTF-8.
+//!
+//! Independent of the encoding, an argument of type `&[u8]` always receives the raw bytes BYOND
+//! passed, without any decoding.
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::byond_fn;
+use crate::str_ffi::{FFIError, StrReturn, TransportError};
+
+/// The text encoding of arguments passed from BYOND, and of strings returned to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum Encoding {
+    /// Arguments must be valid UTF-8, or the call fails with `BAD_UTF8`
+    #[default]
+    Utf8,
+    /// Invalid UTF-8 in arguments is replaced with `U+FFFD`
+    Utf8Lossy,
+    /// ISO-8859-1, where every byte is the code point of the same value
+    Latin1,
+    /// Windows-1252, the default codepage of BYOND 514 servers on western locales
+    Windows1252,
+}
+
+/// The characters for bytes `0x80..=0x9F` in Windows-1252, the only range where it differs from
+/// Latin-1.
+///
+/// The five bytes Windows-1252 leaves undefined map to the C1 control of the same value, so that
+/// every byte round trips.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Used in place of characters that can't be represented in the target encoding
+const REPLACEMENT_BYTE: u8 = b'?';
+
+impl Encoding {
+    /// Parses an encoding from its name, as used in `#[byond_fn(encoding = "...")]`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Some(Self::Utf8),
+            "utf8_lossy" | "utf-8-lossy" => Some(Self::Utf8Lossy),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Self::Latin1),
+            "windows1252" | "windows-1252" | "cp1252" => Some(Self::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// Decodes bytes passed from BYOND into a string.
+    ///
+    /// # Errors
+    ///
+    /// Only `Encoding::Utf8` can fail, with a `TransportError::BadUTF8`.
+    pub fn decode(self, bytes: &[u8]) -> Result<Cow<'_, str>, TransportError> {
+        match self {
+            Self::Utf8 => Ok(Cow::Borrowed(std::str::from_utf8(bytes)?)),
+            Self::Utf8Lossy => Ok(String::from_utf8_lossy(bytes)),
+            Self::Latin1 | Self::Windows1252 if bytes.is_ascii() => {
+                // ASCII is the same in every supported encoding, so it can be borrowed as-is
+                Ok(Cow::Borrowed(std::str::from_utf8(bytes)?))
+            }
+            Self::Latin1 => Ok(Cow::Owned(
+                bytes.iter().map(|&byte| char::from(byte)).collect(),
+            )),
+            Self::Windows1252 => Ok(Cow::Owned(
+                bytes.iter().map(|&byte| windows_1252_char(byte)).collect(),
+            )),
+        }
+    }
+
+    /// Encodes a string to be returned to BYOND.
+    ///
+    /// Characters that can't be represented are replaced with `?`.
+    pub fn encode(self, text: &str) -> Cow<'_, [u8]> {
+        match self {
+            Self::Utf8 | Self::Utf8Lossy => Cow::Borrowed(text.as_bytes()),
+            _ if text.is_ascii() => Cow::Borrowed(text.as_bytes()),
+            Self::Latin1 => Cow::Owned(
+                text.chars()
+                    .map(|char| u8::try_from(char).unwrap_or(REPLACEMENT_BYTE))
+                    .collect(),
+            ),
+            Self::Windows1252 => Cow::Owned(
+                text.chars()
+                    .map(|char| windows_1252_byte(char).unwrap_or(REPLACEMENT_BYTE))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Same as [`encode`](Self::encode), for an owned string.
+    pub fn encode_string(self, text: String) -> Vec<u8> {
+        match self.encode(&text) {
+            Cow::Borrowed(_) => text.into_bytes(),
+            Cow::Owned(encoded) => encoded,
+        }
+    }
+
+    /// Decodes argument `index` from `args`, if it was passed.
+    ///
+    /// This is used internally, but is exposed in case you want the same functionality.
+    ///
+    /// # Errors
+    ///
+    /// See [`Encoding::decode`].
+    pub fn decode_arg<'a>(
+        self,
+        args: &[&'a [u8]],
+        index: usize,
+    ) -> Result<Option<Cow<'a, str>>, FFIError> {
+        args.get(index)
+            .map(|arg| self.decode(arg))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Decodes every argument in `args`.
+    ///
+    /// This is used internally, but is exposed in case you want the same functionality.
+    ///
+    /// # Errors
+    ///
+    /// See [`Encoding::decode`].
+    pub fn decode_args<'a>(self, args: &[&'a [u8]]) -> Result<Vec<Cow<'a, str>>, FFIError> {
+        args.iter()
+            .map(|arg| self.decode(arg).map_err(Into::into))
+            .collect()
+    }
+}
+
+fn windows_1252_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252_HIGH[usize::from(byte - 0x80)],
+        _ => char::from(byte),
+    }
+}
+
+fn windows_1252_byte(char: char) -> Option<u8> {
+    match u32::from(char) {
+        0x00..=0x7F | 0xA0..=0xFF => u8::try_from(char).ok(),
+        _ => WINDOWS_1252_HIGH
+            .iter()
+            .position(|&high| high == char)
+            .and_then(|pos| u8::try_from(pos + 0x80).ok()),
+    }
+}
+
+static DEFAULT_ENCODING: AtomicU8 = AtomicU8::new(Encoding::Utf8 as u8);
+
+/// Sets the encoding used by every function that doesn't set its own with
+/// `#[byond_fn(encoding = "...")]`.
+pub fn set_default_encoding(encoding: Encoding) {
+    DEFAULT_ENCODING.store(encoding as u8, Ordering::Relaxed);
+}
+
+/// The encoding used by every function that doesn't set its own. Defaults to `Encoding::Utf8`.
+pub fn default_encoding() -> Encoding {
+    match DEFAULT_ENCODING.load(Ordering::Relaxed) {
+        1 => Encoding::Utf8Lossy,
+        2 => Encoding::Latin1,
+        3 => Encoding::Windows1252,
+        _ => Encoding::Utf8,
+    }
+}
+
+/// Maps a raw `&[u8]` argument, which skips decoding entirely.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// If the argument wasn't passed, this will return a `TransportError::WrongArgCount`.
+pub fn raw_arg(
+    arg: Option<&[u8]>,
+    expected_min: usize,
+    expected_max: usize,
+    arg_num: usize,
+) -> Result<&[u8], FFIError> {
+    arg.ok_or_else(|| {
+        TransportError::WrongArgCount {
+            expected_min,
+            expected_max,
+            got: arg_num,
+        }
+        .into()
+    })
+}
+
+/// Wraps a return value to re-encode it, if it's text, see [`StrReturn::to_encoded_return`].
+/// Errors are kept as errors, and are encoded when they're returned with
+/// [`byond_return_encoded`](crate::str_ffi::byond_return_encoded).
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub struct Encoded<T> {
+    value: T,
+    encoding: Encoding,
+}
+
+impl<T: StrReturn> Encoded<T> {
+    pub fn new(value: T, encoding: Encoding) -> Self {
+        Encoded { value, encoding }
+    }
+}
+
+impl<T: StrReturn> StrReturn for Encoded<T> {
+    fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
+        self.value.to_encoded_return(self.encoding)
+    }
+}
+
+/// Sets the default encoding for the library, by name.
+#[byond_fn(builtin, name = "byond_fn_set_encoding", encoding = "utf8")]
+fn set_encoding(name: &str) -> Result<(), FFIError> {
+    let encoding = Encoding::from_name(name).ok_or_else(|| TransportError::ArgParse {
+        arg_name: "name".to_string(),
+        actual_content: name.to_string(),
+    })?;
+    set_default_encoding(encoding);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+
+    use super::*;
+
+    #[byond_fn(encoding = "latin1")]
+    fn latin1_binary() -> Vec<u8> {
+        vec![0xC3, 0xA9]
+    }
+
+    #[byond_fn(encoding = "latin1")]
+    fn latin1_text() -> String {
+        "é".to_string()
+    }
+
+    #[test]
+    fn single_byte_encodings_round_trip_every_byte() {
+        let all_bytes: Vec<u8> = (0..=u8::MAX).collect();
+        for encoding in [Encoding::Latin1, Encoding::Windows1252] {
+            let decoded = encoding.decode(&all_bytes).unwrap();
+            assert_eq!(decoded.chars().count(), 256, "{encoding:?}");
+            assert_eq!(
+                encoding.encode(&decoded),
+                all_bytes.as_slice(),
+                "{encoding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn windows_1252_high_range() {
+        let decoded = Encoding::Windows1252
+            .decode(&[0x80, 0x93, 0x94, 0xA3, 0xE9])
+            .unwrap();
+        assert_eq!(decoded, "€“”£é");
+
+        let decoded = Encoding::Latin1.decode(&[0x80, 0xA3, 0xE9]).unwrap();
+        assert_eq!(decoded, "\u{80}£é");
+    }
+
+    #[test]
+    fn unrepresentable_characters_are_replaced() {
+        assert_eq!(&*Encoding::Windows1252.encode("a€✓"), &[b'a', 0x80, b'?']);
+        assert_eq!(&*Encoding::Latin1.encode("a€é"), &[b'a', b'?', 0xE9]);
+    }
+
+    #[test]
+    fn utf8_rejects_or_replaces_invalid_bytes() {
+        assert!(Encoding::Utf8.decode(&[b'a', 0xE9]).is_err());
+        assert_eq!(
+            Encoding::Utf8Lossy.decode(&[b'a', 0xE9]).unwrap(),
+            "a\u{FFFD}"
+        );
+    }
+
+    #[test]
+    fn only_text_returns_are_encoded() {
+        // valid UTF-8 for "é", which is still binary
+        let binary = vec![0xC3, 0xA9];
+        for encoding in [Encoding::Latin1, Encoding::Windows1252] {
+            let returned = Encoded::new(binary.clone(), encoding).to_return();
+            assert_eq!(returned.unwrap().unwrap(), binary);
+            let returned = Encoded::new(Ok::<_, FFIError>(binary.clone()), encoding).to_return();
+            assert_eq!(returned.unwrap().unwrap(), binary);
+        }
+
+        let call = |shim: unsafe extern "C" fn(_, _) -> _| {
+            unsafe { CStr::from_ptr(shim(0, std::ptr::null())) }
+                .to_bytes()
+                .to_vec()
+        };
+        assert_eq!(call(__byond_fn_latin1_binary::latin1_binary), binary);
+        assert_eq!(call(__byond_fn_latin1_text::latin1_text), [0xE9]);
+
+        let text = Encoded::new("é".to_string(), Encoding::Latin1).to_return();
+        assert_eq!(text.unwrap().unwrap(), [0xE9]);
+        let text = Encoded::new(Ok::<_, FFIError>("é"), Encoding::Windows1252).to_return();
+        assert_eq!(text.unwrap().unwrap(), [0xE9]);
+    }
+}
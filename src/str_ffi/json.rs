@@ -1,6 +1,7 @@
+use crate::str_ffi::encoding::Encoding;
+use crate::str_ffi::limits::current_limits;
 use crate::str_ffi::{error_keys, FFIError, StrArg, StrReturn};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
@@ -23,52 +24,96 @@ use std::fmt::{Display, Formatter};
 ///     // this is now a regular ExampleStruct.
 ///     unwrapped.field1 += 1;
 /// }
+/// # fn main() {}
 /// ```
 ///
+/// Arguments only need `T: Deserialize`, and returns only need `T: Serialize`. Arguments can
+/// borrow from the string passed from BYOND, e.g. as a `&str` or
+/// [`&RawValue`](serde_json::value::RawValue) (fields need `#[serde(borrow)]` to borrow into a
+/// `Cow<str>`):
+/// ```
+/// use byond_fn::byond_fn;
+/// use byond_fn::str_ffi::json::Json;
+/// use serde_json::value::RawValue;
+///
+/// #[derive(serde::Deserialize)]
+/// pub struct Message<'a> {
+///     channel: &'a str,
+///     payload: &'a RawValue,
+/// }
+///
+/// #[byond_fn]
+/// fn forward(message: Json<Message>) -> String {
+///     format!("{}: {}", message.0.channel, message.0.payload.get())
+/// }
+/// # fn main() {}
+/// ```
+///
+/// If an argument fails to deserialize, the error names the path of the field that failed, like
+/// `players[3].ckey`.
+///
 /// It is `repr(transparent)` so usage of this type should be zero-cost.
 #[repr(transparent)]
 #[derive(Debug)]
-pub struct Json<T: Serialize + DeserializeOwned>(pub T);
+pub struct Json<T>(pub T);
 
-impl<T: Serialize + DeserializeOwned> Json<T> {
+impl<T> Json<T> {
     pub fn into_inner(self) -> T {
         self.0
     }
 }
 
-impl<T: Serialize + DeserializeOwned> From<T> for Json<T> {
+impl<T> From<T> for Json<T> {
     fn from(t: T) -> Self {
         Json(t)
     }
 }
 
-impl<T> StrReturn for Json<T>
-where
-    T: Serialize + DeserializeOwned,
-{
+impl<T: Serialize> StrReturn for Json<T> {
     fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
         serde_json::to_vec(&self.0)
             .map_err(JsonError::ReturnSerialize)
             .map_err(FFIError::JsonError)
             .map(Some)
     }
+
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError> {
+        serde_json::to_string(&self.0)
+            .map_err(JsonError::ReturnSerialize)
+            .map_err(FFIError::JsonError)
+            .map(|json| Some(encoding.encode_string(json)))
+    }
 }
 
-impl<'a, T> StrArg<'a> for Json<T>
-where
-    T: Serialize + DeserializeOwned,
-{
-    fn from_arg(arg: &'a str, _arg_name: &str) -> Result<Self, FFIError> {
-        let deserialized: T = serde_json::from_str(arg)
-            .map_err(JsonError::ArgDeserialize)
-            .map_err(FFIError::JsonError)?;
+impl<'a, T: Deserialize<'a>> StrArg<'a> for Json<T> {
+    fn from_arg(arg: &'a str, arg_name: &str) -> Result<Self, FFIError> {
+        current_limits().check_json(arg, arg_name)?;
+        let mut deserializer = serde_json::Deserializer::from_str(arg);
+        let deserialized: T =
+            serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+                JsonError::ArgDeserialize {
+                    path: err.path().to_string(),
+                    source: err.into_inner(),
+                }
+            })?;
+        // reject trailing characters, like serde_json::from_str does
+        deserializer
+            .end()
+            .map_err(|source| JsonError::ArgDeserialize {
+                path: ".".to_string(),
+                source,
+            })?;
         Ok(Json(deserialized))
     }
 }
 
 #[derive(Debug)]
 pub enum JsonError {
-    ArgDeserialize(serde_json::Error),
+    /// An argument failed to deserialize at `path`, which is `.` for the root value
+    ArgDeserialize {
+        path: String,
+        source: serde_json::Error,
+    },
     ReturnSerialize(serde_json::Error),
 }
 
@@ -76,8 +121,17 @@ impl Display for JsonError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{};", error_keys::CLASS_JSON)?;
         match self {
-            JsonError::ArgDeserialize(err) => {
-                write!(f, "{};{}", error_keys::JSON_TYPE_DESERIALIZE, err)
+            JsonError::ArgDeserialize { path, source } if path == "." => {
+                write!(f, "{};{}", error_keys::JSON_TYPE_DESERIALIZE, source)
+            }
+            JsonError::ArgDeserialize { path, source } => {
+                write!(
+                    f,
+                    "{};{}: {}",
+                    error_keys::JSON_TYPE_DESERIALIZE,
+                    path,
+                    source
+                )
             }
             JsonError::ReturnSerialize(err) => {
                 write!(f, "{};{}", error_keys::JSON_TYPE_SERIALIZE, err)
@@ -93,3 +147,59 @@ impl From<JsonError> for FFIError {
         FFIError::JsonError(e)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Player {
+        ckey: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Round {
+        players: Vec<Player>,
+    }
+
+    #[test]
+    fn errors_name_the_failing_path() {
+        let arg = r#"{"players":[{"ckey":"a"},{"ckey":"b"},{"ckey":"c"},{"ckey":4}]}"#;
+        let err = Json::<Round>::from_arg(arg, "round")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("@@ERR@@;JSON;DESERIALIZE;players[3].ckey: invalid type"),
+            "{err}"
+        );
+
+        let err = Json::<Round>::from_arg("4", "round")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.starts_with("@@ERR@@;JSON;DESERIALIZE;invalid type"),
+            "{err}"
+        );
+
+        assert!(Json::<Vec<u8>>::from_arg("[1] x", "list").is_err());
+    }
+
+    #[test]
+    fn borrows_from_the_arg() {
+        #[derive(Deserialize)]
+        struct Borrowed<'a> {
+            #[serde(borrow)]
+            name: Cow<'a, str>,
+            raw: &'a serde_json::value::RawValue,
+        }
+
+        let arg = r#"{"name":"plain","raw":{"nested":[1, 2]}}"#;
+        let Json(borrowed) = Json::<Borrowed>::from_arg(arg, "arg").unwrap();
+        assert!(matches!(borrowed.name, Cow::Borrowed("plain")));
+        assert_eq!(borrowed.raw.get(), r#"{"nested":[1, 2]}"#);
+    }
+}
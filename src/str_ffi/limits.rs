@@ -0,0 +1,310 @@
+//! Limits on the size of arguments.
+//!
+//! Arguments are taken as-is by default, so a buggy proc passing a 50 MB string, or deeply nested
+//! JSON, can stall the tick or overflow the stack while it's parsed. Limits reject such arguments
+//! with an `ARG_TOO_LARGE` error before they're decoded or parsed:
+//!
+//! - `max_arg_bytes` - the size of any one argument
+//! - `max_total_bytes` - the size of all of the arguments together
+//! - `max_json_depth` - how deeply arrays and objects nest in a [`Json`](crate::str_ffi::json::Json)
+//!   or [`ByondJson`](crate::str_ffi::byond_json::ByondJson) argument
+//! - `max_collection_len` - how many elements any one array or object in a JSON argument has
+//!
+//! Sizes are in bytes, as BYOND passed them, before decoding. Limits are set for the whole library
+//! with [`set_default_limits`], and can be overridden for a function in its attribute:
+//!
+//! ```
+//! use byond_fn::byond_fn;
+//! use byond_fn::str_ffi::json::Json;
+//!
+//! #[byond_fn(max_arg_bytes = 65536, max_json_depth = 16)]
+//! pub fn sum(numbers: Json<Vec<i32>>) -> i32 {
+//!     numbers.0.iter().sum()
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! Limits only apply to the str transport, as v2 arguments are already parsed by BYOND.
+
+use std::cell::Cell;
+use std::fmt::{Display, Formatter};
+use std::sync::RwLock;
+
+use crate::str_ffi::{FFIError, TransportError};
+
+/// Limits on the size of arguments. `None` is unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArgLimits {
+    pub max_arg_bytes: Option<usize>,
+    pub max_total_bytes: Option<usize>,
+    pub max_json_depth: Option<usize>,
+    pub max_collection_len: Option<usize>,
+}
+
+impl ArgLimits {
+    /// No limits at all, which is the default
+    pub const UNLIMITED: Self = Self {
+        max_arg_bytes: None,
+        max_total_bytes: None,
+        max_json_depth: None,
+        max_collection_len: None,
+    };
+
+    /// Checks the size of raw arguments, before they're decoded.
+    ///
+    /// `arg_names` names the arguments in errors. Arguments past the end of it are named after the
+    /// last one, which is how `Rest` and `ByondArgs` parameters take them.
+    ///
+    /// # Errors
+    ///
+    /// If an argument, or all of them together, are larger than allowed.
+    pub fn check_args(&self, args: &[&[u8]], arg_names: &[&str]) -> Result<(), TransportError> {
+        let arg_name = |index: usize| {
+            arg_names
+                .get(index)
+                .or(arg_names.last())
+                .map_or_else(|| format!("{index}"), ToString::to_string)
+        };
+        let mut total = 0usize;
+        for (index, arg) in args.iter().enumerate() {
+            if let Some(max) = self.max_arg_bytes.filter(|&max| arg.len() > max) {
+                return Err(TransportError::ArgTooLarge {
+                    arg_name: arg_name(index),
+                    limit: Limit::ArgBytes,
+                    actual: arg.len(),
+                    max,
+                });
+            }
+            total = total.saturating_add(arg.len());
+        }
+        match self.max_total_bytes {
+            Some(max) if total > max => Err(TransportError::ArgTooLarge {
+                arg_name: arg_name(args.len().saturating_sub(1)),
+                limit: Limit::TotalBytes,
+                actual: total,
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks how deeply `json` nests and how long its arrays and objects are, without parsing it.
+    ///
+    /// The text isn't validated otherwise, which is left to the JSON parser.
+    ///
+    /// # Errors
+    ///
+    /// If `json` nests deeper, or has a longer array or object, than allowed.
+    pub fn check_json(&self, json: &str, arg_name: &str) -> Result<(), TransportError> {
+        if self.max_json_depth.is_none() && self.max_collection_len.is_none() {
+            return Ok(());
+        }
+        let too_large = |limit, actual, max| TransportError::ArgTooLarge {
+            arg_name: arg_name.to_string(),
+            limit,
+            actual,
+            max,
+        };
+        // the number of elements in each array or object that is still open
+        let mut lengths: Vec<usize> = Vec::new();
+        let mut in_string = false;
+        let mut escaped = false;
+        for byte in json.bytes() {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'[' | b'{' => {
+                    if let Some(length) = lengths.last_mut() {
+                        *length = (*length).max(1);
+                    }
+                    lengths.push(0);
+                    if let Some(max) = self.max_json_depth.filter(|&max| lengths.len() > max) {
+                        return Err(too_large(Limit::JsonDepth, lengths.len(), max));
+                    }
+                }
+                b']' | b'}' => {
+                    lengths.pop();
+                }
+                b',' => {
+                    if let Some(length) = lengths.last_mut() {
+                        *length += 1;
+                        if let Some(max) = self.max_collection_len.filter(|&max| *length > max) {
+                            return Err(too_large(Limit::CollectionLen, *length, max));
+                        }
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => {}
+                byte => {
+                    in_string = byte == b'"';
+                    if let Some(length) = lengths.last_mut() {
+                        // counts the first element, the ones after are counted at their comma
+                        *length = (*length).max(1);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which limit an argument went over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    ArgBytes,
+    TotalBytes,
+    JsonDepth,
+    CollectionLen,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Limit::ArgBytes => "is too large",
+            Limit::TotalBytes => "brings the arguments over their total size limit",
+            Limit::JsonDepth => "nests too deeply",
+            Limit::CollectionLen => "has a collection with too many elements",
+        })
+    }
+}
+
+static DEFAULT_LIMITS: RwLock<ArgLimits> = RwLock::new(ArgLimits::UNLIMITED);
+
+thread_local! {
+    static CALL_LIMITS: Cell<Option<ArgLimits>> = const { Cell::new(None) };
+}
+
+/// Sets the limits used by every function that doesn't set its own in its attribute.
+pub fn set_default_limits(limits: ArgLimits) {
+    *DEFAULT_LIMITS
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = limits;
+}
+
+/// The limits used by every function that doesn't set its own. Defaults to
+/// [`ArgLimits::UNLIMITED`].
+pub fn default_limits() -> ArgLimits {
+    *DEFAULT_LIMITS.read().unwrap_or_else(|err| err.into_inner())
+}
+
+/// The limits of the function whose arguments are being parsed on this thread, or the default
+/// limits outside of one.
+pub fn current_limits() -> ArgLimits {
+    CALL_LIMITS.with(Cell::get).unwrap_or_else(default_limits)
+}
+
+/// Makes `limits` the [`current_limits`] of this thread, until it's dropped.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub struct LimitsScope {
+    previous: Option<ArgLimits>,
+}
+
+impl LimitsScope {
+    pub fn enter(limits: ArgLimits) -> Self {
+        Self {
+            previous: CALL_LIMITS.with(|cell| cell.replace(Some(limits))),
+        }
+    }
+}
+
+impl Drop for LimitsScope {
+    fn drop(&mut self) {
+        CALL_LIMITS.with(|cell| cell.set(self.previous));
+    }
+}
+
+/// Checks the raw arguments of a call against `limits`, then makes them the current limits for
+/// parsing the arguments.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Errors
+///
+/// Same as [`ArgLimits::check_args`].
+pub fn enter_call(
+    limits: ArgLimits,
+    args: &[&[u8]],
+    arg_names: &[&str],
+) -> Result<LimitsScope, FFIError> {
+    limits.check_args(args, arg_names)?;
+    Ok(LimitsScope::enter(limits))
+}
+
+#[cfg(all(test, feature = "json_transport"))]
+mod test {
+    use std::ffi::{c_int, CStr, CString};
+
+    use super::*;
+    use crate::byond_fn;
+    use crate::str_ffi::json::Json;
+    use crate::str_ffi::Rest;
+
+    #[byond_fn(max_arg_bytes = 8, max_total_bytes = 12, max_json_depth = 2)]
+    fn count(items: Json<Vec<Vec<u8>>>, rest: Rest<String>) -> usize {
+        items.0.len() + rest.0.len()
+    }
+
+    fn call(args: &[&str]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| CString::new(*arg).unwrap()).collect();
+        let argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let argc = c_int::try_from(argv.len()).unwrap();
+        let returned = unsafe { __byond_fn_count::count(argc, argv.as_ptr()) };
+        unsafe { CStr::from_ptr(returned) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn check_json(json: &str, max_json_depth: usize, max_collection_len: usize) -> Option<Limit> {
+        let limits = ArgLimits {
+            max_json_depth: Some(max_json_depth),
+            max_collection_len: Some(max_collection_len),
+            ..ArgLimits::UNLIMITED
+        };
+        match limits.check_json(json, "json") {
+            Ok(()) => None,
+            Err(TransportError::ArgTooLarge { limit, .. }) => Some(limit),
+            Err(err) => panic!("unexpected error {err}"),
+        }
+    }
+
+    #[test]
+    fn json_is_scanned_for_depth_and_length() {
+        assert_eq!(check_json("[[1, 2], {\"a\": [3]}]", 3, 2), None);
+        assert_eq!(check_json("[[[[1]]]]", 3, 2), Some(Limit::JsonDepth));
+        assert_eq!(check_json("[1, 2, 3]", 3, 2), Some(Limit::CollectionLen));
+        assert_eq!(
+            check_json("{\"a\": 1, \"b\": 2, \"c\": 3}", 3, 2),
+            Some(Limit::CollectionLen)
+        );
+        assert_eq!(check_json("[]", 3, 0), None);
+        assert_eq!(check_json("[[]]", 3, 1), None);
+        // brackets and commas in strings don't count
+        assert_eq!(check_json("[\"[[[[,,,\", \"\\\"[[[[\"]", 3, 2), None);
+    }
+
+    #[test]
+    fn shims_apply_their_limits() {
+        assert_eq!(call(&["[[1]]", "a", "b"]), "3");
+        assert_eq!(
+            call(&["[[1],[2]]", "a"]),
+            "@@ERR@@;FFI;ARG_TOO_LARGE;Argument \"items\" is too large (9 > 8)"
+        );
+        assert_eq!(
+            call(&["[[1]]", "abcd", "efgh"]),
+            "@@ERR@@;FFI;ARG_TOO_LARGE;Argument \"rest\" brings the arguments over their total size limit (13 > 12)"
+        );
+        assert_eq!(
+            call(&["[[[1]]]"]),
+            "@@ERR@@;FFI;ARG_TOO_LARGE;Argument \"items\" nests too deeply (3 > 2)"
+        );
+        // the limits only last for the call
+        assert_eq!(current_limits(), default_limits());
+    }
+}
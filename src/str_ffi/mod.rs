@@ -14,8 +14,8 @@
 //! The error class is an easily machine readable string that describes the general category of error that occurred.
 //! Possible classes are:
 //! - `FFI` - An error occurred while parsing arguments, serializing return values, or the function being called
-//! incorrectly
-//! -`JSON` - An error occurred while parsing or serializing JSON arguments or return values
+//!   incorrectly
+//! - `JSON` - An error occurred while parsing or serializing JSON arguments or return values
 //! - `FN` - An error occurred within the function itself being called and was returned as an `Err`
 //!
 //! The error type is an easily machine readable string that describes the specific error that occurred
@@ -48,60 +48,98 @@
 //! pub fn add(arg1: u8, arg2: u8) -> u8 {
 //!     arg1 + arg2
 //! }
+//! # fn main() {}
 //! ```
 //! will generate an adjacent module that looks like this:
-//! ```
+//! ```ignore
 //! mod __byond_fn_add {
 //!     #[no_mangle]
 //!     pub unsafe extern "C" fn add(
 //!         argc: ::std::os::raw::c_int,
 //!         argv: *const *const ::std::os::raw::c_char,
 //!     ) -> *const ::std::os::raw::c_char {
-//!         if argc < 2i32 || argc > 2i32 {
-//!             return byond_fn::str_ffi::byond_return(
-//!                 byond_fn::str_ffi::TransportError::WrongArgCount,
-//!              );
+//!         let encoding = byond_fn::str_ffi::encoding::default_encoding();
+//!         if argc != 2i32 {
+//!             return byond_fn::str_ffi::byond_return_encoded(
+//!                 byond_fn::str_ffi::TransportError::WrongArgCount {
+//!                     expected_min: 2usize,
+//!                     expected_max: 2usize,
+//!                     got: argc as usize,
+//!                 },
+//!                 encoding,
+//!             );
 //!         }
-//!         let args = match byond_fn::str_ffi::parse_str_args(argc, argv) {
-//!              Ok(args) => args,
-//!              Err(err) => {
-//!                  return byond_fn::str_ffi::byond_return(err);
-//!              }
-//!          };
-//!          let arg1: u8 = match byond_fn::str_ffi::StrArg::from_arg(
-//!              args.get(0usize).map(|arg| *arg),
-//!          ) {
-//!              Ok(arg) => arg,
-//!              Err(err) => {
-//!                  return byond_fn::str_ffi::byond_return(err);
-//!              }
-//!          };
-//!          let arg2: u8 = match byond_fn::str_ffi::StrArg::from_arg(
-//!              args.get(1usize).map(|arg| *arg),
-//!          ) {
-//!              Ok(arg) => arg,
-//!              Err(err) => {
-//!                  return byond_fn::str_ffi::byond_return(err);
-//!              }
-//!          };
-//!          byond_fn::str_ffi::byond_return(super::add(arg1, arg2))
+//!         let args = byond_fn::str_ffi::parse_raw_args(argc, argv);
+//!         let __byond_fn_limits = match byond_fn::str_ffi::limits::enter_call(
+//!             byond_fn::str_ffi::limits::ArgLimits {
+//!                 ..byond_fn::str_ffi::limits::default_limits()
+//!             },
+//!             &args,
+//!             &["arg1", "arg2"],
+//!         ) {
+//!             Ok(arg) => arg,
+//!             Err(err) => {
+//!                 return byond_fn::str_ffi::byond_return_encoded(err, encoding);
+//!             }
+//!         };
+//!         let __byond_fn_arg_0 = match encoding.decode_arg(&args, 0usize) {
+//!             Ok(arg) => arg,
+//!             Err(err) => {
+//!                 return byond_fn::str_ffi::byond_return_encoded(err, encoding);
+//!             }
+//!         };
+//!         let arg1 = match byond_fn::str_ffi::StrArg::map_arg(
+//!             __byond_fn_arg_0.as_deref(),
+//!             2usize,
+//!             2usize,
+//!             "arg1",
+//!             0usize,
+//!         ) {
+//!             Ok(arg) => arg,
+//!             Err(err) => {
+//!                 return byond_fn::str_ffi::byond_return_encoded(err, encoding);
+//!             }
+//!         };
+//!         // ...and the same for arg2
+//!         drop(__byond_fn_limits);
+//!         let __byond_fn_ret = byond_fn::str_ffi::binary::NulChecked::new(
+//!             super::add(arg1, arg2),
+//!             byond_fn::str_ffi::binary::nul_policy(),
+//!         );
+//!         byond_fn::str_ffi::byond_return_encoded(__byond_fn_ret, encoding)
 //!     }
 //! }
 //! ```
 
+pub mod binary;
+#[cfg(feature = "json_transport")]
+pub mod byond_json;
+pub mod chunked;
+pub mod encoding;
 #[cfg(feature = "json_transport")]
 pub mod json;
+pub mod limits;
+pub mod sandbox;
 
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::error::Error;
 use std::ffi::{c_char, c_int, CStr, CString};
 use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
 use std::slice;
 use std::str::Utf8Error;
 
+#[cfg(feature = "ffi_v2")]
+use crate::ffi_v2::api::ApiError;
+#[cfg(feature = "ffi_v2")]
+use crate::host::ByondVersion;
+use crate::str_ffi::binary::NulPolicy;
+use crate::str_ffi::encoding::{Encoded, Encoding};
 use crate::str_ffi::json::JsonError;
+use crate::str_ffi::limits::Limit;
 
 // BYOND doesn't like receiving back an empty string, so throw back just a null byte instead.
 const EMPTY_STRING: c_char = 0;
@@ -120,16 +158,46 @@ pub mod error_keys {
     pub const CLASS_FFI: &str = "FFI";
     pub const CLASS_JSON: &str = "JSON";
     pub const CLASS_FN: &str = "FN";
+    pub const CLASS_API: &str = "API";
 
     pub const FFI_TYPE_BAD_UTF8: &str = "BAD_UTF8";
     pub const FFI_TYPE_WRONG_ARG_COUNT: &str = "WRONG_ARG_COUNT";
     pub const FFI_TYPE_ARG_PARSE: &str = "ARG_PARSE";
     pub const FFI_TYPE_RETURN_STR: &str = "RETURN_STR";
+    pub const FFI_TYPE_NO_INSTANCE: &str = "NO_INSTANCE";
+    pub const FFI_TYPE_INSTANCE_IN_USE: &str = "INSTANCE_IN_USE";
+    pub const FFI_TYPE_STALE_HANDLE: &str = "STALE_HANDLE";
+    pub const FFI_TYPE_HANDLE_IN_USE: &str = "HANDLE_IN_USE";
+    pub const FFI_TYPE_BAD_CHUNK: &str = "BAD_CHUNK";
+    pub const FFI_TYPE_RETURN_NUL: &str = "RETURN_NUL";
+    pub const FFI_TYPE_PATH_ESCAPE: &str = "PATH_ESCAPE";
+    pub const FFI_TYPE_ARG_TOO_LARGE: &str = "ARG_TOO_LARGE";
+    #[cfg(feature = "ffi_v2")]
+    pub const FFI_TYPE_UNSUPPORTED_HOST: &str = "UNSUPPORTED_HOST";
+    #[cfg(feature = "hot_reload")]
+    pub const FFI_TYPE_LIBRARY_LOAD: &str = "LIBRARY_LOAD";
+    #[cfg(feature = "hot_reload")]
+    pub const FFI_TYPE_MISSING_SYMBOL: &str = "MISSING_SYMBOL";
 
     #[cfg(feature = "json_transport")]
     pub const JSON_TYPE_SERIALIZE: &str = "SERIALIZE";
     #[cfg(feature = "json_transport")]
     pub const JSON_TYPE_DESERIALIZE: &str = "DESERIALIZE";
+
+    #[cfg(feature = "ffi_v2")]
+    pub const API_TYPE_NO_HOST: &str = "NO_HOST";
+    #[cfg(feature = "ffi_v2")]
+    pub const API_TYPE_MISSING_SYMBOL: &str = "MISSING_SYMBOL";
+    #[cfg(feature = "ffi_v2")]
+    pub const API_TYPE_FAILED: &str = "FAILED";
+    #[cfg(feature = "ffi_v2")]
+    pub const API_TYPE_INVALID_NAME: &str = "INVALID_NAME";
+    #[cfg(feature = "ffi_v2")]
+    pub const API_TYPE_CONVERT: &str = "CONVERT";
+    #[cfg(feature = "ffi_v2")]
+    pub const API_TYPE_SERDE: &str = "SERDE";
+    #[cfg(feature = "ffi_v2")]
+    pub const API_TYPE_NOT_MAIN_THREAD: &str = "NOT_MAIN_THREAD";
 }
 
 /// Turns the `argc` and `argv` arguments into a Rust `Vec<&str>`.
@@ -159,41 +227,127 @@ pub unsafe fn parse_str_args<'a>(
         .collect()
 }
 
+/// Turns the `argc` and `argv` arguments into a Rust `Vec<&[u8]>`, without decoding them.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+///
+/// # Safety
+/// Same as [`parse_str_args`].
+pub unsafe fn parse_raw_args<'a>(argc: c_int, argv: *const *const c_char) -> Vec<&'a [u8]> {
+    unsafe {
+        slice::from_raw_parts(argv, argc as usize)
+            .iter()
+            .map(|ptr| CStr::from_ptr(*ptr).to_bytes())
+            .collect()
+    }
+}
+
 /// A function to prep a value for returning to BYOND.
 ///
 /// Converts the value into a string, and then returns a pointer to the string. The string is allocated into a
 /// thread-local buffer, so it will be overwritten on the next call.
 ///
+/// The value isn't passed through a [`NulPolicy`](crate::str_ffi::binary::NulPolicy), which the
+/// generated functions apply before returning. A value that still has a NUL byte in it is returned
+/// as a `RETURN_NUL` error, rather than cut short.
+///
 /// This is used internally, but is exposed in case you want the same functionality.
 pub fn byond_return(value: impl StrReturn) -> *const c_char {
-    let value = match value.to_return() {
-        Ok(inner) => inner.filter(|inner| !inner.is_empty()),
-        Err(err) => Some(err.to_string().into_bytes()),
+    return_string(value.to_return(), Encoding::Utf8)
+}
+
+/// Stores a returned value, or the error string in `error_encoding`, for BYOND to read
+fn return_string(
+    value: Result<Option<Vec<u8>>, FFIError>,
+    error_encoding: Encoding,
+) -> *const c_char {
+    let value = value.and_then(|inner| {
+        inner
+            .map(|inner| NulPolicy::Error.apply(inner))
+            .transpose()
+            .map_err(Into::into)
+    });
+    let value = match value {
+        Ok(inner) => inner,
+        Err(err) => Some(
+            error_encoding.encode_string(String::from_utf8_lossy(&error_bytes(&err)).into_owned()),
+        ),
     };
     match value {
         None => &EMPTY_STRING,
         Some(vec) if vec.is_empty() => &EMPTY_STRING,
         Some(vec) => RETURN_STRING.with(|cell| {
-            // Panicking over an FFI boundary is bad form, so if a NUL ends up
-            // in the result, just truncate.
-            let cstring = CString::new(vec).unwrap_or_else(|err| {
-                let post = err.nul_position();
-                let mut vec = err.into_vec();
-                vec.truncate(post);
-                CString::new(vec).unwrap_or_default()
-            });
-            cell.replace(cstring);
+            // NULs were handled above, so this can't fail
+            cell.replace(CString::new(vec).unwrap_or_default());
             cell.borrow().as_ptr()
         }),
     }
 }
 
+/// The bytes of the string last returned on this thread, if `returned` points to it.
+///
+/// Anything returned through [`byond_return`] is, and so is NUL terminated, as it's held by a
+/// `CString`.
+#[cfg(feature = "fuzzing")]
+pub(crate) fn returned_bytes(returned: *const c_char) -> Option<Vec<u8>> {
+    if std::ptr::eq(returned, &EMPTY_STRING) {
+        return Some(Vec::new());
+    }
+    RETURN_STRING.with(|cell| {
+        let string = cell.borrow();
+        std::ptr::eq(string.as_ptr(), returned).then(|| string.as_bytes().to_vec())
+    })
+}
+
+/// The string returned to BYOND for an error.
+///
+/// NUL bytes are always dropped, so the error gets through whatever the NUL policy is. Every
+/// returned error passes through here, so this is also where errors are counted for metrics.
+pub(crate) fn error_bytes(err: &FFIError) -> Vec<u8> {
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_error(err);
+    let mut bytes = err.to_string().into_bytes();
+    bytes.retain(|&byte| byte != 0);
+    bytes
+}
+
+/// Quotes and escapes `value` as a JSON string, for the hand-written JSON of metrics and traces.
+#[cfg(any(feature = "metrics", feature = "profiling"))]
+pub(crate) fn json_string(value: &str) -> String {
+    use std::fmt::Write;
+
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            char if char.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", u32::from(char));
+            }
+            char => escaped.push(char),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Same as [`byond_return`], but with the returned string, or the error string, encoded in
+/// `encoding`.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub fn byond_return_encoded(value: impl StrReturn, encoding: Encoding) -> *const c_char {
+    return_string(Encoded::new(value, encoding).to_return(), encoding)
+}
+
 #[derive(Debug)]
 pub enum FFIError {
     TransportError(TransportError),
     OtherError(Box<dyn Error>),
     #[cfg(feature = "json_transport")]
     JsonError(JsonError),
+    #[cfg(feature = "ffi_v2")]
+    ApiError(ApiError),
 }
 
 impl Display for FFIError {
@@ -204,6 +358,8 @@ impl Display for FFIError {
             FFIError::OtherError(err) => write!(f, "{err}"),
             #[cfg(feature = "json_transport")]
             FFIError::JsonError(err) => write!(f, "{err}"),
+            #[cfg(feature = "ffi_v2")]
+            FFIError::ApiError(err) => write!(f, "{err}"),
         }
     }
 }
@@ -214,6 +370,13 @@ impl From<TransportError> for FFIError {
     }
 }
 
+#[cfg(feature = "ffi_v2")]
+impl From<ApiError> for FFIError {
+    fn from(err: ApiError) -> Self {
+        Self::ApiError(err)
+    }
+}
+
 impl From<Box<dyn Error>> for FFIError {
     fn from(err: Box<dyn Error>) -> Self {
         Self::OtherError(err)
@@ -233,6 +396,56 @@ pub enum TransportError {
         actual_content: String,
     },
     ReturnStr(String),
+    /// A method was called without an instance of its type being registered
+    NoInstance(&'static str),
+    /// A method was called from inside another call on the same instance
+    InstanceInUse(&'static str),
+    /// A handle argument didn't refer to a live value of the expected type
+    StaleHandle {
+        arg_name: String,
+        handle: String,
+        expected_type: &'static str,
+    },
+    /// A handle argument referred to a value that is already borrowed
+    HandleInUse {
+        arg_name: String,
+    },
+    /// A chunk was fetched from a transfer that doesn't exist, or that doesn't have that chunk left
+    BadChunk {
+        id: u64,
+        index: usize,
+    },
+    /// A returned value contained a NUL byte, which BYOND would have cut the string short at
+    ReturnNul {
+        position: usize,
+    },
+    /// A sandboxed path argument led outside of its sandbox, see [`sandbox`]
+    PathEscape {
+        arg_name: String,
+        path: String,
+    },
+    /// An argument went over one of the function's [`limits`]
+    ArgTooLarge {
+        arg_name: String,
+        limit: Limit,
+        actual: usize,
+        max: usize,
+    },
+    /// The function requires a newer BYOND version than the host's, see [`host`](crate::host)
+    #[cfg(feature = "ffi_v2")]
+    UnsupportedHost {
+        required: ByondVersion,
+        actual: Option<ByondVersion>,
+    },
+    /// A library to forward calls to couldn't be loaded
+    #[cfg(feature = "hot_reload")]
+    LibraryLoad {
+        path: String,
+        reason: String,
+    },
+    /// A forwarded function isn't exported by the loaded library
+    #[cfg(feature = "hot_reload")]
+    MissingSymbol(String),
 }
 
 impl Display for TransportError {
@@ -247,6 +460,7 @@ impl Display for TransportError {
             } => {
                 let range_str = match (expected_min, expected_max) {
                     (min, max) if min == max => format!("{min}"),
+                    (min, &usize::MAX) => format!("at least {min}"),
                     (min, max) => format!("{min}-{max}"),
                 };
                 write!(f, "Expected {range_str} args, got {got}")
@@ -267,6 +481,98 @@ impl Display for TransportError {
                 error_keys::FFI_TYPE_RETURN_STR,
                 failed_return,
             ),
+            Self::NoInstance(type_name) => write!(
+                f,
+                "{};No instance of \"{}\" has been registered",
+                error_keys::FFI_TYPE_NO_INSTANCE,
+                type_name,
+            ),
+            Self::InstanceInUse(type_name) => write!(
+                f,
+                "{};The instance of \"{}\" is already in use by this call",
+                error_keys::FFI_TYPE_INSTANCE_IN_USE,
+                type_name,
+            ),
+            Self::StaleHandle {
+                arg_name,
+                handle,
+                expected_type,
+            } => write!(
+                f,
+                "{};Argument \"{}\" is not a live handle to \"{}\" (content was \"{}\")",
+                error_keys::FFI_TYPE_STALE_HANDLE,
+                arg_name,
+                expected_type,
+                handle,
+            ),
+            Self::HandleInUse { arg_name } => write!(
+                f,
+                "{};Argument \"{}\" refers to a handle that is already in use",
+                error_keys::FFI_TYPE_HANDLE_IN_USE,
+                arg_name,
+            ),
+            Self::BadChunk { id, index } => write!(
+                f,
+                "{};Chunk {} of transfer {} doesn't exist or was already fetched",
+                error_keys::FFI_TYPE_BAD_CHUNK,
+                index,
+                id,
+            ),
+            Self::ReturnNul { position } => write!(
+                f,
+                "{};Return value contains a NUL byte at position {}",
+                error_keys::FFI_TYPE_RETURN_NUL,
+                position,
+            ),
+            Self::PathEscape { arg_name, path } => write!(
+                f,
+                "{};Argument \"{}\" leads outside of its sandbox (content was \"{}\")",
+                error_keys::FFI_TYPE_PATH_ESCAPE,
+                arg_name,
+                path,
+            ),
+            Self::ArgTooLarge {
+                arg_name,
+                limit,
+                actual,
+                max,
+            } => write!(
+                f,
+                "{};Argument \"{}\" {} ({} > {})",
+                error_keys::FFI_TYPE_ARG_TOO_LARGE,
+                arg_name,
+                limit,
+                actual,
+                max,
+            ),
+            #[cfg(feature = "ffi_v2")]
+            Self::UnsupportedHost { required, actual } => {
+                write!(
+                    f,
+                    "{};Requires BYOND {}, but the host ",
+                    error_keys::FFI_TYPE_UNSUPPORTED_HOST,
+                    required,
+                )?;
+                match actual {
+                    Some(actual) => write!(f, "is {actual}"),
+                    None => write!(f, "doesn't report its version"),
+                }
+            }
+            #[cfg(feature = "hot_reload")]
+            Self::LibraryLoad { path, reason } => write!(
+                f,
+                "{};Failed to load library \"{}\": {}",
+                error_keys::FFI_TYPE_LIBRARY_LOAD,
+                path,
+                reason,
+            ),
+            #[cfg(feature = "hot_reload")]
+            Self::MissingSymbol(symbol) => write!(
+                f,
+                "{};The loaded library doesn't export \"{}\"",
+                error_keys::FFI_TYPE_MISSING_SYMBOL,
+                symbol,
+            ),
         }
     }
 }
@@ -284,6 +590,17 @@ pub trait StrReturn {
     /// Converts the type into a `Vec<u8>` that can be returned to BYOND.
     /// If `None` is returned, an empty string will be returned to BYOND.
     fn to_return(self) -> Result<Option<Vec<u8>>, FFIError>;
+
+    /// Same as [`to_return`](Self::to_return), with text encoded in `encoding`. Only types that
+    /// return text encode it, while bytes like `Vec<u8>` are returned as they are, even if they
+    /// happen to be valid UTF-8.
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError>
+    where
+        Self: Sized,
+    {
+        let _ = encoding;
+        self.to_return()
+    }
 }
 
 impl StrReturn for () {
@@ -296,12 +613,20 @@ impl StrReturn for &'static str {
     fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
         Ok(Some(self.as_bytes().to_vec()))
     }
+
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError> {
+        Ok(Some(encoding.encode(self).into_owned()))
+    }
 }
 
 impl StrReturn for String {
     fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
         Ok(Some(self.into_bytes()))
     }
+
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError> {
+        Ok(Some(encoding.encode_string(self)))
+    }
 }
 
 impl StrReturn for Vec<u8> {
@@ -327,6 +652,23 @@ where
             Err(err) => Err(FFIError::OtherError(Box::new(err))),
         }
     }
+
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError> {
+        match self {
+            Ok(inner) => inner.to_encoded_return(encoding),
+            Err(err) => Err(FFIError::OtherError(Box::new(err))),
+        }
+    }
+}
+
+impl<T: StrReturn> StrReturn for Result<T, FFIError> {
+    fn to_return(self) -> Result<Option<Vec<u8>>, FFIError> {
+        self.and_then(StrReturn::to_return)
+    }
+
+    fn to_encoded_return(self, encoding: Encoding) -> Result<Option<Vec<u8>>, FFIError> {
+        self.and_then(|inner| inner.to_encoded_return(encoding))
+    }
 }
 
 impl StrReturn for TransportError {
@@ -447,3 +789,267 @@ impl<'a, T: StrArg<'a>> StrArg<'a> for Option<T> {
         }
     }
 }
+
+/// Represents a group of consecutive arguments parsed from BYOND into a single struct.
+///
+/// Derived with `#[derive(ByondArgs)]`, which maps each field to the argument at the same position,
+/// parsed with [`StrArg`]. `Option` fields must come last and are optional arguments, and a
+/// [`Rest`] field can be the very last one. The struct is then taken as the only parameter of a
+/// `#[byond_fn]`, marked with `#[byond_args]`. The marker is needed since `#[byond_fn]` only sees
+/// the name of the parameter's type, so it can't tell a `ByondArgs` struct from a type parsed with
+/// `StrArg`:
+/// ```
+/// use byond_fn::{byond_fn, ByondArgs};
+///
+/// #[derive(ByondArgs)]
+/// pub struct Spawn<'a> {
+///     path: &'a str,
+///     x: u16,
+///     y: u16,
+///     z: Option<u16>,
+/// }
+///
+/// #[byond_fn]
+/// pub fn spawn(#[byond_args] spawn: Spawn) -> String {
+///     format!("{} at {},{},{}", spawn.path, spawn.x, spawn.y, spawn.z.unwrap_or(1))
+/// }
+/// # fn main() {}
+/// ```
+///
+/// `call_ext("example_name.dll", "spawn")("/mob", "1", "2") // returns "/mob at 1,2,1"`
+///
+/// The number of arguments is checked against `MIN_ARGS` and `MAX_ARGS` before any of them are
+/// decoded, the same as for a function taking the fields as its parameters.
+pub trait ByondArgs<'a>: Sized {
+    /// The number of arguments that have to be passed
+    const MIN_ARGS: usize;
+    /// The number of arguments that can be passed, `usize::MAX` with a `Rest` field
+    const MAX_ARGS: usize;
+
+    /// Parses the struct from every argument passed to the function.
+    ///
+    /// # Errors
+    ///
+    /// If the wrong number of arguments were passed, this will return a
+    /// `TransportError::WrongArgCount`. Otherwise, returns the first error of parsing a field.
+    fn from_args(args: &[&'a str]) -> Result<Self, FFIError>;
+}
+
+/// A `ByondArgs` struct taken by a generated function, which checks the number of arguments
+/// against it before they're decoded.
+///
+/// The struct's type is inferred from what [`ArgsStruct::from_args`] is passed to, as the generated
+/// function can't always name it.
+///
+/// This is used internally, but is exposed in case you want the same functionality.
+pub struct ArgsStruct<A>(PhantomData<A>);
+
+impl<A> ArgsStruct<A> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+
+    /// Checks the number of arguments passed against the struct's `MIN_ARGS` and `MAX_ARGS`.
+    ///
+    /// # Errors
+    ///
+    /// If `argc` is out of range, this will return a `TransportError::WrongArgCount`.
+    pub fn check_count<'a>(&self, argc: usize) -> Result<(), TransportError>
+    where
+        A: ByondArgs<'a>,
+    {
+        if (A::MIN_ARGS..=A::MAX_ARGS).contains(&argc) {
+            Ok(())
+        } else {
+            Err(TransportError::WrongArgCount {
+                expected_min: A::MIN_ARGS,
+                expected_max: A::MAX_ARGS,
+                got: argc,
+            })
+        }
+    }
+
+    /// Parses the struct with [`ByondArgs::from_args`].
+    ///
+    /// # Errors
+    ///
+    /// Same as [`ByondArgs::from_args`].
+    pub fn from_args<'a>(&self, args: &[&'a str]) -> Result<A, FFIError>
+    where
+        A: ByondArgs<'a>,
+    {
+        A::from_args(args)
+    }
+}
+
+impl<A> Default for ArgsStruct<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects all remaining arguments passed from BYOND into a `Vec<T>`.
+///
+/// Must be the last parameter of a `#[byond_fn]`. Any number of arguments (including zero) can be
+/// passed in its place, and each one is parsed with [`StrArg::from_arg`]:
+/// ```
+/// use byond_fn::byond_fn;
+/// use byond_fn::str_ffi::Rest;
+///
+/// #[byond_fn]
+/// pub fn concat(sep: String, parts: Rest<String>) -> String {
+///     parts.join(&sep)
+/// }
+/// # fn main() {}
+/// ```
+///
+/// `call_ext("example_name.dll", "concat")(", ", "a", "b", "c") // returns "a, b, c"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rest<T>(pub Vec<T>);
+
+impl<T> Rest<T> {
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<'a, T: StrArg<'a>> Rest<T> {
+    /// Parses every remaining argument into a `T`.
+    ///
+    /// Each argument is named `<arg_name>[<index>]` in parse errors.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error produced by `T::from_arg`.
+    pub fn from_args(args: &[&'a str], arg_name: &str) -> Result<Self, FFIError> {
+        args.iter()
+            .enumerate()
+            .map(|(idx, arg)| T::from_arg(arg, &format!("{arg_name}[{idx}]")))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Rest)
+    }
+}
+
+impl<T> Deref for Rest<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Rest<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T> IntoIterator for Rest<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::byond_fn;
+
+    #[byond_fn]
+    fn sum(base: u32, numbers: Rest<u32>) -> u32 {
+        base + numbers.iter().sum::<u32>()
+    }
+
+    #[derive(crate::ByondArgs)]
+    struct Spawn<'a> {
+        path: &'a str,
+        x: u16,
+        y: u16,
+        z: Option<u16>,
+    }
+
+    #[byond_fn]
+    fn spawn(#[byond_args] spawn: Spawn) -> String {
+        format!(
+            "{} at {},{},{}",
+            spawn.path,
+            spawn.x,
+            spawn.y,
+            spawn.z.unwrap_or(1)
+        )
+    }
+
+    type Shim = unsafe extern "C" fn(c_int, *const *const c_char) -> *const c_char;
+
+    fn call(shim: Shim, args: &[&[u8]]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| CString::new(*arg).unwrap()).collect();
+        let argv: Vec<_> = args.iter().map(|arg| arg.as_ptr()).collect();
+        let argc = c_int::try_from(argv.len()).unwrap();
+        let returned = unsafe { shim(argc, argv.as_ptr()) };
+        unsafe { CStr::from_ptr(returned) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn call_sum(args: &[&str]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| arg.as_bytes()).collect();
+        call(__byond_fn_sum::sum, &args)
+    }
+
+    #[test]
+    fn rest_parses_every_arg() {
+        let rest = Rest::<u32>::from_args(&["1", "2", "3"], "numbers").unwrap();
+        assert_eq!(rest, Rest(vec![1, 2, 3]));
+
+        let rest = Rest::<u32>::from_args(&[], "numbers").unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn rest_errors_name_the_element() {
+        let err = Rest::<u32>::from_args(&["1", "two"], "numbers").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "@@ERR@@;FFI;ARG_PARSE;Failed to parse argument \"numbers[1]\" (content was \"two\")"
+        );
+    }
+
+    #[test]
+    fn rest_shims_check_the_min_arg_count() {
+        assert_eq!(call_sum(&["1"]), "1");
+        assert_eq!(call_sum(&["1", "2", "3"]), "6");
+        assert_eq!(call_sum(&[]), "@@ERR@@;FFI;Expected at least 1 args, got 0");
+        assert_eq!(
+            call_sum(&["1", "x"]),
+            "@@ERR@@;FFI;ARG_PARSE;Failed to parse argument \"numbers[0]\" (content was \"x\")"
+        );
+    }
+
+    #[test]
+    fn args_structs_take_every_arg() {
+        let spawn: Shim = __byond_fn_spawn::spawn;
+        assert_eq!(call(spawn, &[b"/mob", b"1", b"2"]), "/mob at 1,2,1");
+        assert_eq!(call(spawn, &[b"/mob", b"1", b"2", b"3"]), "/mob at 1,2,3");
+        assert_eq!(
+            call(spawn, &[b"/mob", b"1", b"y"]),
+            "@@ERR@@;FFI;ARG_PARSE;Failed to parse argument \"y\" (content was \"y\")"
+        );
+    }
+
+    #[test]
+    fn args_struct_shims_check_the_arg_count_first() {
+        let spawn: Shim = __byond_fn_spawn::spawn;
+        assert_eq!(
+            call(spawn, &[b"/mob", b"1"]),
+            "@@ERR@@;FFI;Expected 3-4 args, got 2"
+        );
+        // the count is checked before the invalid UTF-8 would be decoded
+        assert_eq!(
+            call(spawn, &[b"\xFF", b"1", b"2", b"3", b"4"]),
+            "@@ERR@@;FFI;Expected 3-4 args, got 5"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Path arguments confined to a directory.
+//!
+//! A `PathBuf` or `&Path` argument is taken as-is, so a path from BYOND can point anywhere, such
+//! as `../../etc/passwd`. A [`SandboxedPath<Root>`] argument is instead resolved against the
+//! directory given by `Root`, and fails with a `PATH_ESCAPE` error if it would point outside of it:
+//!
+//! - absolute paths, and on Windows paths with a drive or UNC prefix, are rejected
+//! - `.` and `..` are resolved, and rejected if they lead above the root
+//! - symlinks inside the root that lead outside of it are rejected
+//!
+//! The path doesn't have to exist, so it can name a file to create.
+//!
+//! ```
+//! use std::path::PathBuf;
+//! use byond_fn::byond_fn;
+//! use byond_fn::str_ffi::sandbox::{SandboxRoot, SandboxedPath};
+//!
+//! pub struct Uploads;
+//!
+//! impl SandboxRoot for Uploads {
+//!     fn root() -> PathBuf {
+//!         PathBuf::from("data/uploads")
+//!     }
+//! }
+//!
+//! #[byond_fn]
+//! pub fn read_upload(path: SandboxedPath<Uploads>) -> std::io::Result<String> {
+//!     std::fs::read_to_string(path)
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! The path is checked when the argument is parsed, so something that changes the files in the
+//! root in the meantime, such as a new symlink, isn't caught.
+
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::{Component, Path, PathBuf};
+
+use crate::str_ffi::{FFIError, StrArg, TransportError};
+
+/// The directory a [`SandboxedPath`] is confined to.
+pub trait SandboxRoot {
+    /// The root directory, which must exist. Relative paths are relative to the working directory
+    /// of the process.
+    fn root() -> PathBuf;
+}
+
+/// A path confined to the directory given by `Root`. See [`sandbox`](self).
+pub struct SandboxedPath<Root> {
+    path: PathBuf,
+    relative: PathBuf,
+    _root: PhantomData<fn() -> Root>,
+}
+
+impl<Root: SandboxRoot> SandboxedPath<Root> {
+    /// Resolves `path` against the root, where `arg_name` names the path in errors.
+    ///
+    /// # Errors
+    ///
+    /// If the path leads outside of the root, which is a `TransportError::PathEscape`, or the root
+    /// can't be resolved.
+    pub fn resolve(path: impl AsRef<Path>, arg_name: &str) -> Result<Self, FFIError> {
+        let path = path.as_ref();
+        let escape = || {
+            FFIError::TransportError(TransportError::PathEscape {
+                arg_name: arg_name.to_string(),
+                path: path.display().to_string(),
+            })
+        };
+
+        let root = Root::root();
+        let root = root.canonicalize().map_err(|err| {
+            FFIError::OtherError(Box::new(io::Error::new(
+                err.kind(),
+                format!(
+                    "sandbox root \"{}\" can't be resolved: {err}",
+                    root.display()
+                ),
+            )))
+        })?;
+
+        let relative = normalize(path).ok_or_else(escape)?;
+        if !within_root(&root, &relative) {
+            return Err(escape());
+        }
+        Ok(Self {
+            path: root.join(&relative),
+            relative,
+            _root: PhantomData,
+        })
+    }
+
+    /// The path relative to the root
+    pub fn relative(&self) -> &Path {
+        &self.relative
+    }
+
+    pub fn into_path_buf(self) -> PathBuf {
+        self.path
+    }
+}
+
+/// Resolves `.` and `..` in a relative path, or returns `None` if it isn't relative or would
+/// lead above where it starts.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(part) => normalized.push(part),
+        }
+    }
+    Some(normalized)
+}
+
+/// Whether every part of `relative` that exists resolves to somewhere inside `root`, which must
+/// be canonical. A symlink anywhere along the path could otherwise lead outside of it.
+fn within_root(root: &Path, relative: &Path) -> bool {
+    let mut current = root.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        if current.symlink_metadata().is_err() {
+            // nothing below a path that doesn't exist can be a symlink
+            return true;
+        }
+        // a symlink that can't be resolved can't be checked
+        match current.canonicalize() {
+            Ok(resolved) if resolved.starts_with(root) => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+impl<Root> Deref for SandboxedPath<Root> {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.path
+    }
+}
+
+impl<Root> AsRef<Path> for SandboxedPath<Root> {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl<Root> Debug for SandboxedPath<Root> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("SandboxedPath").field(&self.path).finish()
+    }
+}
+
+impl<'a, Root: SandboxRoot> StrArg<'a> for SandboxedPath<Root> {
+    fn from_arg(arg: &'a str, arg_name: &str) -> Result<Self, FFIError> {
+        Self::resolve(arg, arg_name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    struct TestRoot;
+
+    fn test_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("byond_fn_sandbox_{}", std::process::id()))
+    }
+
+    impl SandboxRoot for TestRoot {
+        fn root() -> PathBuf {
+            test_dir().join("root")
+        }
+    }
+
+    fn resolve(path: &str) -> Result<PathBuf, String> {
+        SandboxedPath::<TestRoot>::resolve(path, "path")
+            .map(|path| path.relative().to_path_buf())
+            .map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn paths_are_confined_to_the_root() {
+        let root = TestRoot::root();
+        fs::create_dir_all(root.join("logs")).unwrap();
+        fs::create_dir_all(test_dir().join("outside")).unwrap();
+
+        assert_eq!(
+            resolve("logs/today.txt").unwrap(),
+            Path::new("logs/today.txt")
+        );
+        assert_eq!(resolve("./logs/../new.txt").unwrap(), Path::new("new.txt"));
+        assert_eq!(resolve("").unwrap(), Path::new(""));
+        let resolved = SandboxedPath::<TestRoot>::resolve("logs", "path").unwrap();
+        assert_eq!(&*resolved, root.canonicalize().unwrap().join("logs"));
+
+        for escaping in ["../outside", "logs/../../outside", "/etc/passwd"] {
+            assert_eq!(
+                resolve(escaping).unwrap_err(),
+                format!(
+                    "@@ERR@@;FFI;PATH_ESCAPE;Argument \"path\" leads outside of its sandbox (content was \"{escaping}\")"
+                )
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+
+            symlink(test_dir().join("outside"), root.join("escape")).unwrap();
+            symlink(root.join("logs"), root.join("inside")).unwrap();
+            symlink(root.join("missing"), root.join("dangling")).unwrap();
+            assert!(resolve("escape/file.txt").is_err());
+            assert!(resolve("dangling").is_err());
+            assert_eq!(
+                resolve("inside/file.txt").unwrap(),
+                Path::new("inside/file.txt")
+            );
+        }
+
+        fs::remove_dir_all(test_dir()).unwrap();
+    }
+
+    #[test]
+    fn missing_roots_are_an_error() {
+        struct MissingRoot;
+
+        impl SandboxRoot for MissingRoot {
+            fn root() -> PathBuf {
+                PathBuf::from("/byond_fn/does/not/exist")
+            }
+        }
+
+        let err = SandboxedPath::<MissingRoot>::resolve("file", "path").unwrap_err();
+        assert!(matches!(err, FFIError::OtherError(_)));
+    }
+}
@@ -1,6 +1,9 @@
 #![warn(clippy::pedantic)]
+// byond functions are only ever called over FFI, and take their arguments by value
+#![allow(clippy::must_use_candidate, clippy::needless_pass_by_value)]
 
-use byond_fn_impl::byond_fn;
+use byond_fn::str_ffi::binary::{Base64, Hex};
+use byond_fn_impl::{byond_fn, byond_impl, byond_prefix, ByondArgs};
 
 #[byond_fn]
 pub fn example_byond_fn() {
@@ -12,6 +15,7 @@ pub fn add(arg1: u8, arg2: u8) -> u8 {
     arg1 + arg2
 }
 
+#[allow(clippy::similar_names, clippy::cast_sign_loss, clippy::get_first)]
 mod __byond_fn_add {
     #[no_mangle]
     pub unsafe extern "C" fn add(
@@ -66,5 +70,213 @@ pub fn example_optional_params(arg1: u8, arg2: Option<u8>) -> u8 {
     arg1 + arg2.unwrap_or(0)
 }
 
+#[byond_fn]
+pub fn example_rest_params(
+    sep: String,
+    first: Option<String>,
+    rest: byond_fn::str_ffi::Rest<String>,
+) -> String {
+    first.into_iter().chain(rest).collect::<Vec<_>>().join(&sep)
+}
+
+#[byond_fn(name = "example-renamed")]
+pub fn example_renamed() {}
+
+#[byond_fn(str, name = "example_explicit_transport")]
+pub fn example_explicit_transport() {}
+
+#[byond_fn(chunked)]
+pub fn example_chunked() -> String {
+    "chunk".repeat(100_000)
+}
+
+#[byond_fn(chunked = 1024)]
+pub fn example_chunked_sized(times: usize) -> Vec<u8> {
+    vec![b'a'; times]
+}
+
+#[byond_fn(encoding = "windows1252")]
+pub fn example_encoded(text: String, raw: &[u8]) -> String {
+    format!("{text}: {} bytes", raw.len())
+}
+
+#[byond_fn(nul = "escape")]
+pub fn example_nul_escaped() -> Vec<u8> {
+    b"a\0b".to_vec()
+}
+
+#[byond_fn]
+pub fn example_binary(data: Base64<Vec<u8>>, key: Hex<[u8; 4]>) -> Base64<Vec<u8>> {
+    Base64(
+        data.iter()
+            .zip(key.iter().cycle())
+            .map(|(a, b)| a ^ b)
+            .collect(),
+    )
+}
+
+#[derive(ByondArgs)]
+pub struct ExampleArgs<'a> {
+    name: &'a str,
+    count: usize,
+    scale: Option<u32>,
+}
+
+#[byond_fn]
+pub fn example_args(#[byond_args] args: ExampleArgs) -> String {
+    format!(
+        "{}x{}",
+        args.name.repeat(args.count),
+        args.scale.unwrap_or(1)
+    )
+}
+
+#[derive(ByondArgs)]
+pub struct ExampleTupleArgs(String, byond_fn::str_ffi::Rest<u8>);
+
+#[byond_fn]
+pub fn example_tuple_args(#[byond_args] ExampleTupleArgs(sep, values): ExampleTupleArgs) -> String {
+    values
+        .iter()
+        .map(u8::to_string)
+        .collect::<Vec<_>>()
+        .join(&sep)
+}
+
+#[byond_prefix("example_")]
+mod prefixed {
+    use byond_fn::byond_fn;
+
+    #[byond_fn]
+    pub fn prefixed() {}
+
+    pub struct PrefixedHandled(u32);
+
+    #[byond_fn_impl::byond_impl(handle)]
+    impl PrefixedHandled {
+        #[byond_fn]
+        pub fn get(&self) -> u32 {
+            self.0
+        }
+    }
+
+    mod nested {
+        use byond_fn::byond_fn;
+
+        #[byond_fn]
+        pub fn prefixed_nested() {}
+    }
+}
+
+#[derive(Default)]
+pub struct ExampleCounter {
+    count: u32,
+}
+
+#[byond_impl]
+impl ExampleCounter {
+    #[byond_fn]
+    pub fn init() {
+        byond_fn::instance::set_instance(ExampleCounter::default());
+    }
+
+    #[byond_fn]
+    pub fn increment(&mut self, by: Option<u32>) -> u32 {
+        self.count += by.unwrap_or(1);
+        self.count
+    }
+
+    #[byond_fn(name = "example_counter_get")]
+    pub fn get(&self) -> u32 {
+        self.count
+    }
+}
+
+pub struct ExampleHandled {
+    value: String,
+}
+
+#[byond_impl(handle)]
+impl ExampleHandled {
+    #[byond_fn]
+    pub fn new(value: String) -> byond_fn::handle::Handle<Self> {
+        byond_fn::handle::Handle::new(Self { value })
+    }
+
+    #[byond_fn]
+    pub fn value(&self) -> String {
+        self.value.clone()
+    }
+}
+
+#[byond_fn]
+pub fn example_handle_args(
+    target: &mut byond_fn::handle::Handle<ExampleHandled>,
+    source: &byond_fn::handle::Handle<ExampleHandled>,
+) {
+    target.value.push_str(&source.value);
+}
+
+#[cfg(feature = "ffi_v2")]
+mod v2 {
+    use std::collections::HashMap;
+
+    use byond_fn::ffi_v2::byond_serde::ByondSerde;
+    use byond_fn::ffi_v2::{ByondList, ByondValue};
+    use byond_fn::str_ffi::FFIError;
+    use byond_fn_impl::byond_fn;
+
+    #[byond_fn(v2)]
+    pub fn example_v2(value: ByondValue, scale: Option<f32>) -> Option<f32> {
+        value
+            .as_number()
+            .map(|number| number * scale.unwrap_or(1.0))
+    }
+
+    #[derive(serde::Deserialize, serde::Serialize)]
+    pub struct ExampleGas {
+        moles: f32,
+        temperature: Option<f32>,
+    }
+
+    #[byond_fn(v2)]
+    pub fn example_v2_serde(gas: ByondSerde<ExampleGas>) -> ByondSerde<Vec<ExampleGas>> {
+        ByondSerde(vec![gas.into_inner()])
+    }
+
+    #[byond_fn(v2, name = "example_v2_lists")]
+    pub fn example_lists(
+        list: ByondList,
+        names: Vec<String>,
+        counts: HashMap<String, u32>,
+    ) -> Result<Vec<u32>, FFIError> {
+        list.push(&ByondValue::number(1.0))?;
+        Ok(names
+            .iter()
+            .map(|name| counts.get(name).copied().unwrap_or_default())
+            .collect())
+    }
+
+    #[byond_fn(v2, fallback = "str", min_version = "515.1609", encoding = "latin1")]
+    pub fn example_v2_fallback(name: String, count: Option<u32>) -> String {
+        name.repeat(count.unwrap_or(1) as usize)
+    }
+
+    #[byond_fn(v2, errors = "runtime")]
+    pub fn example_v2_runtime(value: ByondValue) -> Result<f32, FFIError> {
+        value.get_var("health")
+    }
+}
+
+extern "C" {
+    // the prefix of the module applies to the `_free` export of a handle block too
+    fn example_PrefixedHandled_free(
+        argc: std::os::raw::c_int,
+        argv: *const *const std::os::raw::c_char,
+    ) -> *const std::os::raw::c_char;
+}
+
 #[test]
-fn compiles() {}
+fn compiles() {
+    let _ = example_PrefixedHandled_free as unsafe extern "C" fn(_, _) -> _;
+}
@@ -0,0 +1,149 @@
+#![cfg(feature = "json_transport")]
+#![warn(clippy::pedantic)]
+//! `ByondJson<T>` against `json_encode` output, kept in `tests/fixtures/byond_json`. See the
+//! README there for where the fixtures come from.
+
+use std::collections::HashMap;
+
+use byond_fn::str_ffi::byond_json::{from_str, ByondJson, DatumRef};
+use byond_fn::str_ffi::{StrArg, StrReturn};
+use serde::{Deserialize, Serialize};
+
+fn fixture(name: &str) -> String {
+    let path = format!(
+        "{}/tests/fixtures/byond_json/{name}.json",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    std::fs::read_to_string(path).unwrap()
+}
+
+fn parse<T: serde::de::DeserializeOwned>(name: &str) -> T {
+    let text = fixture(name);
+    match ByondJson::<T>::from_arg(&text, name) {
+        Ok(ByondJson(value)) => value,
+        Err(err) => panic!("{name}: {err}"),
+    }
+}
+
+/// Returns `value` the way a function would, and parses it back
+fn round_trip<T: Serialize + serde::de::DeserializeOwned>(value: T) -> T {
+    let returned = ByondJson(value).to_return().unwrap().unwrap();
+    from_str(std::str::from_utf8(&returned).unwrap()).unwrap()
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Player {
+    ckey: String,
+    roles: Vec<String>,
+    vars: HashMap<String, String>,
+    friends: HashMap<String, u32>,
+}
+
+#[test]
+fn empty_lists() {
+    let player: Player = parse("empty_lists");
+    assert_eq!(player.ckey, "urist");
+    assert!(player.roles.is_empty() && player.vars.is_empty() && player.friends.is_empty());
+    assert_eq!(round_trip(player).ckey, "urist");
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Numbers {
+    health: u8,
+    max_health: u32,
+    offset: i64,
+    ratio: f32,
+    mass: f64,
+}
+
+#[test]
+fn numbers() {
+    let numbers: Numbers = parse("numbers");
+    assert_eq!(
+        numbers,
+        Numbers {
+            health: 100,
+            max_health: 1_000_000,
+            offset: -1_000_000,
+            ratio: 0.75,
+            mass: 16_777_200.0,
+        }
+    );
+    assert_eq!(round_trip(numbers).max_health, 1_000_000);
+
+    let err = ByondJson::<Numbers>::from_arg(r#"{"health":1.5}"#, "numbers").unwrap_err();
+    assert!(err.to_string().contains("health"), "{err}");
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Refs {
+    owner: DatumRef,
+    contents: Vec<DatumRef>,
+    loc: Option<DatumRef>,
+}
+
+#[test]
+fn refs() {
+    let refs: Refs = parse("refs");
+    assert_eq!(refs.owner, DatumRef(0x200_0001));
+    assert_eq!(refs.contents, [DatumRef(0x200_0012), DatumRef(0x2100_0005)]);
+    assert_eq!(refs.loc, None);
+
+    let returned = ByondJson(&refs).to_return().unwrap().unwrap();
+    assert_eq!(returned, fixture("refs").as_bytes());
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct NonFinite {
+    max: f64,
+    min: f32,
+    undefined: f64,
+    values: Vec<f64>,
+}
+
+#[test]
+fn non_finite() {
+    let numbers: NonFinite = parse("non_finite");
+    assert!(numbers.max.is_infinite() && numbers.max > 0.0);
+    assert!(numbers.min.is_infinite() && numbers.min < 0.0);
+    assert!(numbers.undefined.is_nan());
+    assert_eq!(numbers.values, [0.0, f64::INFINITY, f64::INFINITY]);
+
+    // written back the way `json_encode` writes them, rather than as `null`. `capture.dm` checks
+    // that `json_decode` reads this fixture back
+    let returned = ByondJson(&numbers).to_return().unwrap().unwrap();
+    assert_eq!(returned, fixture("returned_non_finite").as_bytes());
+    let numbers = round_trip(numbers);
+    assert!(numbers.max.is_infinite() && numbers.max > 0.0);
+    assert!(numbers.min.is_infinite() && numbers.min < 0.0);
+    assert!(numbers.undefined.is_nan());
+
+    // strings are only read as numbers where a number is expected
+    let text: Vec<String> = from_str(r#"["1.#INF"]"#).unwrap();
+    assert_eq!(text, ["1.#INF"]);
+}
+
+#[test]
+#[ignore = "decoded_non_finite.json hasn't been captured yet, see the fixtures README"]
+fn non_finite_returns_decode_in_dm() {
+    // `json_decode` of `returned_non_finite.json`, encoded again
+    let numbers: NonFinite = parse("decoded_non_finite");
+    assert!(numbers.max.is_infinite() && numbers.max > 0.0);
+    assert!(numbers.min.is_infinite() && numbers.min < 0.0);
+    assert!(numbers.undefined.is_nan());
+    assert_eq!(numbers.values, [0.0, f64::INFINITY, f64::INFINITY]);
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Stats {
+    ckey: String,
+    stats: HashMap<String, u32>,
+}
+
+#[test]
+fn nested() {
+    let stats: Vec<Stats> = parse("nested");
+    assert!(stats[0].stats.is_empty());
+    assert_eq!(stats[1].stats["deaths"], 1000);
+    assert_eq!(round_trip(stats)[1].stats["kills"], 3);
+}
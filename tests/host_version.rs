@@ -0,0 +1,47 @@
+#![cfg(feature = "ffi_v2")]
+#![warn(clippy::pedantic)]
+//! The host version as DM reports it, for hosts without byondapi.
+//!
+//! This is its own test binary because the reported version is shared by the whole process.
+
+use std::ffi::{c_int, CStr, CString};
+use std::os::raw::c_char;
+use std::sync::Arc;
+
+use byond_fn::ffi_v2::api::{mock::MockApi, with_api};
+use byond_fn::host::{self, ByondVersion};
+
+extern "C" {
+    fn byond_fn_set_version(argc: c_int, argv: *const *const c_char) -> *const c_char;
+}
+
+fn report(major: &str, build: &str) -> String {
+    let args = [CString::new(major).unwrap(), CString::new(build).unwrap()];
+    let pointers = args.each_ref().map(|arg| arg.as_ptr());
+    let returned = unsafe { byond_fn_set_version(2, pointers.as_ptr()) };
+    unsafe { CStr::from_ptr(returned) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn dm_reports_the_version_without_byondapi() {
+    // this test binary is the "host", which has no byondapi
+    assert_eq!(host::version(), None);
+    assert_eq!(
+        report("514", "latest"),
+        "@@ERR@@;FFI;ARG_PARSE;Failed to parse argument \"build\" (content was \"latest\")"
+    );
+    assert_eq!(host::version(), None);
+
+    assert_eq!(report("514", "1589"), "");
+    assert_eq!(host::version(), Some(ByondVersion::new(514, 1589)));
+    assert!(!host::supports_v2());
+
+    // byondapi knows better, when there is one
+    let mock = Arc::new(MockApi::new());
+    mock.set_version(Some(ByondVersion::new(515, 1609)));
+    with_api(mock, || {
+        assert_eq!(host::version(), Some(ByondVersion::new(515, 1609)));
+    });
+}
@@ -0,0 +1,75 @@
+#![warn(clippy::pedantic)]
+//! The library-wide NUL policy against the policy of a single function.
+//!
+//! This is its own test binary because it changes the library-wide policy.
+
+use std::ffi::{c_int, CStr, CString};
+use std::os::raw::c_char;
+
+use byond_fn::str_ffi::binary::{set_nul_policy, NulPolicy};
+use byond_fn_impl::byond_fn;
+
+#[byond_fn]
+fn nul_default() -> Vec<u8> {
+    b"a\0b\\c".to_vec()
+}
+
+#[byond_fn(nul = "strip")]
+fn nul_strip() -> Vec<u8> {
+    b"a\0b\\c".to_vec()
+}
+
+#[byond_fn(nul = "error")]
+fn nul_error() -> Vec<u8> {
+    b"a\0b\\c".to_vec()
+}
+
+#[byond_fn(nul = "strip", chunked = 2)]
+fn nul_strip_chunked() -> Vec<u8> {
+    b"a\0b\\c".to_vec()
+}
+
+extern "C" {
+    fn byond_fn_fetch_chunk(argc: c_int, argv: *const *const c_char) -> *const c_char;
+}
+
+fn call(shim: unsafe extern "C" fn(c_int, *const *const c_char) -> *const c_char) -> String {
+    read(unsafe { shim(0, std::ptr::null()) })
+}
+
+fn read(returned: *const c_char) -> String {
+    unsafe { CStr::from_ptr(returned) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn fetch_chunks(header: &str) -> String {
+    let parts: Vec<_> = header.split(';').collect();
+    assert_eq!(parts[0], "@@CHUNKED@@");
+    let count: usize = parts[2].parse().unwrap();
+    (0..count)
+        .map(|index| {
+            let args = [
+                CString::new(parts[1]).unwrap(),
+                CString::new(index.to_string()).unwrap(),
+            ];
+            let pointers = args.each_ref().map(|arg| arg.as_ptr());
+            read(unsafe { byond_fn_fetch_chunk(2, pointers.as_ptr()) })
+        })
+        .collect()
+}
+
+#[test]
+fn function_policies_override_the_library_policy() {
+    set_nul_policy(NulPolicy::Escape);
+    // applied once, so the backslash is escaped once
+    assert_eq!(call(__byond_fn_nul_default::nul_default), "a\\0b\\\\c");
+    assert_eq!(call(__byond_fn_nul_strip::nul_strip), "ab\\c");
+    assert_eq!(
+        call(__byond_fn_nul_error::nul_error),
+        "@@ERR@@;FFI;RETURN_NUL;Return value contains a NUL byte at position 1"
+    );
+    // and not again for each chunk
+    let header = call(__byond_fn_nul_strip_chunked::nul_strip_chunked);
+    assert_eq!(fetch_chunks(&header), "ab\\c");
+}